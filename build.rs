@@ -0,0 +1,16 @@
+//! Generates the gRPC messages and service code (feature `grpc`) from `proto/gree.proto`, with the vendored `protoc`
+//! unless `PROTOC` is set.
+
+fn main() {
+    #[cfg(feature = "grpc")]
+    {
+        println!("cargo:rerun-if-changed=proto/gree.proto");
+        if std::env::var_os("PROTOC").is_none() {
+            let protoc = protoc_bin_vendored::protoc_bin_path().expect("vendored protoc");
+            std::env::set_var("PROTOC", protoc);
+        }
+        tonic_build::configure()
+            .compile_protos(&["proto/gree.proto"], &["proto"])
+            .expect("compiling proto/gree.proto");
+    }
+}
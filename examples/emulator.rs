@@ -0,0 +1,28 @@
+use gree::{*, emulator::*, sync_client::*, vars::*};
+use log::info;
+
+/// Runs the high-level client against a pair of emulated devices, one of which speaks the GCM protocol variant
+fn main() -> Result<()> {
+    env_logger::init();
+
+    let mut v2 = EmulatedDevice::new("000cc0000002", "bedroom");
+    v2.variant = ProtocolVariant::V2;
+    let emu = Emulator::start(EmulatorConfig::default(), vec![EmulatedDevice::new("000cc0000001", "livingroom"), v2])?;
+    info!("emulator listening on {}", emu.local_addr());
+
+    let mut cfg = GreeConfig::default();
+    cfg.client_config.bcast_addr = emu.local_addr().ip();
+    cfg.client_config.scan_timeout = std::time::Duration::from_millis(500);
+    let gree = Gree::new(cfg)?;
+
+    for mac in ["000cc0000001", "000cc0000002"] {
+        gree.set(mac, POW, OnOff::On)?;
+        gree.set(mac, MOD, Mod::Heat)?;
+        let pow: OnOff = gree.get(mac, POW)?;
+        let mode: Mod = gree.get(mac, MOD)?;
+        let variant = gree.with_device(mac, |dev| dev.variant)?;
+        println!("{mac} ({variant:?}): Pow={pow} Mod={mode}");
+    }
+
+    Ok(())
+}
@@ -10,6 +10,7 @@ use base64::{Engine as _, engine::general_purpose};
 
 use aes::Aes128;
 use aes::cipher::{BlockEncrypt, BlockDecrypt, KeyInit, generic_array::GenericArray };
+use aes_gcm::{Aes128Gcm, Nonce, Tag, AeadInPlace};
 use serde_json::Value;
 
 use crate::*;
@@ -20,12 +21,56 @@ pub mod vars {
 
 pub type VarName = &'static str;
 
+/// Implements typed conversions for an enumeration of variable values: `TryFrom<Value>`, `Into<Value>`, `FromStr` and `Display`.
+/// 
+/// `FromStr` accepts either the numeric value or the variant name (case-insensitive); `Display` yields the variant name.
+macro_rules! typed_var {
+    ($t:ident { $($v:ident),+ $(,)? }) => {
+        impl From<$t> for Value {
+            fn from(v: $t) -> Self { (v as i32).into() }
+        }
+
+        impl TryFrom<&Value> for $t {
+            type Error = Error;
+            fn try_from(value: &Value) -> Result<Self> {
+                match value.as_i64() {
+                    $(Some(n) if n == $t::$v as i64 => Ok($t::$v),)+
+                    _ => Err(Error::invalid_value(stringify!($t), &value.to_string()))
+                }
+            }
+        }
+
+        impl TryFrom<Value> for $t {
+            type Error = Error;
+            fn try_from(value: Value) -> Result<Self> { Self::try_from(&value) }
+        }
+
+        impl std::str::FromStr for $t {
+            type Err = Error;
+            fn from_str(s: &str) -> Result<Self> {
+                if let Ok(n) = s.parse::<i64>() { return Self::try_from(Value::from(n)) }
+                $(if s.eq_ignore_ascii_case(stringify!($v)) { return Ok($t::$v) })+
+                Err(Error::invalid_value(stringify!($t), s))
+            }
+        }
+
+        impl std::fmt::Display for $t {
+            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                match self { $($t::$v => f.write_str(stringify!($v)),)+ }
+            }
+        }
+    };
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[repr(i32)]
 pub enum OnOff {
     Off = 0,
     On = 1
 }
 
+typed_var!(OnOff { Off, On });
+
 
 /// `Pow`: power state of the device
 /// * 0: off
@@ -42,6 +87,7 @@ pub type Pow = OnOff;
 /// * 4: heat
 pub const MOD: VarName = "Mod";
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[repr(i32)]
 pub enum Mod {
     Auto = 0,
@@ -51,22 +97,117 @@ pub enum Mod {
     Heat = 4,
 }
 
-/// `SetTem` and `TemUn`: set temperature and temperature unit
-/// * if `TemUn` = 0, `SetTem` is the set temperature in Celsius
-/// * if `TemUn` = 1, `SetTem` is the set temperature is Fahrenheit
+typed_var!(Mod { Auto, Cool, Dry, Fan, Heat });
+
+/// Alias for [Mod]
+pub type Mode = Mod;
+
+/// `SetTem`: set temperature, always in whole degrees Celsius. See [Temperature] for setting Fahrenheit 
+/// temperatures, which also involves `TemRec`.
 pub const SET_TEM: VarName = "SetTem";
 
-/// `SetTem` and `TemUn`: set temperature and temperature unit
-/// * if `TemUn` = 0, `SetTem` is the set temperature in Celsius
-/// * if `TemUn` = 1, `SetTem` is the set temperature is Fahrenheit
+/// `TemUn`: temperature unit shown by the device
+/// * 0: Celsius
+/// * 1: Fahrenheit
 pub const TEM_UN: VarName = "TemUn";
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[repr(i32)]
 pub enum TemUn {
     Celsius = 0,
     Fahrenheit = 1,
 }
 
+typed_var!(TemUn { Celsius, Fahrenheit });
+
+/// Temperature in degrees Celsius, as written to `SetTem` when `TemUn` is [TemUn::Celsius]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Celsius(pub u8);
+
+impl From<Celsius> for Value {
+    fn from(t: Celsius) -> Self { t.0.into() }
+}
+
+impl From<Celsius> for Temperature {
+    fn from(t: Celsius) -> Self { Self::Celsius(t.0.into()) }
+}
+
+/// Set temperature in either unit, a single abstraction over `SetTem`, `TemRec` and `TemUn`
+/// 
+/// `SetTem` holds whole degrees Celsius only, which can not tell apart some adjacent Fahrenheit degrees (e.g. 62°F 
+/// and 63°F both round to 17°C). Fahrenheit temperatures are therefore written as the nearest Celsius degree plus 
+/// the `TemRec` bit, which is set if the exact temperature is not below `SetTem`. 
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+pub enum Temperature {
+    Celsius(f32),
+    Fahrenheit(f32),
+}
+
+impl Temperature {
+    /// Variables that jointly define the temperature, in the order used by [Temperature::to_values]
+    pub const VARS: [VarName; 3] = [SET_TEM, TEM_REC, TEM_UN];
+
+    /// The temperature in degrees Celsius
+    pub fn celsius(self) -> f32 {
+        match self {
+            Self::Celsius(t) => t,
+            Self::Fahrenheit(t) => (t - 32.0) * 5.0 / 9.0,
+        }
+    }
+
+    /// The temperature in degrees Fahrenheit
+    pub fn fahrenheit(self) -> f32 {
+        match self {
+            Self::Celsius(t) => t * 9.0 / 5.0 + 32.0,
+            Self::Fahrenheit(t) => t,
+        }
+    }
+
+    /// The unit the temperature is expressed in
+    pub fn unit(self) -> TemUn {
+        match self {
+            Self::Celsius(_) => TemUn::Celsius,
+            Self::Fahrenheit(_) => TemUn::Fahrenheit,
+        }
+    }
+
+    /// Values for `SetTem`, `TemRec` and `TemUn` representing this temperature
+    pub fn to_values(self) -> [Value; 3] {
+        let c = self.celsius();
+        // float to int casts saturate, so that out-of-range temperatures end up at the bounds of u8
+        let set_tem = c.round() as u8;
+        let tem_rec = match self {
+            Self::Celsius(_) => OnOff::Off,
+            Self::Fahrenheit(_) if c - c.round() > -0.001 => OnOff::On,
+            Self::Fahrenheit(_) => OnOff::Off,
+        };
+        [set_tem.into(), tem_rec.into(), self.unit().into()]
+    }
+
+    /// Decodes the temperature from the values of `SetTem`, `TemRec` and `TemUn`. Fahrenheit temperatures are 
+    /// rounded to whole degrees. A missing `TemRec` is taken as 0.
+    pub fn from_values(set_tem: &Value, tem_rec: &Value, tem_un: &Value) -> Option<Self> {
+        let set_tem = set_tem.as_u64().and_then(|t| u8::try_from(t).ok())? as f32;
+        match TemUn::try_from(tem_un).ok()? {
+            TemUn::Celsius => Some(Self::Celsius(set_tem)),
+            TemUn::Fahrenheit => {
+                // TemRec tells in which half of the Celsius degree the temperature lies; take the middle of that half
+                let c = if tem_rec.as_i64().unwrap_or(0) != 0 { set_tem + 0.25 } else { set_tem - 0.25 };
+                Some(Self::Fahrenheit((c * 9.0 / 5.0 + 32.0).round()))
+            }
+        }
+    }
+}
+
+impl std::fmt::Display for Temperature {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Celsius(t) => write!(f, "{t}°C"),
+            Self::Fahrenheit(t) => write!(f, "{t}°F"),
+        }
+    }
+}
+
 /// `WdSpd`: fan speed
 /// * 0: auto
 /// * 1: low
@@ -76,6 +217,7 @@ pub enum TemUn {
 /// * 5: high
 pub const WD_SPD: VarName = "WdSpd";
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[repr(i32)]
 pub enum WdSpd {
     Auto = 0,
@@ -86,6 +228,8 @@ pub enum WdSpd {
     High = 5,
 }
 
+typed_var!(WdSpd { Auto, Low, MediumLow, Medium, MediumHigh, High });
+
 /// `Air`: controls the state of the fresh air valve (not available on all units)
 /// * 0: off
 /// * 1: on
@@ -127,6 +271,7 @@ pub type Lig = OnOff;
 /// Full swing, like for SwUpDn is not supported
 pub const SWING_LF_RIG: VarName = "SwingLfRig";
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[repr(i32)]
 pub enum SwingLfRig {
     Default = 0,
@@ -138,6 +283,8 @@ pub enum SwingLfRig {
     Pos4 = 6
 }
 
+typed_var!(SwingLfRig { Default, Full, Pos0, Pos1, Pos2, Pos3, Pos4 });
+
 
 /// `SwUpDn`: controls the swing mode of the vertical air blades
 /// * 0: default
@@ -154,6 +301,7 @@ pub enum SwingLfRig {
 /// * 11: swing in the upmost region (1/5)
 pub const SW_UP_DN: VarName = "SwUpDn";
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[repr(i32)]
 pub enum SwUpDn {
     Default = 0,
@@ -170,6 +318,8 @@ pub enum SwUpDn {
     Swing1 = 11
 }
 
+typed_var!(SwUpDn { Default, Full, Fixed1, Fixed2, Fixed3, Fixed4, Fixed5, Swing5, Swing4, Swing3, Swing2, Swing1 });
+
 /// `Quiet`: controls the Quiet mode which slows down the fan to its most quiet speed. Not available in Dry and Fan mode.
 /// * 0: off
 /// * 1: on
@@ -191,7 +341,7 @@ pub const ST_HT: VarName = "StHt";
 /// `HeatCoolType`: unknown
 pub const HEAT_COOL_TYPE: VarName = "HeatCoolType"; 
 
-/// `TemRec`: this bit is used to distinguish between two Fahrenheit values (see Setting the temperature using Fahrenheit section below)
+/// `TemRec`: distinguishes between two Fahrenheit temperatures rounding to the same `SetTem`, see [Temperature]
 pub const TEM_REC: VarName = "TemRec"; 
 
 /// `SvSt`: energy saving mode
@@ -207,13 +357,86 @@ pub type SvSt = OnOff;
 /// For example if you get 65 from the device it means the current temperature is 65 - 40 = 25.
 pub const TEM_SEN: VarName = "TemSen";
 
+/// The room temperature in degrees Celsius from the value of `TemSen`, `None` if the unit does not report it (0)
+pub fn room_temperature(tem_sen: &Value) -> Option<i64> {
+    tem_sen.as_i64().filter(|t| *t != 0).map(|t| t - 40)
+}
+
+/// `TmrOn`: built-in on-timer (only on units with a hardware timer), minutes until the unit turns on
+/// * 0: disabled
+/// * 1-1440: minutes
+/// 
+/// Not part of the documented protocol; units without the timer report an empty string.
+pub const TMR_ON: VarName = "TmrOn";
+
+/// `TmrOff`: built-in off-timer (only on units with a hardware timer), minutes until the unit turns off
+/// * 0: disabled
+/// * 1-1440: minutes
+/// 
+/// Not part of the documented protocol; units without the timer report an empty string.
+pub const TMR_OFF: VarName = "TmrOff";
+
+/// `AntiDirectBlow`: keeps the air blades from blowing directly at people (newer firmwares only)
+/// * 0: off
+/// * 1: on
+pub const ANTI_DIRECT_BLOW: VarName = "AntiDirectBlow";
+
+pub type AntiDirectBlow = OnOff;
+
+/// `LigSen`: light sensor, dimming the display in the dark (newer firmwares only)
+/// * 0: off
+/// * 1: on
+pub const LIG_SEN: VarName = "LigSen";
+
+pub type LigSen = OnOff;
+
+/// `SlpMod`: sleep curve, i.e. how the temperature changes while sleep mode (`SwhSlp`) is on (newer firmwares only)
+/// * 0: standard
+/// * 1-3: curve 1-3
+pub const SLP_MOD: VarName = "SlpMod";
+
+/// Highest value of `SlpMod`
+pub const MAX_SLP_MOD: u8 = 3;
+
+/// `Buzzer_ON_OFF`: silences the beep acknowledging the commands (newer firmwares only)
+/// * 0: beep
+/// * 1: silent
+pub const BUZZER: VarName = "Buzzer_ON_OFF";
+
+pub type Buzzer = OnOff;
+
+/// `SelfClean`: self-cleaning of the evaporator, run while the unit is off (newer firmwares only)
+/// * 0: off
+/// * 1: on
+pub const SELF_CLEAN: VarName = "SelfClean";
+
+pub type SelfClean = OnOff;
+
+/// Longest delay the built-in timers accept, in minutes
+pub const MAX_TIMER_MINUTES: u16 = 24 * 60;
+
+/// Converts a delay into the value of the timer variable (`TmrOn` or `TmrOff`), rounding it up to whole minutes
+pub fn timer_minutes(name: VarName, after: std::time::Duration) -> Result<u16> {
+    let minutes = after.as_nanos().div_ceil(60_000_000_000);
+    u16::try_from(minutes).ok().filter(|m| *m <= MAX_TIMER_MINUTES)
+        .ok_or_else(|| Error::invalid_value(name, &format!("{after:?} exceeds {MAX_TIMER_MINUTES} minutes")))
+}
+
 /// `time`: read or set device time. Requires custom pack and must be used separately from other vars.
 /// 
 /// Format: "2018-05-11 19:42:01"
 pub const TIME: VarName = "time";
 
+/// Format of the `time` variable, in `chrono` syntax
+pub const TIME_FORMAT: &str = "%Y-%m-%d %H:%M:%S";
+
+/// Current local time of the host, formatted as the value of the `time` variable
+pub fn local_time() -> String {
+    chrono::Local::now().format(TIME_FORMAT).to_string()
+}
+
 //------------------------------------------------------------------------------------------------------------------------------
-pub const ALL: [VarName; 20] = [
+pub const ALL: [VarName; 27] = [
     POW, 
     MOD, 
     SET_TEM, 
@@ -233,6 +456,13 @@ pub const ALL: [VarName; 20] = [
     TEM_REC,
     SV_ST,
     TEM_SEN,
+    TMR_ON,
+    TMR_OFF,
+    ANTI_DIRECT_BLOW,
+    LIG_SEN,
+    SLP_MOD,
+    BUZZER,
+    SELF_CLEAN,
     TIME,
 ];
 
@@ -258,14 +488,67 @@ pub fn name_of(n: &str) -> Option<VarName> {
         TEM_REC => Some(TEM_REC),
         SV_ST => Some(SV_ST),
         TEM_SEN => Some(TEM_SEN),
+        TMR_ON => Some(TMR_ON),
+        TMR_OFF => Some(TMR_OFF),
+        ANTI_DIRECT_BLOW => Some(ANTI_DIRECT_BLOW),
+        LIG_SEN => Some(LIG_SEN),
+        SLP_MOD => Some(SLP_MOD),
+        BUZZER => Some(BUZZER),
+        SELF_CLEAN => Some(SELF_CLEAN),
         TIME => Some(TIME),
-        _ => None,
+        n => registry().get_key_value(n).map(|(n, _)| *n),
     }
 }
 
+/// Parser and validator of the values of a registered variable, see [register_with]
+pub type VarValidator = std::sync::Arc<dyn Fn(&str) -> Result<Value> + Send + Sync>;
+
+type Registry = std::collections::HashMap<VarName, Option<VarValidator>>;
+
+static REGISTRY: std::sync::LazyLock<std::sync::RwLock<Registry>> = std::sync::LazyLock::new(Default::default);
+
+fn registry() -> std::sync::RwLockReadGuard<'static, Registry> {
+    REGISTRY.read().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Registers a vendor-specific variable, making it known to [name_of], and so to the configuration, the services and 
+/// [parse_value], which parses its values as numbers if numeric and as strings otherwise. Returns the interned name.
+/// 
+/// Registering a name again returns the name interned before; built-in names can not be registered. The registry is 
+/// process-wide.
+pub fn register(name: &str) -> Result<VarName> {
+    register_var(name, None)
+}
+
+/// Registers a vendor-specific variable (see [register]) along with the parser and validator of its values, replacing 
+/// the one registered before, if any
+pub fn register_with(name: &str, validator: impl Fn(&str) -> Result<Value> + Send + Sync + 'static) -> Result<VarName> {
+    register_var(name, Some(std::sync::Arc::new(validator)))
+}
+
+fn register_var(name: &str, validator: Option<VarValidator>) -> Result<VarName> {
+    if ALL.contains(&name) { return Err(Error::InvalidVar(format!("{name} (built in, can not be registered)"))) }
+    let mut registry = REGISTRY.write().unwrap_or_else(|e| e.into_inner());
+    let name = match registry.get_key_value(name) {
+        Some((n, _)) => *n,
+        // names are interned for the lifetime of the process, as VarName is `&'static str`
+        None => &*Box::leak(name.to_owned().into_boxed_str()),
+    };
+    let entry = registry.entry(name).or_default();
+    if validator.is_some() { *entry = validator }
+    Ok(name)
+}
+
+/// Names of the variables registered (see [register])
+pub fn registered() -> Vec<VarName> {
+    registry().keys().copied().collect()
+}
+
 use crate::{Result, Value, Error};
 
 /// Parses value for the specified variable
+/// 
+/// Enumerated variables (e.g. `Mod`) accept either a number or a variant name of the corresponding enumeration (e.g. `cool`).
 pub fn parse_value(name: VarName, value: impl AsRef<str>) -> Result<Value> {
     Ok(match name {
         //Arbitrary string so far (TODO: enforce format)
@@ -273,16 +556,44 @@ pub fn parse_value(name: VarName, value: impl AsRef<str>) -> Result<Value> {
             Value::String(value.as_ref().to_owned())
         }
         //{0,1}
-        POW | TEM_UN | AIR | BLO | HEALTH | SWH_SLP | LIG | QUIET | TUR | SV_ST | ST_HT => {
+        POW | AIR | BLO | HEALTH | SWH_SLP | LIG | QUIET | TUR | SV_ST | ST_HT | ANTI_DIRECT_BLOW | LIG_SEN | BUZZER | SELF_CLEAN => {
+            value.as_ref().parse::<OnOff>().map_err(|_| Error::invalid_value(name, value.as_ref()))?.into()
+        }
+        //enumerations
+        TEM_UN => value.as_ref().parse::<TemUn>()?.into(),
+        MOD => value.as_ref().parse::<Mod>()?.into(),
+        WD_SPD => value.as_ref().parse::<WdSpd>()?.into(),
+        SWING_LF_RIG => value.as_ref().parse::<SwingLfRig>()?.into(),
+        SW_UP_DN => value.as_ref().parse::<SwUpDn>()?.into(),
+        //minutes
+        TMR_ON | TMR_OFF => {
+            let w: u16 = value.as_ref().parse()?;
+            if w > MAX_TIMER_MINUTES { return Err(Error::invalid_value(name, value.as_ref())) }
+            Value::Number(w.into())
+        }
+        //0..=MAX_SLP_MOD
+        SLP_MOD => {
             let w: u8 = value.as_ref().parse()?;
-            if w > 1 { return Err(Error::invalid_value(name, value.as_ref())) }
+            if w > MAX_SLP_MOD { return Err(Error::invalid_value(name, value.as_ref())) }
             Value::Number(w.into())
         }
         //u8
-        MOD | SET_TEM | TEM_REC | WD_SPD | SWING_LF_RIG | SW_UP_DN  => {
+        SET_TEM | TEM_REC => {
             let w: u8 = value.as_ref().parse()?;
             Value::Number(w.into())
         }
+        //registered
+        n if registry().contains_key(n) => {
+            // not holding the registry lock while validating
+            let validator = registry().get(n).cloned().flatten();
+            match validator {
+                Some(validator) => validator(value.as_ref())?,
+                None => match value.as_ref().parse::<i64>() {
+                    Ok(w) => Value::Number(w.into()),
+                    Err(_) => value.as_ref().into(),
+                }
+            }
+        }
         //the rest is assumed string
         _ => {
             //let w: u8 = value.as_ref().parse()?;
@@ -292,36 +603,118 @@ pub fn parse_value(name: VarName, value: impl AsRef<str>) -> Result<Value> {
     })
 }
 
+/// Fan intensity, a single abstraction over the interacting `WdSpd`, `Tur` and `Quiet` variables
+/// * `Quiet`: `Quiet` = 1, the fan runs at its most quiet speed. Not available in Dry and Fan mode.
+/// * `Auto`: `WdSpd` = 0
+/// * `Low`, `MediumLow`, `Medium`, `MediumHigh`, `High`: `WdSpd` = 1..5
+/// * `Turbo`: `Tur` = 1, the fan runs at its maximum speed. Only available in Dry and Cool mode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde_derive::Serialize, serde_derive::Deserialize)]
+pub enum FanLevel {
+    Quiet,
+    Auto,
+    Low,
+    MediumLow,
+    Medium,
+    MediumHigh,
+    High,
+    Turbo,
+}
+
+impl FanLevel {
+    /// Variables that jointly define the fan level, in the order used by [FanLevel::to_values]
+    pub const VARS: [VarName; 3] = [WD_SPD, TUR, QUIET];
+
+    /// Checks that the level is available in the mode of operation (`Mod`) given. Unknown modes are not checked.
+    pub fn check_mode(self, mode: &Value) -> Result<()> {
+        let mode = match mode.as_i64() { Some(m) => m, None => return Ok(()) };
+        match self {
+            Self::Quiet if mode == Mod::Dry as i64 || mode == Mod::Fan as i64 => 
+                Err(Error::invalid_value(QUIET, &format!("not available in mode {mode}"))),
+            Self::Turbo if mode != Mod::Cool as i64 && mode != Mod::Dry as i64 => 
+                Err(Error::invalid_value(TUR, &format!("not available in mode {mode}"))),
+            _ => Ok(())
+        }
+    }
+
+    /// Values for `WdSpd`, `Tur` and `Quiet` representing this level
+    pub fn to_values(self) -> [Value; 3] {
+        let (wd_spd, tur, quiet) = match self {
+            Self::Quiet => (WdSpd::Low, OnOff::Off, OnOff::On),
+            Self::Auto => (WdSpd::Auto, OnOff::Off, OnOff::Off),
+            Self::Low => (WdSpd::Low, OnOff::Off, OnOff::Off),
+            Self::MediumLow => (WdSpd::MediumLow, OnOff::Off, OnOff::Off),
+            Self::Medium => (WdSpd::Medium, OnOff::Off, OnOff::Off),
+            Self::MediumHigh => (WdSpd::MediumHigh, OnOff::Off, OnOff::Off),
+            Self::High => (WdSpd::High, OnOff::Off, OnOff::Off),
+            Self::Turbo => (WdSpd::High, OnOff::On, OnOff::Off),
+        };
+        [wd_spd.into(), tur.into(), quiet.into()]
+    }
+
+    /// Decodes the level from the values of `WdSpd`, `Tur` and `Quiet`. `Tur` takes precedence over `Quiet`.
+    pub fn from_values(wd_spd: &Value, tur: &Value, quiet: &Value) -> Option<Self> {
+        if tur.as_i64().unwrap_or(0) != 0 { return Some(Self::Turbo) }
+        if quiet.as_i64().unwrap_or(0) != 0 { return Some(Self::Quiet) }
+        match wd_spd.as_i64()? {
+            0 => Some(Self::Auto),
+            1 => Some(Self::Low),
+            2 => Some(Self::MediumLow),
+            3 => Some(Self::Medium),
+            4 => Some(Self::MediumHigh),
+            5 => Some(Self::High),
+            _ => None
+        }
+    }
+}
+
 }
 
+/// Scan message, broadcast unencrypted
 pub const SCAN_MESSAGE: &[u8] = br#"{
   "t": "scan"
 }"#;
 //const SM2: Value = json!({"t":"scan"});
 
 
-#[derive(Deserialize, Debug)]
+/// Message received from a device; `pack` is encrypted, except in the announcements
+#[derive(Deserialize, Debug, Default)]
 pub struct GenericMessage {
-    #[serde(default)]
+    #[serde(default, deserialize_with = "lenient::string")]
     pub cid: String,
     
-    #[serde(default)]
+    #[serde(default, deserialize_with = "lenient::int")]
     pub i: Int,
     
-    #[serde(default)]
+    #[serde(default, deserialize_with = "lenient::string")]
     pub pack: String,
 
-    #[serde(default)]
+    #[serde(default, deserialize_with = "lenient::string")]
     pub t: String,
     
-    #[serde(default)]
+    #[serde(default, deserialize_with = "lenient::string")]
     pub tcid: String,
 
-    #[serde(default)]
+    #[serde(default, deserialize_with = "lenient::int")]
     pub uid: Int,
+
+    /// GCM authentication tag ([ProtocolVariant::V2] only)
+    #[serde(default, deserialize_with = "lenient::string")]
+    pub tag: String,
+
+    /// Whether the pack is parsed leniently, as the message was (see [GreeClientConfig::lenient])
+    #[serde(skip)]
+    pub(crate) lenient: bool,
 }
 
 
+impl GenericMessage {
+    /// Protocol variant of the message, detected by the presence of the `tag`
+    pub fn variant(&self) -> ProtocolVariant {
+        if self.tag.is_empty() { ProtocolVariant::V1 } else { ProtocolVariant::V2 }
+    }
+}
+
+/// Message sent to a device, carrying the encrypted `pack`
 #[derive(Serialize)]
 pub struct GenericOutMessage<'t> {
     pub cid: &'t str,
@@ -330,52 +723,102 @@ pub struct GenericOutMessage<'t> {
     pub t:  &'t str,
     pub tcid:  &'t str,
     pub uid: Int,
+    /// GCM authentication tag ([ProtocolVariant::V2] only)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tag: Option<String>,
+}
+
+/// True if `gm`, received from `addr`, answers a request to `mac` sent to `ip`. Responses are correlated by the device 
+/// MAC (`cid`), so that multi-unit bridges and NAT work; the source address is only compared if the MAC is not reported.
+pub fn is_response(ip: IpAddr, mac: &str, addr: IpAddr, gm: &GenericMessage) -> bool {
+    if gm.cid.is_empty() || mac.is_empty() {
+        addr == ip
+    } else {
+        gm.cid == mac
+    }
 }
 
-#[derive(Deserialize, Debug)]
+/// Protocol variant, defining the encryption of packs
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum ProtocolVariant {
+    /// AES-ECB; the original protocol
+    #[default]
+    V1,
+    /// AES-GCM with a `tag` field in the message; newer firmwares (1.21+)
+    V2,
+}
+
+impl ProtocolVariant {
+    /// Generic key, used for scans and binds
+    pub fn generic_key(self) -> &'static str {
+        match self {
+            Self::V1 => GENERIC_KEY,
+            Self::V2 => GENERIC_KEY_GCM,
+        }
+    }
+
+    /// The other variant, to fall back to during bind negotiation
+    pub fn other(self) -> Self {
+        match self {
+            Self::V1 => Self::V2,
+            Self::V2 => Self::V1,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
 pub struct ScanResponsePack {
-    #[serde(default)]
+    #[serde(default, deserialize_with = "lenient::string")]
     pub t: String,
 
-    #[serde(default)]
+    #[serde(default, deserialize_with = "lenient::string")]
     pub cid: String,
 
-    #[serde(default)]
+    #[serde(default, deserialize_with = "lenient::string")]
     pub bc: String,
 
-    #[serde(default)]
+    #[serde(default, deserialize_with = "lenient::string")]
     pub brand: String,
 
-    #[serde(default)]
+    #[serde(default, deserialize_with = "lenient::string")]
     pub catalog:String,
 
-    #[serde(default)]
+    #[serde(default, deserialize_with = "lenient::string")]
     pub mac: String, // !!!
 
-    #[serde(default)]
+    #[serde(default, deserialize_with = "lenient::string")]
     pub mid: String,
 
-    #[serde(default)]
+    #[serde(default, deserialize_with = "lenient::string")]
     pub model: String,
 
-    #[serde(default)]
+    #[serde(default, deserialize_with = "lenient::string")]
     pub name: String,
 
-    #[serde(default)]
+    #[serde(default, deserialize_with = "lenient::int")]
     pub lock: i32,
 
-    #[serde(default)]
+    #[serde(default, deserialize_with = "lenient::string")]
     pub series: String,
     
-    #[serde(default)]
+    #[serde(default, deserialize_with = "lenient::string")]
     pub vender: String,
     
-    #[serde(default)]
-    pub ver: String
+    #[serde(default, deserialize_with = "lenient::string")]
+    pub ver: String,
+
+    /// Number of sub-units of a multi-split bridge, listed with a `subList` pack; 0 for plain devices
+    #[serde(default, rename = "subCnt", deserialize_with = "lenient::int")]
+    pub sub_cnt: i32,
+
+    /// Fields not known to this crate
+    #[serde(flatten)]
+    pub extra: serde_json::Map<String, Value>,
 }
 
 
 
+/// Scan message, see [SCAN_MESSAGE]
 pub fn scan_request() -> &'static [u8] { SCAN_MESSAGE }
 
 //------------------------------------------------------------------------------------------------------------------------------
@@ -385,8 +828,11 @@ pub fn scan_request() -> &'static [u8] { SCAN_MESSAGE }
 "uid": 0
 } */
 
+/// Bind request pack, encrypted with the generic key (see [ProtocolVariant::generic_key])
 #[derive(Serialize)]
 pub struct BindRequestPack<'t> {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    cid: Option<&'t str>,
     mac: &'t str,
     t: &'t str,
     uid: Int,
@@ -399,15 +845,48 @@ pub struct BindRequestPack<'t> {
   "r": 200
 } */
 
-#[derive(Debug, Deserialize)]
+/// Bind response pack, carrying the device key
+#[derive(Debug, Serialize, Deserialize)]
 pub struct BindResponsePack {
+    #[serde(deserialize_with = "lenient::string")]
     pub t: String,
+    #[serde(deserialize_with = "lenient::string")]
     pub mac: String,
+    #[serde(deserialize_with = "lenient::string")]
     pub key: String,
-    pub r: Int
+    #[serde(deserialize_with = "lenient::int")]
+    pub r: Int,
+    /// Fields not known to this crate
+    #[serde(flatten)]
+    pub extra: serde_json::Map<String, Value>,
 }
 
-pub fn bind_request<'t>(mac: &'t str, key: &str) -> Result<GenericOutMessage<'t>> {
+/// Result code reported by the devices on success
+pub const R_OK: i32 = 200;
+
+/// Result codes by which the devices reject the key of a request (unauthorized, forbidden), see 
+/// `Error::is_key_failure`
+pub const R_KEY_REJECTED: [i32; 2] = [401, 403];
+
+/// Response packs carrying the device's result code (`r`)
+pub trait ResultCode {
+    fn result_code(&self) -> i32;
+}
+
+impl ResultCode for BindResponsePack { fn result_code(&self) -> i32 { self.r } }
+impl ResultCode for StatusResponsePack { fn result_code(&self) -> i32 { self.r } }
+impl ResultCode for CommandResponsePack { fn result_code(&self) -> i32 { self.r } }
+
+/// Fails with `Error::DeviceError` unless the pack's result code is [R_OK]
+pub fn check_result<T: ResultCode>(mac: &str, pack: T) -> Result<T> {
+    match pack.result_code() {
+        R_OK => Ok(pack),
+        code => Err(Error::DeviceError { mac: mac.into(), code }),
+    }
+}
+
+/// Bind request carrying `uid` in the pack and in the message, see [GreeClientConfig::uid]
+pub fn bind_request<'t>(mac: &'t str, key: &str, variant: ProtocolVariant, uid: Int) -> Result<GenericOutMessage<'t>> {
 
     /* {
     "mac": "<MAC address>",
@@ -415,12 +894,13 @@ pub fn bind_request<'t>(mac: &'t str, key: &str) -> Result<GenericOutMessage<'t>
     "uid": 0
     }*/
     let pack = serde_json::to_vec(&BindRequestPack {
+        cid: if variant == ProtocolVariant::V2 { Some(mac) } else { None },
         mac,
         t: "bind",
-        uid: 0
+        uid,
     })?;
 
-    let pack = encode_request(pack, key.as_bytes());
+    let (pack, tag) = encode_pack(pack, key.as_bytes(), variant)?;
 
     /*
     {
@@ -439,13 +919,180 @@ pub fn bind_request<'t>(mac: &'t str, key: &str) -> Result<GenericOutMessage<'t>
         pack,
         t: "pack",
         tcid: mac,
-        uid: 0
+        uid,
+        tag,
     })
 }
 
 
 
 
+//------------------------------------------------------------------------------------------------------------------------------
+/* {
+"mac": "<MAC address>",
+"t": "dev"
+} */
+
+/// Device details request pack, see [DeviceInfoPack]
+#[derive(Serialize)]
+pub struct DeviceInfoRequestPack<'t> {
+    mac: &'t str,
+    t: &'t str,
+}
+
+/* {
+  "t": "dev",
+  "cid": "<MAC address>",
+  "mac": "<MAC address>",
+  "mid": "10001",
+  "model": "gree",
+  "name": "<name>",
+  "hid": "362001000762+U-CS532AE(LT)V3.31.bin",
+  "ver": "V1.1.13",
+  ...
+} */
+/// Device details answered to a keyed `dev` pack. Unlike the scan response, it carries the hardware/firmware id 
+/// (`hid`); fields not known to this crate are kept in `extra`.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct DeviceInfoPack {
+    #[serde(default, deserialize_with = "lenient::string")]
+    pub t: String,
+    #[serde(default, deserialize_with = "lenient::string")]
+    pub mac: String,
+    #[serde(default, deserialize_with = "lenient::string")]
+    pub mid: String,
+    #[serde(default, deserialize_with = "lenient::string")]
+    pub model: String,
+    #[serde(default, deserialize_with = "lenient::string")]
+    pub name: String,
+    #[serde(default, deserialize_with = "lenient::string")]
+    pub brand: String,
+    #[serde(default, deserialize_with = "lenient::string")]
+    pub series: String,
+    #[serde(default, deserialize_with = "lenient::string")]
+    pub vender: String,
+    /// Firmware version
+    #[serde(default, deserialize_with = "lenient::string")]
+    pub ver: String,
+    /// Hardware id, naming the firmware image of the main board
+    #[serde(default, deserialize_with = "lenient::string")]
+    pub hid: String,
+    #[serde(flatten)]
+    pub extra: serde_json::Map<String, Value>,
+}
+
+/// Requests the details of the device `mac`, see [DeviceInfoPack]
+pub fn device_info_request<'t>(mac: &'t str, key: &str, variant: ProtocolVariant) -> Result<GenericOutMessage<'t>> {
+    let pack = serde_json::to_vec(&DeviceInfoRequestPack { mac, t: "dev" })?;
+
+    let (pack, tag) = encode_pack(pack, key.as_bytes(), variant)?;
+
+    Ok(GenericOutMessage {
+        cid: "app",
+        i: 0,
+        pack,
+        t: "pack",
+        tcid: mac,
+        uid: 0,
+        tag,
+    })
+}
+
+//------------------------------------------------------------------------------------------------------------------------------
+/* {
+"mac": "<MAC address of the bridge>",
+"i": 0,
+"t": "subList"
+} */
+
+/// Request pack for a page of the sub-units of a bridge
+#[derive(Serialize)]
+pub struct SubListRequestPack<'t> {
+    mac: &'t str,
+    i: Int,
+    t: &'t str,
+}
+
+/* {
+  "t": "subList",
+  "mac": "<MAC address of the bridge>",
+  "r": 200,
+  "c": 2,
+  "i": 0,
+  "list": [{ "mac": "<MAC address of the sub-unit>", "mid": "10001" }, ...]
+} */
+/// A page of the sub-units of a bridge; `c` is the number of the units in total
+#[derive(Debug, Deserialize)]
+pub struct SubListResponsePack {
+    #[serde(deserialize_with = "lenient::string")]
+    pub t: String,
+    #[serde(deserialize_with = "lenient::string")]
+    pub mac: String,
+    #[serde(deserialize_with = "lenient::int")]
+    pub r: Int,
+    /// Total number of the sub-units
+    #[serde(default, deserialize_with = "lenient::int")]
+    pub c: Int,
+    /// Index of the page
+    #[serde(default, deserialize_with = "lenient::int")]
+    pub i: Int,
+    #[serde(default)]
+    pub list: Vec<SubDeviceInfo>,
+    /// Fields not known to this crate
+    #[serde(flatten)]
+    pub extra: serde_json::Map<String, Value>,
+}
+
+/// A sub-unit of a multi-split bridge, as listed by the bridge
+#[derive(Debug, Clone, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct SubDeviceInfo {
+    #[serde(deserialize_with = "lenient::string")]
+    pub mac: String,
+    #[serde(default, deserialize_with = "lenient::string")]
+    pub mid: String,
+    #[serde(default, deserialize_with = "lenient::string")]
+    pub model: String,
+    #[serde(default, deserialize_with = "lenient::string")]
+    pub name: String,
+}
+
+impl ResultCode for SubListResponsePack { fn result_code(&self) -> i32 { self.r } }
+
+/// Request carrying an arbitrary pack, e.g. of a pack type not supported otherwise. The pack is sent as is, so it 
+/// should name its type in `t` and usually the device in `mac`.
+pub fn raw_request<'t>(mac: &'t str, key: &str, variant: ProtocolVariant, pack: &Value) -> Result<GenericOutMessage<'t>> {
+    let pack = serde_json::to_vec(pack)?;
+
+    let (pack, tag) = encode_pack(pack, key.as_bytes(), variant)?;
+
+    Ok(GenericOutMessage {
+        cid: "app",
+        i: 0,
+        pack,
+        t: "pack",
+        tcid: mac,
+        uid: 0,
+        tag,
+    })
+}
+
+/// Requests the page `i` of the sub-units of the bridge `mac`
+pub fn sub_list_request<'t>(mac: &'t str, key: &str, variant: ProtocolVariant, i: Int) -> Result<GenericOutMessage<'t>> {
+    let pack = serde_json::to_vec(&SubListRequestPack { mac, i, t: "subList" })?;
+
+    let (pack, tag) = encode_pack(pack, key.as_bytes(), variant)?;
+
+    Ok(GenericOutMessage {
+        cid: "app",
+        i: 0,
+        pack,
+        t: "pack",
+        tcid: mac,
+        uid: 0,
+        tag,
+    })
+}
+
 //------------------------------------------------------------------------------------------------------------------------------
 /* {
   "cols": [
@@ -471,6 +1118,7 @@ pub fn bind_request<'t>(mac: &'t str, key: &str) -> Result<GenericOutMessage<'t>
   "mac": "<MAC address>",
   "t": "status"
 } */
+/// Status request pack, naming the variables to read in `cols`
 #[derive(Serialize)]
 pub struct StatusRequestPack<'t> {
     cols: &'t[&'t str], 
@@ -505,23 +1153,37 @@ pub struct StatusRequestPack<'t> {
   ],
   "dat": [1, 1, 25, 1, 0, 0, 1, 0, 0, 0, 4, 0, 0, 0, 0, 0, 0, 0]
 } */
-#[derive(Debug, Deserialize)]
+/// Status response pack, with the values (`dat`) of the variables read (`cols`)
+#[derive(Debug, Serialize, Deserialize)]
 pub struct StatusResponsePack {
+    #[serde(deserialize_with = "lenient::string")]
     pub t: String,
+    #[serde(deserialize_with = "lenient::string")]
     pub mac: String,
+    #[serde(deserialize_with = "lenient::int")]
     pub r: Int,
     pub cols: Vec<String>,
     pub dat: Vec<Value>,
+    /// Fields not known to this crate
+    #[serde(flatten)]
+    pub extra: serde_json::Map<String, Value>,
 }
 
-pub fn status_request<'t>(mac: &'t str, key: &str, variables: &[&str]) -> Result<GenericOutMessage<'t>> {
+/// Status request reading the variables of the device `mac`
+pub fn status_request<'t>(mac: &'t str, key: &str, variant: ProtocolVariant, variables: &[&str]) -> Result<GenericOutMessage<'t>> {
+    sub_status_request(mac, mac, key, variant, variables)
+}
+
+/// Status request for the unit `sub` of the bridge `mac`, which is the unit itself for plain devices. The request is 
+/// sent to the bridge; the unit is only named in the pack.
+pub fn sub_status_request<'t>(mac: &'t str, sub: &str, key: &str, variant: ProtocolVariant, variables: &[&str]) -> Result<GenericOutMessage<'t>> {
     let pack = serde_json::to_vec(&StatusRequestPack {
         cols: variables,
-        mac,
+        mac: sub,
         t: "status",
     })?;
 
-    let pack = encode_request(pack, key.as_bytes());
+    let (pack, tag) = encode_pack(pack, key.as_bytes(), variant)?;
 
     /* {
     "cid": "app",
@@ -538,7 +1200,8 @@ pub fn status_request<'t>(mac: &'t str, key: &str, variables: &[&str]) -> Result
         pack,
         t: "pack",
         tcid: mac,
-        uid: 0
+        uid: 0,
+        tag,
     })
 
 }
@@ -551,11 +1214,15 @@ pub fn status_request<'t>(mac: &'t str, key: &str, variables: &[&str]) -> Result
 "t": "cmd"
 } */
 
+/// Command pack, writing the values (`p`) of the variables (`opt`)
 #[derive(Serialize)]
 pub struct CommandPack<'t> {
     opt: &'t[&'t str], 
     p: &'t[Value],
     t: &'t str,
+    /// The sub-unit addressed, if the device is a multi-split bridge
+    #[serde(skip_serializing_if = "Option::is_none")]
+    sub: Option<&'t str>,
 }
 
 /* {
@@ -566,32 +1233,48 @@ pub struct CommandPack<'t> {
   "p": [0, 27],
   "val": [0, 27]
 } */
-#[derive(Debug, Deserialize)]
+/// Command response pack, with the values (`p`) of the variables written (`opt`)
+#[derive(Debug, Serialize, Deserialize)]
 pub struct CommandResponsePack {
+    #[serde(deserialize_with = "lenient::string")]
     pub t: String,
+    #[serde(deserialize_with = "lenient::string")]
     pub mac: String,
+    #[serde(deserialize_with = "lenient::int")]
     pub r: Int,
     pub opt: Vec<String>,
     pub p: Vec<Value>,
 
     #[serde(default)]
     pub val: Vec<Value>,
+
+    /// Fields not known to this crate
+    #[serde(flatten)]
+    pub extra: serde_json::Map<String, Value>,
 }
 
 
-pub fn setvar_request<'t>(mac: &'t str, key: &str, names: &[&str], values: &[Value]) -> Result<GenericOutMessage<'t>> {
+/// Command writing the variables of the device `mac`
+pub fn setvar_request<'t>(mac: &'t str, key: &str, variant: ProtocolVariant, names: &[&str], values: &[Value]) -> Result<GenericOutMessage<'t>> {
+    sub_setvar_request(mac, None, key, variant, names, values)
+}
+
+/// Command for the unit `sub` of the bridge `mac`, or for the device itself if `sub` is `None`
+pub fn sub_setvar_request<'t>(mac: &'t str, sub: Option<&str>, key: &str, variant: ProtocolVariant, names: &[&str], values: &[Value]) -> Result<GenericOutMessage<'t>> {
     /* {
     "opt": ["TemUn", "SetTem"],
     "p": [0, 27],
-    "t": "cmd"
+    "t": "cmd",
+    "sub": "<MAC address of the sub-unit>"
     } */
     let pack = serde_json::to_vec(&CommandPack {
         opt: names,
         p: values,
         t: "cmd",
+        sub,
     })?;
 
-    let pack = encode_request(pack, key.as_bytes());
+    let (pack, tag) = encode_pack(pack, key.as_bytes(), variant)?;
 
 
     /* {
@@ -609,29 +1292,88 @@ pub fn setvar_request<'t>(mac: &'t str, key: &str, names: &[&str], values: &[Val
         pack,
         t: "pack",
         tcid: mac,
-        uid: 0
+        uid: 0,
+        tag,
     })
 }
 
 
-pub fn handle_response<T: de::DeserializeOwned + Debug>(addr: IpAddr, pack:&str, key: &str) -> Result<T> {
-    let pack = decode_response(pack, key)?;
+/// Decrypts the pack of the message `gm` received from `addr` and parses it as `T`
+pub fn handle_response<T: de::DeserializeOwned + Debug>(addr: IpAddr, gm: &GenericMessage, key: &str, variant: ProtocolVariant) -> Result<T> {
+    let pack = decode_pack(&gm.pack, &gm.tag, key, variant)?;
     trace!("[{}] pack raw: {}", addr, pack);
-    let pack: T = serde_json::from_str(&pack)?;
+    let pack: T = with_lenient(gm.lenient, || serde_json::from_str(&pack))?;
     debug!("[{}] pack: {:?}", addr, pack);
     Ok(pack)
 }
 
 //------------------------------------------------------------------------------------------------------------------------------
 
-fn pkcs7_unpad(payload: &mut Vec<u8>) {
-    if let Some(b) = payload.last() { 
-        for _ in 0..*b {
-            payload.pop();
+thread_local! {
+    static LENIENT: std::cell::Cell<bool> = const { std::cell::Cell::new(false) };
+}
+
+/// Runs `f`, parsing a message or a pack, with the coercions of [lenient] enabled if `lenient`
+pub(crate) fn with_lenient<R>(lenient: bool, f: impl FnOnce() -> R) -> R {
+    let prev = LENIENT.replace(lenient);
+    let r = f();
+    LENIENT.set(prev);
+    r
+}
+
+/// Deserializers of the fields of the messages and the packs. They are strict, unless run within [with_lenient], 
+/// which accepts integers sent as strings (`"r": "200"`), strings sent as numbers (`"mid": 10001`), and `null` for 
+/// either. See [GreeClientConfig::lenient].
+mod lenient {
+    use serde::{de::Error as _, Deserialize, Deserializer};
+    use super::*;
+
+    pub fn int<'de, D: Deserializer<'de>>(d: D) -> std::result::Result<Int, D::Error> {
+        if !LENIENT.get() { return Int::deserialize(d) }
+        let v = Value::deserialize(d)?;
+        let n = match &v {
+            Value::Null => Some(0),
+            Value::Bool(b) => Some(*b as i64),
+            // whole floats, e.g. `200.0`
+            Value::Number(n) => n.as_i64().or_else(|| n.as_f64().filter(|f| f.fract() == 0.0).map(|f| f as i64)),
+            Value::String(s) => s.trim().parse().ok(),
+            _ => None,
+        };
+        n.and_then(|n| Int::try_from(n).ok()).ok_or_else(|| D::Error::custom(format!("expected an integer, got {v}")))
+    }
+
+    pub fn string<'de, D: Deserializer<'de>>(d: D) -> std::result::Result<String, D::Error> {
+        if !LENIENT.get() { return String::deserialize(d) }
+        match Value::deserialize(d)? {
+            Value::Null => Ok(String::new()),
+            Value::String(s) => Ok(s),
+            v @ (Value::Number(_) | Value::Bool(_)) => Ok(v.to_string()),
+            v => Err(D::Error::custom(format!("expected a string, got {v}"))),
         }
     }
 }
 
+//------------------------------------------------------------------------------------------------------------------------------
+
+/// Ensures the key is usable with AES-128 (the key may come from a device, so it is not trusted)
+fn check_key(key: &[u8]) -> Result<&[u8]> {
+    if key.len() != 16 { return Err(InvalidPack::KeyLength(key.len()).into()) }
+    Ok(key)
+}
+
+/// Strips PKCS#7 padding, checking that all the padding bytes agree
+fn pkcs7_unpad(payload: &mut Vec<u8>, blocksize: usize) -> Result<()> {
+    let pad_len = *payload.last().ok_or(InvalidPack::Padding)? as usize;
+    if pad_len == 0 || pad_len > blocksize || pad_len > payload.len() {
+        return Err(InvalidPack::Padding.into())
+    }
+    if payload[payload.len() - pad_len..].iter().any(|b| *b as usize != pad_len) {
+        return Err(InvalidPack::Padding.into())
+    }
+    payload.truncate(payload.len() - pad_len);
+    Ok(())
+}
+
 fn pkcs7_pad(payload: &mut Vec<u8>, blocksize: u8) {
     let pad_len = blocksize - ((payload.len() % (blocksize as usize)) as u8);
     for _ in 0..pad_len {
@@ -639,38 +1381,178 @@ fn pkcs7_pad(payload: &mut Vec<u8>, blocksize: u8) {
     }
 }
 
+/// The decrypted payload as text, reusing its buffer unless it is not valid UTF-8
+fn into_string(payload: Vec<u8>) -> String {
+    String::from_utf8(payload).unwrap_or_else(|e| String::from_utf8_lossy(e.as_bytes()).into_owned())
+}
+
+/// Decodes AES-ECB encrypted pack ([ProtocolVariant::V1])
 pub fn decode_response(pack: &str, key: &str) -> Result<String> {
-    let key = GenericArray::clone_from_slice(key.as_bytes());
+    let key = GenericArray::clone_from_slice(check_key(key.as_bytes())?);
     let cipher = Aes128::new(&key);
     let blocksize = 16;
 
     let mut payload = general_purpose::STANDARD.decode(pack)?;
+    if payload.is_empty() || payload.len() % blocksize != 0 {
+        return Err(InvalidPack::Length(payload.len()).into())
+    }
 
-    for pos in (0..payload.len()).step_by(blocksize) {
-        let slice = &mut payload[pos..pos+blocksize];
-        let mut block = GenericArray::clone_from_slice(slice);
-        cipher.decrypt_block(&mut block);
-        slice.copy_from_slice(block.as_slice())
+    for block in payload.chunks_exact_mut(blocksize) {
+        cipher.decrypt_block(GenericArray::from_mut_slice(block));
     }
-    pkcs7_unpad(&mut payload);
-    Ok(String::from_utf8_lossy(&payload).to_string())
+    pkcs7_unpad(&mut payload, blocksize)?;
+    Ok(into_string(payload))
 }
 
-pub fn encode_request(mut payload: Vec<u8>, key: &[u8]) -> String {
-    let key = GenericArray::clone_from_slice(key);
+/// Encodes pack with AES-ECB ([ProtocolVariant::V1]), PKCS#7-padded and base64-encoded
+pub fn encode_request(mut payload: Vec<u8>, key: &[u8]) -> Result<String> {
+    let key = GenericArray::clone_from_slice(check_key(key)?);
     let cipher = Aes128::new(&key);
     let blocksize = 16;
 
     pkcs7_pad(&mut payload, blocksize as u8);
 
-    for pos in (0..payload.len()).step_by(blocksize) {
-        let slice = &mut payload[pos..pos+blocksize];
-        let mut block = GenericArray::clone_from_slice(slice);
-        cipher.encrypt_block(&mut block);
-        slice.copy_from_slice(block.as_slice())   
+    for block in payload.chunks_exact_mut(blocksize) {
+        cipher.encrypt_block(GenericArray::from_mut_slice(block));
     }
 
-    general_purpose::STANDARD.encode(payload)
+    Ok(general_purpose::STANDARD.encode(payload))
 }
 
+const GCM_NONCE: [u8; 12] = [0x54, 0x40, 0x78, 0x44, 0x49, 0x67, 0x5a, 0x51, 0x6c, 0x5e, 0x63, 0x13];
+const GCM_AAD: &[u8] = b"qualcomm-test";
+
+/// Decodes AES-GCM encrypted pack, verifying its tag
+pub fn decode_response_gcm(pack: &str, tag: &str, key: &str) -> Result<String> {
+    let cipher = Aes128Gcm::new(GenericArray::from_slice(check_key(key.as_bytes())?));
+    let mut payload = general_purpose::STANDARD.decode(pack)?;
+    let tag = general_purpose::STANDARD.decode(tag)?;
+    if tag.len() != 16 { return Err(InvalidPack::TagLength(tag.len()).into()) }
+    cipher.decrypt_in_place_detached(Nonce::from_slice(&GCM_NONCE), GCM_AAD, &mut payload, Tag::from_slice(&tag))
+        .map_err(|_| Error::Crypto)?;
+    Ok(into_string(payload))
+}
+
+/// Encodes pack with AES-GCM. Returns the encoded pack and tag.
+pub fn encode_request_gcm(mut payload: Vec<u8>, key: &[u8]) -> Result<(String, String)> {
+    let cipher = Aes128Gcm::new(GenericArray::from_slice(check_key(key)?));
+    let tag = cipher.encrypt_in_place_detached(Nonce::from_slice(&GCM_NONCE), GCM_AAD, &mut payload)
+        .map_err(|_| Error::Crypto)?;
+    Ok((general_purpose::STANDARD.encode(payload), general_purpose::STANDARD.encode(tag)))
+}
 
+/// Decodes pack according to the protocol variant. `tag` is ignored for [ProtocolVariant::V1].
+pub fn decode_pack(pack: &str, tag: &str, key: &str, variant: ProtocolVariant) -> Result<String> {
+    match variant {
+        ProtocolVariant::V1 => decode_response(pack, key),
+        ProtocolVariant::V2 => decode_response_gcm(pack, tag, key),
+    }
+}
+
+/// Encodes pack according to the protocol variant. Returns the encoded pack and the tag ([ProtocolVariant::V2] only).
+pub fn encode_pack(payload: Vec<u8>, key: &[u8], variant: ProtocolVariant) -> Result<(String, Option<String>)> {
+    match variant {
+        ProtocolVariant::V1 => encode_request(payload, key).map(|pack| (pack, None)),
+        ProtocolVariant::V2 => encode_request_gcm(payload, key).map(|(pack, tag)| (pack, Some(tag))),
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY: &str = "a3K8Bx%2r8Y7#xDh";
+
+    /// Encrypts whole blocks with AES-ECB without adding padding, to build malformed packs
+    fn ecb_raw(mut payload: Vec<u8>) -> String {
+        let cipher = Aes128::new(GenericArray::from_slice(KEY.as_bytes()));
+        for block in payload.chunks_exact_mut(16) {
+            cipher.encrypt_block(GenericArray::from_mut_slice(block));
+        }
+        general_purpose::STANDARD.encode(payload)
+    }
+
+    fn padded(pad: &[u8]) -> Vec<u8> {
+        let mut payload = vec![b'x'; 16 - pad.len()];
+        payload.extend_from_slice(pad);
+        payload
+    }
+
+    #[test]
+    fn pkcs7_unpad_checks_padding() {
+        let mut p = padded(&[3, 3, 3]);
+        pkcs7_unpad(&mut p, 16).unwrap();
+        assert_eq!(p, vec![b'x'; 13]);
+
+        let mut p = vec![16; 16];
+        pkcs7_unpad(&mut p, 16).unwrap();
+        assert!(p.is_empty());
+
+        for mut p in [vec![], padded(&[0]), padded(&[17]), padded(&[2, 3, 3]), padded(&[1, 3, 3]), vec![4, 4, 4]] {
+            assert!(matches!(pkcs7_unpad(&mut p, 16), Err(Error::InvalidPack(InvalidPack::Padding))), "{p:?}");
+        }
+    }
+
+    #[test]
+    fn decode_response_round_trip() {
+        for text in ["", "{}", "0123456789abcdef", "{\"t\":\"status\",\"mac\":\"000cc0000001\"}"] {
+            let pack = encode_request(text.as_bytes().to_vec(), KEY.as_bytes()).unwrap();
+            assert_eq!(decode_response(&pack, KEY).unwrap(), text);
+        }
+    }
+
+    #[test]
+    fn decode_response_rejects_bad_length() {
+        assert!(matches!(decode_response("", KEY), Err(Error::InvalidPack(InvalidPack::Length(0)))));
+        let pack = general_purpose::STANDARD.encode([0u8; 15]);
+        assert!(matches!(decode_response(&pack, KEY), Err(Error::InvalidPack(InvalidPack::Length(15)))));
+        let pack = general_purpose::STANDARD.encode([0u8; 17]);
+        assert!(matches!(decode_response(&pack, KEY), Err(Error::InvalidPack(InvalidPack::Length(17)))));
+        assert!(matches!(decode_response("not base64!", KEY), Err(Error::Base64Decode(_))));
+    }
+
+    #[test]
+    fn decode_response_rejects_bad_padding() {
+        for pad in [&[0u8][..], &[17], &[2, 3, 3], &[5, 5]] {
+            let pack = ecb_raw(padded(pad));
+            assert!(matches!(decode_response(&pack, KEY), Err(Error::InvalidPack(InvalidPack::Padding))), "{pad:?}");
+        }
+        let pack = ecb_raw(padded(&[2, 2]));
+        assert_eq!(decode_response(&pack, KEY).unwrap(), "x".repeat(14));
+    }
+
+    #[test]
+    fn decode_rejects_wrong_key_length() {
+        let pack = encode_request(b"{}".to_vec(), KEY.as_bytes()).unwrap();
+        for key in ["", "short", "a3K8Bx%2r8Y7#xDh+"] {
+            assert!(matches!(decode_response(&pack, key), Err(Error::InvalidPack(InvalidPack::KeyLength(n))) if n == key.len()));
+            assert!(matches!(decode_response_gcm(&pack, "", key), Err(Error::InvalidPack(InvalidPack::KeyLength(n))) if n == key.len()));
+        }
+        assert!(matches!(encode_request(vec![], b"short"), Err(Error::InvalidPack(InvalidPack::KeyLength(5)))));
+        assert!(matches!(encode_request_gcm(vec![], b"short"), Err(Error::InvalidPack(InvalidPack::KeyLength(5)))));
+    }
+
+    #[test]
+    fn decode_response_gcm_round_trip() {
+        for text in ["", "{}", "{\"t\":\"status\",\"mac\":\"000cc0000001\"}"] {
+            let (pack, tag) = encode_request_gcm(text.as_bytes().to_vec(), KEY.as_bytes()).unwrap();
+            assert_eq!(decode_response_gcm(&pack, &tag, KEY).unwrap(), text);
+        }
+    }
+
+    #[test]
+    fn decode_response_gcm_checks_tag() {
+        let (pack, tag) = encode_request_gcm(b"{}".to_vec(), KEY.as_bytes()).unwrap();
+        let mut raw_tag = general_purpose::STANDARD.decode(&tag).unwrap();
+
+        let short = general_purpose::STANDARD.encode(&raw_tag[..12]);
+        assert!(matches!(decode_response_gcm(&pack, &short, KEY), Err(Error::InvalidPack(InvalidPack::TagLength(12)))));
+        assert!(matches!(decode_response_gcm(&pack, "", KEY), Err(Error::InvalidPack(InvalidPack::TagLength(0)))));
+
+        raw_tag[0] ^= 1;
+        let forged = general_purpose::STANDARD.encode(&raw_tag);
+        assert!(matches!(decode_response_gcm(&pack, &forged, KEY), Err(Error::Crypto)));
+        assert!(matches!(decode_response_gcm(&pack, &tag, "fedcba9876543210"), Err(Error::Crypto)));
+    }
+}
@@ -1,228 +1,981 @@
-//! Asynchronous Gree cilents (requires `tokio`)
+//! Asynchronous Gree cilents (requires `tokio`, `async-std` or `smol`)
 //! 
 //! Example usage:
 //! 
-//! ```
+//! ```no_run
+//! # use gree::{*, async_client::*};
+//! # async fn run() -> Result<()> {
 //! let mut cc = GreeClientConfig::default();
 //! cc.bcast_addr = [192, 168, 0, 255].into();
 //! let c = GreeClient::new(cc).await?;
-//! for (ip, _, pack) in  cc.scan().await? {
+//! for (ip, _, pack) in  c.scan().await? {
 //!     println!("{ip} {pack:?}")
 //! }
+//! # Ok(())
+//! # }
 //! ```
 
-#![cfg(feature = "tokio")]
+#![cfg(any(feature = "tokio", feature = "async-std", feature = "smol"))]
 
-use std::{net::IpAddr, time::Instant};
-use tokio::{select, net::UdpSocket, time};
+use std::{net::{IpAddr, SocketAddr}, time::{Instant, Duration, SystemTime}, future::Future, sync::{Arc, atomic::{AtomicBool, AtomicI32, Ordering}}, collections::HashMap};
+use tokio::sync::{Mutex, RwLock, mpsc, oneshot, watch};
 use serde_json::Value;
-use crate::{state::*, vars::VarName};
+use futures_util::future::join_all;
+use crate::{state::*, vars::VarName, rt::{self, UdpSocket}, recording::{Direction, RawPacketTap}};
 use super::*;
 
+pub use crate::rt::JoinHandle;
+
+/// Datagram transport of the [GreeClient]
+/// 
+/// Implemented for the UDP socket of the runtime (e.g. `tokio::net::UdpSocket`). Alternative transports (in-memory, relayed, replayed etc.) may be plugged in 
+/// with [GreeClient::with_transport].
+pub trait Transport: Send + Sync + 'static {
+    /// Sends a datagram to the address specified
+    fn send_to(&self, buf: &[u8], addr: SocketAddr) -> impl Future<Output = Result<usize>> + Send;
+    /// Receives a datagram
+    fn recv_from(&self, buf: &mut [u8]) -> impl Future<Output = Result<(usize, SocketAddr)>> + Send;
+}
+
+impl Transport for UdpSocket {
+    async fn send_to(&self, buf: &[u8], addr: SocketAddr) -> Result<usize> {
+        Ok(UdpSocket::send_to(self, buf, addr).await?)
+    }
+
+    async fn recv_from(&self, buf: &mut [u8]) -> Result<(usize, SocketAddr)> {
+        Ok(UdpSocket::recv_from(self, buf).await?)
+    }
+}
+
+impl<T: Transport> Transport for Arc<T> {
+    async fn send_to(&self, buf: &[u8], addr: SocketAddr) -> Result<usize> {
+        (**self).send_to(buf, addr).await
+    }
+
+    async fn recv_from(&self, buf: &mut [u8]) -> Result<(usize, SocketAddr)> {
+        (**self).recv_from(buf).await
+    }
+}
+
+/// An exchange awaiting its response
+struct Waiter {
+    ip: IpAddr,
+    mac: String,
+    tx: oneshot::Sender<GenericMessage>,
+}
+
+impl Waiter {
+    fn is_answered_by(&self, addr: IpAddr, gm: &GenericMessage) -> bool {
+        is_response(self.ip, &self.mac, addr, gm)
+    }
+}
+
+type Waiters = Arc<std::sync::Mutex<Vec<Waiter>>>;
+
 /// Low-level Gree API
 /// 
+/// Datagrams are received by a background task, which dispatches the responses to the exchanges awaiting them, so 
+/// that exchanges with different devices may be in flight simultaneously over the one socket. Scans are exclusive, 
+/// as scan responses cannot be told apart from other traffic. The client must be created within a Tokio runtime.
+/// 
 /// See module-level docs for a quick example.
-pub struct GreeClient {
-    s: UdpSocket,
+pub struct GreeClient<T: Transport = UdpSocket> {
+    s: Arc<T>,
     cfg: GreeClientConfig,
+    /// Shared by the exchanges, taken exclusively by the scans
+    busy: RwLock<()>,
+    waiters: Waiters,
+    /// Set once the receive task has stopped on an error, after which the exchanges fail immediately
+    closed: Arc<AtomicBool>,
+    /// Datagrams not answering any exchange, i.e. scan responses
+    unsolicited: Mutex<mpsc::UnboundedReceiver<(IpAddr, GenericMessage)>>,
+    recv_task: JoinHandle<()>,
+    tap: RawPacketTap,
+    stats: std::sync::Mutex<HashMap<MacAddr, NetStats>>,
+    /// Last `i` sent, see [SeqMode::Increment]
+    seq: AtomicI32,
 }
 
 impl GreeClient {
     /// Crates new `GreeClient` from `GreeClientConfig`
     pub async fn new(cfg: GreeClientConfig) -> Result<Self> {
         let s = UdpSocket::bind(cfg.bind_addr).await?;
+        Self::from_socket(cfg, s)
+    }
+
+    /// Creates new `GreeClient` over a socket bound by the application, e.g. with `SO_REUSEADDR` set or bound to a 
+    /// particular interface. Enables broadcasting on the socket; `bind_addr` of the configuration is not used. The 
+    /// client receives all the datagrams arriving at the socket, so the socket should not be read by anything else.
+    pub fn from_socket(cfg: GreeClientConfig, s: UdpSocket) -> Result<Self> {
         s.set_broadcast(true)?;
         trace!("Bound to: {:?}", s.local_addr());
-        Ok(Self { s, cfg })
+        Ok(Self::with_transport(cfg, s))
     }
 
-    async fn recv(&self) -> Result<(IpAddr, GenericMessage)> {
-        let mut b = vec![0u8; self.cfg.buffer_size];
-        let (len, addr) = select! {
-            la = self.s.recv_from(&mut b) => { la? }
-            _ = time::sleep(self.cfg.recv_timeout) => { Err(Error::ResponseTimeout)? }
-        };
+    /// Same as [GreeClient::from_socket], for a `std::net::UdpSocket` (e.g. one created with `socket2`), which is 
+    /// switched to non-blocking mode
+    pub fn from_std_socket(cfg: GreeClientConfig, s: std::net::UdpSocket) -> Result<Self> {
+        Self::from_socket(cfg, rt::udp_socket_from_std(s)?)
+    }
+}
+
+impl<T: Transport> GreeClient<T> {
+    /// Creates new `GreeClient` over the transport specified. `bind_addr` of the configuration is not used.
+    /// 
+    /// Spawns the receive task, so must be called within the runtime (a Tokio runtime with `tokio`).
+    pub fn with_transport(cfg: GreeClientConfig, s: T) -> Self {
+        let s = Arc::new(s);
+        let waiters = Waiters::default();
+        let (tx, rx) = mpsc::unbounded_channel();
+        let tap = RawPacketTap::default();
+        let closed = Arc::new(AtomicBool::new(false));
+        let recv_task = rt::spawn(Self::recv_loop(s.clone(), cfg.clone(), waiters.clone(), closed.clone(), tx, tap.clone()));
+        Self { s, cfg, busy: RwLock::new(()), waiters, closed, unsolicited: Mutex::new(rx), recv_task, tap, stats: Default::default(), seq: AtomicI32::new(0) }
+    }
+
+    /// Sets the hook called with every datagram sent or received, undecoded, e.g. for dumping the traffic (see 
+    /// [RawPacketLogger](crate::recording::RawPacketLogger)). The hook runs within the task sending or receiving the 
+    /// datagram, so it should not block. Replaces the hook set before.
+    pub fn on_raw_packet(&self, hook: impl Fn(Direction, SocketAddr, &[u8]) + Send + Sync + 'static) {
+        self.tap.set(Some(Arc::new(hook)));
+    }
+
+    /// Removes the hook set by [GreeClient::on_raw_packet]
+    pub fn clear_raw_packet_hook(&self) {
+        self.tap.set(None);
+    }
+
+    /// Network statistics of the devices exchanged with, by MAC address (of the bridge, for sub-units)
+    pub fn stats(&self) -> HashMap<MacAddr, NetStats> {
+        lock(&self.stats).clone()
+    }
 
-        trace!("[{}] raw: {}", addr, String::from_utf8_lossy(&b[..len]));
+    fn count(&self, mac: &str, f: impl FnOnce(&mut NetStats)) {
+        f(lock(&self.stats).entry(mac.into()).or_default())
+    }
 
-        let gm: GenericMessage = serde_json::from_slice(&b[..len])?;
-        debug!("[{}]: {:?}", addr, gm);
+    /// Decodes the response, counting the failures in the statistics of `mac`
+    fn decode<P: serde::de::DeserializeOwned + std::fmt::Debug>(&self, mac: &str, addr: IpAddr, gm: &GenericMessage, key: &str, variant: ProtocolVariant) -> Result<P> {
+        handle_response(addr, gm, key, variant).inspect_err(|_| self.count(mac, |st| st.decode_errors += 1))
+    }
 
-        Ok((addr.ip(), gm))
+    async fn send_to(&self, b: &[u8], ip: IpAddr) -> Result<usize> {
+        let addr = (ip, PORT).into();
+        let nbytes = self.s.send_to(b, addr).await?;
+        self.tap.call(Direction::Sent, addr, b);
+        Ok(nbytes)
+    }
+
+    /// Receives the datagrams, passing each to the exchange it answers, or to `unsolicited`. Transient errors (e.g. 
+    /// an ICMP port unreachable reported as `ConnectionReset`) are skipped; on any other error, the loop stops and 
+    /// marks the client `closed`.
+    async fn recv_loop(s: Arc<T>, cfg: GreeClientConfig, waiters: Waiters, closed: Arc<AtomicBool>, unsolicited: mpsc::UnboundedSender<(IpAddr, GenericMessage)>, tap: RawPacketTap) {
+        use std::io::ErrorKind::*;
+        let mut b = vec![0u8; cfg.buffer_size];
+        loop {
+            let (len, addr) = match s.recv_from(&mut b).await {
+                Ok(la) => la,
+                Err(Error::Io(e)) if matches!(e.kind(), WouldBlock | TimedOut | Interrupted | ConnectionReset | ConnectionRefused) => {
+                    debug!("Recv: {e}");
+                    continue
+                }
+                Err(e) => { error!("Recv: {e}"); break }
+            };
+            tap.call(Direction::Received, addr, &b[..len]);
+            trace!("[{}] raw: {}", addr, String::from_utf8_lossy(&b[..len]));
+            let Some(gm) = parse_datagram(addr, &b[..len], &cfg) else { continue };
+            debug!("[{}]: {:?}", addr, gm);
+            Self::dispatch(&waiters, &unsolicited, addr.ip(), gm);
+        }
+        // fail the pending exchanges rather than let them time out, and the later ones at once
+        closed.store(true, Ordering::SeqCst);
+        lock(&waiters).clear();
+    }
+
+    fn dispatch(waiters: &Waiters, unsolicited: &mpsc::UnboundedSender<(IpAddr, GenericMessage)>, addr: IpAddr, mut gm: GenericMessage) {
+        let mut waiters = lock(waiters);
+        while let Some(i) = waiters.iter().position(|w| w.is_answered_by(addr, &gm)) {
+            // an error means that the exchange has been abandoned; the response may still answer another one
+            match waiters.remove(i).tx.send(gm) {
+                Ok(()) => return,
+                Err(returned) => gm = returned,
+            }
+        }
+        drop(waiters);
+        // a send error only means that the client has gone
+        let _ = unsolicited.send((addr, gm));
     }
 
-    async fn exchange<'t>(&self, ip: IpAddr, request: &GenericOutMessage<'t>) -> Result<GenericMessage> {
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "trace", skip_all, fields(ip = %ip, mac = request.tcid), err))]
+    async fn exchange<'t>(&self, ip: IpAddr, mut request: GenericOutMessage<'t>, timeout: Duration) -> Result<GenericMessage> {
+        let retry = self.cfg.retry;
+        request.uid = self.cfg.uid;
+        request.i = self.cfg.seq.value(request.i, &self.seq);
+        let request = &request;
         let b = serde_json::to_vec(request)?;
-        self.s.send_to(&b, (ip, PORT)).await?;
+        self.count(request.tcid, |st| st.requests += 1);
+        let mut attempt = 1;
+        loop {
+            let started = Instant::now();
+            match self.exchange_once(ip, request, &b, timeout).await {
+                Err(e) if e.is_timeout() && attempt < retry.attempts => {
+                    self.count(request.tcid, |st| st.retries += 1);
+                    let delay = retry.delay(attempt);
+                    debug!("[{ip}] {e}, retrying in {delay:?}");
+                    rt::sleep(delay).await;
+                    attempt += 1;
+                }
+                r => {
+                    match &r {
+                        Ok(_) => self.count(request.tcid, |st| st.last_rtt = Some(started.elapsed())),
+                        Err(e) if e.is_timeout() => self.count(request.tcid, |st| st.timeouts += 1),
+                        Err(_) => (),
+                    }
+                    break r
+                }
+            }
+        }
+    }
 
-        let gm = loop {
-            let (addr, gm) = self.recv().await?;
-            if addr == ip { break gm }
-        };
+    async fn exchange_once<'t>(&self, ip: IpAddr, request: &GenericOutMessage<'t>, b: &[u8], timeout: Duration) -> Result<GenericMessage> {
+        let _busy = self.busy.read().await;
+        let (tx, rx) = oneshot::channel();
+        {
+            let mut waiters = lock(&self.waiters);
+            waiters.retain(|w| !w.tx.is_closed());
+            waiters.push(Waiter { ip, mac: request.tcid.to_owned(), tx });
+        }
+        // checked after the waiter is registered, so that it is either cleared by the receive task or not sent at all
+        if self.closed.load(Ordering::SeqCst) { return Err(Error::receiver_disconnected()) }
+        self.send_to(b, ip).await?;
 
-        Ok(gm)
+        match rt::timeout(timeout, rx).await {
+            Some(Ok(gm)) => Ok(gm),
+            Some(Err(_)) => Err(Error::receiver_disconnected()),
+            None => Err(Error::response_timeout()),
+        }
     }
 
-    /// Performs network scan to discover devices. 
+    /// Performs network scan to discover devices, broadcasting the scan pack to `bcast_addr` and `extra_bcast_addrs`. 
     /// 
-    /// The scan is terminated either when max device count is reached, or by timeout     
+    /// The scan is terminated as set by [GreeClientConfig::scan_mode]     
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip_all, fields(op = "scan", bcast = %self.cfg.bcast_addr), err))]
     pub async fn scan(&self) -> Result<Vec<(IpAddr, GenericMessage, ScanResponsePack)>> {
-        self.s.send_to(scan_request(), (self.cfg.bcast_addr, PORT)).await?;
+        let bcast_addrs = std::iter::once(self.cfg.bcast_addr).chain(self.cfg.extra_bcast_addrs.iter().copied());
+        self.scan_to(bcast_addrs).await
+    }
+
+    /// Performs network scan by sending the scan pack directly to each of the addresses, rather than broadcasting it. 
+    /// Useful where broadcasts are filtered (e.g. Docker bridges, some Wi-Fi networks). See [ip_range] for address ranges.
+    /// 
+    /// The scan is terminated as set by [GreeClientConfig::scan_mode]
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip_all, fields(op = "scan_range"), err))]
+    pub async fn scan_range(&self, ips: impl IntoIterator<Item = IpAddr>) -> Result<Vec<(IpAddr, GenericMessage, ScanResponsePack)>> {
+        self.scan_to(ips).await
+    }
+
+    async fn scan_to(&self, ips: impl IntoIterator<Item = IpAddr>) -> Result<Vec<(IpAddr, GenericMessage, ScanResponsePack)>> {
+        let _busy = self.busy.write().await;
+        let mut unsolicited = self.unsolicited.lock().await;
+        // stale datagrams, e.g. late responses to the exchanges timed out
+        while unsolicited.try_recv().is_ok() { }
+        for ip in ips {
+            self.send_to(scan_request(), ip).await
+                .map_err(|e| e.with_context(Operation::Scan, None, Some(ip)))?;
+        }
     
         let mut rv = vec![];
+        let started = Instant::now();
     
-        for _ in 0..self.cfg.max_count {
-            match self.recv().await {
-                Ok((addr, gm)) => {
-                    let pack = handle_response(addr, &gm.pack, GENERIC_KEY)?;
+        while let Some(wait) = self.cfg.scan_wait(started, rv.len()) {
+            match rt::timeout(wait, unsolicited.recv()).await {
+                Some(Some((addr, gm))) => {
+                    let pack = match handle_response(addr, &gm, self.cfg.generic_key(gm.variant()), gm.variant()) {
+                        Ok(pack) => pack,
+                        Err(e) => { error!("[{addr}] scan response skipped: {e}"); continue }
+                    };
                     rv.push((addr, gm, pack));
-                } 
-                Err(_) => break, //timeout
+                }
+                Some(None) => Err(Error::receiver_disconnected())?,
+                None => break, //timeout
             }
         }
         Ok(rv)
     }
     
     /// Performs binding operation on a device
-    pub async fn bind(&self, addr: IpAddr, mac: &str) -> Result<BindResponsePack> {
-        let gm = bind_request(mac, GENERIC_KEY)?;
-        let ogm = self.exchange(addr, &gm).await?;
-        Ok(handle_response(addr, &ogm.pack, GENERIC_KEY)?)
+    pub async fn bind(&self, addr: IpAddr, mac: &str, variant: ProtocolVariant) -> Result<BindResponsePack> {
+        self.bind_with_key(addr, mac, variant, self.cfg.generic_key(variant)).await
+    }
+
+    /// Performs binding operation on a device, encrypting the exchange with `key` instead of the generic key, for 
+    /// firmwares shipping with a different default key
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip_all, fields(op = "bind", ip = %addr, mac, ?variant), err))]
+    pub async fn bind_with_key(&self, addr: IpAddr, mac: &str, variant: ProtocolVariant, key: &str) -> Result<BindResponsePack> {
+        async {
+            let gm = bind_request(mac, key, variant, self.cfg.uid)?;
+            let ogm = self.exchange(addr, gm, self.cfg.bind_timeout).await?;
+            check_result(mac, self.decode(mac, addr, &ogm, key, variant)?)
+        }.await.map_err(|e| e.with_context(Operation::Bind, Some(mac), Some(addr)))
     }
 
     /// Reads specified variables from the device
-    pub async fn getvars(&self, addr: IpAddr, mac: &str, key: &str, vars: &[&str]) -> Result<StatusResponsePack> {
-        let gm = status_request(mac, key, vars)?;
-        let ogm = self.exchange(addr, &gm).await?;
-        Ok(handle_response(addr, &ogm.pack, key)?)
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip_all, fields(op = "getvars", ip = %addr, mac, ?vars), err))]
+    pub async fn getvars(&self, addr: IpAddr, mac: &str, key: &str, variant: ProtocolVariant, vars: &[&str]) -> Result<StatusResponsePack> {
+        async {
+            let gm = status_request(mac, key, variant, vars)?;
+            let ogm = self.exchange(addr, gm, self.cfg.exchange_timeout).await?;
+            check_result(mac, self.decode(mac, addr, &ogm, key, variant)?)
+        }.await.map_err(|e| e.with_context(Operation::Get, Some(mac), Some(addr)))
     }
 
     /// Writes specified variables to the device
-    pub async fn setvars(&self, addr: IpAddr, mac: &str, key: &str, names: &[VarName], values: &[Value]) -> Result<CommandResponsePack> {
-        let gm = setvar_request(mac, key, names, values)?;
-        let ogm = self.exchange(addr, &gm).await?;
-        Ok(handle_response(addr, &ogm.pack, key)?)
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip_all, fields(op = "setvars", ip = %addr, mac, ?names), err))]
+    pub async fn setvars(&self, addr: IpAddr, mac: &str, key: &str, variant: ProtocolVariant, names: &[VarName], values: &[Value]) -> Result<CommandResponsePack> {
+        async {
+            let gm = setvar_request(mac, key, variant, names, values)?;
+            let ogm = self.exchange(addr, gm, self.cfg.exchange_timeout).await?;
+            check_result(mac, self.decode(mac, addr, &ogm, key, variant)?)
+        }.await.map_err(|e| e.with_context(Operation::Set, Some(mac), Some(addr)))
+    }
+
+    /// Reads specified variables from the unit `sub` of the multi-split bridge `mac`
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip_all, fields(op = "getvars", ip = %addr, mac, sub, ?vars), err))]
+    pub async fn getvars_sub(&self, addr: IpAddr, mac: &str, sub: &str, key: &str, variant: ProtocolVariant, vars: &[&str]) -> Result<StatusResponsePack> {
+        async {
+            let gm = sub_status_request(mac, sub, key, variant, vars)?;
+            let ogm = self.exchange(addr, gm, self.cfg.exchange_timeout).await?;
+            check_result(sub, self.decode(mac, addr, &ogm, key, variant)?)
+        }.await.map_err(|e| e.with_context(Operation::Get, Some(sub), Some(addr)))
+    }
+
+    /// Writes specified variables to the unit `sub` of the multi-split bridge `mac`
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip_all, fields(op = "setvars", ip = %addr, mac, sub, ?names), err))]
+    #[allow(clippy::too_many_arguments)]
+    pub async fn setvars_sub(&self, addr: IpAddr, mac: &str, sub: &str, key: &str, variant: ProtocolVariant, names: &[VarName], values: &[Value]) -> Result<CommandResponsePack> {
+        async {
+            let gm = sub_setvar_request(mac, Some(sub), key, variant, names, values)?;
+            let ogm = self.exchange(addr, gm, self.cfg.exchange_timeout).await?;
+            check_result(sub, self.decode(mac, addr, &ogm, key, variant)?)
+        }.await.map_err(|e| e.with_context(Operation::Set, Some(sub), Some(addr)))
+    }
+
+    /// Lists the sub-units of the multi-split bridge `mac` (see [ScanResponsePack::sub_cnt]), requesting the pages 
+    /// until all the units reported are received
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip_all, fields(op = "sub_list", ip = %addr, mac), err))]
+    pub async fn sub_list(&self, addr: IpAddr, mac: &str, key: &str, variant: ProtocolVariant) -> Result<Vec<SubDeviceInfo>> {
+        async {
+            let mut rv = vec![];
+            for i in 0.. {
+                let gm = sub_list_request(mac, key, variant, i)?;
+                let ogm = self.exchange(addr, gm, self.cfg.exchange_timeout).await?;
+                let pack: SubListResponsePack = check_result(mac, self.decode(mac, addr, &ogm, key, variant)?)?;
+                let last = pack.list.is_empty();
+                rv.extend(pack.list);
+                if last || rv.len() >= pack.c.max(0) as usize { break }
+            }
+            Ok::<_, Error>(rv)
+        }.await.map_err(|e| e.with_context(Operation::Get, Some(mac), Some(addr)))
     }
 
+    /// Queries the device details, including the firmware and hardware ids not carried by the scan response
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip_all, fields(op = "device_info", ip = %addr, mac), err))]
+    pub async fn device_info(&self, addr: IpAddr, mac: &str, key: &str, variant: ProtocolVariant) -> Result<DeviceInfoPack> {
+        async {
+            let gm = device_info_request(mac, key, variant)?;
+            let ogm = self.exchange(addr, gm, self.cfg.exchange_timeout).await?;
+            self.decode(mac, addr, &ogm, key, variant)
+        }.await.map_err(|e| e.with_context(Operation::Get, Some(mac), Some(addr)))
+    }
+
+    /// Sends an arbitrary pack (a JSON object naming its type in `t`) encrypted with the key, and returns the pack 
+    /// received in response, decrypted but otherwise uninterpreted. See [protocol::raw_request].
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip_all, fields(op = "raw", ip = %addr, mac), err))]
+    pub async fn exchange_pack(&self, addr: IpAddr, mac: &str, key: &str, variant: ProtocolVariant, pack: &Value) -> Result<Value> {
+        async {
+            let gm = raw_request(mac, key, variant, pack)?;
+            let ogm = self.exchange(addr, gm, self.cfg.exchange_timeout).await?;
+            self.decode(mac, addr, &ogm, key, variant)
+        }.await.map_err(|e| e.with_context(Operation::Raw, Some(mac), Some(addr)))
+    }
+
+    /// Sets the device clock. `time` is formatted as `"YYYY-MM-DD HH:MM:SS"`, see [vars::local_time]. The `time` 
+    /// variable is sent in a pack of its own, as the devices do not accept it along with other variables.
+    pub async fn set_time(&self, addr: IpAddr, mac: &str, key: &str, variant: ProtocolVariant, time: &str) -> Result<CommandResponsePack> {
+        self.setvars(addr, mac, key, variant, &[vars::TIME], &[Value::String(time.to_owned())]).await
+    }
+
+}
+
+impl<T: Transport> Drop for GreeClient<T> {
+    fn drop(&mut self) {
+        self.recv_task.abort();
+    }
+}
+
+/// Operations of the low-level client, used by [Gree]
+/// 
+/// Implemented by [GreeClient]. Other implementations (e.g. mocks) may be injected with [Gree::with_client].
+pub trait GreeClientApi: Send + Sync {
+    /// Performs network scan to discover devices
+    fn scan(&self) -> impl Future<Output = Result<Vec<(IpAddr, GenericMessage, ScanResponsePack)>>> + Send;
+    /// Performs network scan by sending the scan pack to each of the addresses
+    fn scan_range(&self, ips: &[IpAddr]) -> impl Future<Output = Result<Vec<(IpAddr, GenericMessage, ScanResponsePack)>>> + Send;
+    /// Performs binding operation on a device
+    fn bind(&self, addr: IpAddr, mac: &str, variant: ProtocolVariant) -> impl Future<Output = Result<BindResponsePack>> + Send;
+    /// Reads specified variables from the device
+    fn getvars(&self, addr: IpAddr, mac: &str, key: &str, variant: ProtocolVariant, vars: &[&str]) -> impl Future<Output = Result<StatusResponsePack>> + Send;
+    /// Writes specified variables to the device
+    fn setvars(&self, addr: IpAddr, mac: &str, key: &str, variant: ProtocolVariant, names: &[VarName], values: &[Value]) -> impl Future<Output = Result<CommandResponsePack>> + Send;
+    /// Reads specified variables from a unit of a multi-split bridge
+    fn getvars_sub(&self, addr: IpAddr, mac: &str, sub: &str, key: &str, variant: ProtocolVariant, vars: &[&str]) -> impl Future<Output = Result<StatusResponsePack>> + Send;
+    /// Writes specified variables to a unit of a multi-split bridge
+    #[allow(clippy::too_many_arguments)]
+    fn setvars_sub(&self, addr: IpAddr, mac: &str, sub: &str, key: &str, variant: ProtocolVariant, names: &[VarName], values: &[Value]) -> impl Future<Output = Result<CommandResponsePack>> + Send;
+    /// Lists the units of a multi-split bridge
+    fn sub_list(&self, addr: IpAddr, mac: &str, key: &str, variant: ProtocolVariant) -> impl Future<Output = Result<Vec<SubDeviceInfo>>> + Send;
+    /// Queries the device details
+    fn device_info(&self, addr: IpAddr, mac: &str, key: &str, variant: ProtocolVariant) -> impl Future<Output = Result<DeviceInfoPack>> + Send;
+    /// Exchanges an arbitrary pack with the device
+    fn exchange_pack(&self, addr: IpAddr, mac: &str, key: &str, variant: ProtocolVariant, pack: &Value) -> impl Future<Output = Result<Value>> + Send;
+    /// Network statistics of the devices, by MAC address; none by default
+    fn net_stats(&self) -> HashMap<MacAddr, NetStats> {
+        HashMap::new()
+    }
 }
 
+impl<T: Transport> GreeClientApi for GreeClient<T> {
+    async fn scan(&self) -> Result<Vec<(IpAddr, GenericMessage, ScanResponsePack)>> {
+        GreeClient::<T>::scan(self).await
+    }
 
-struct GreeInternal {
-    c: GreeClient,
-    s: GreeState,
+    async fn scan_range(&self, ips: &[IpAddr]) -> Result<Vec<(IpAddr, GenericMessage, ScanResponsePack)>> {
+        GreeClient::<T>::scan_range(self, ips.iter().copied()).await
+    }
+
+    async fn bind(&self, addr: IpAddr, mac: &str, variant: ProtocolVariant) -> Result<BindResponsePack> {
+        GreeClient::<T>::bind(self, addr, mac, variant).await
+    }
+
+    async fn getvars(&self, addr: IpAddr, mac: &str, key: &str, variant: ProtocolVariant, vars: &[&str]) -> Result<StatusResponsePack> {
+        GreeClient::<T>::getvars(self, addr, mac, key, variant, vars).await
+    }
+
+    async fn setvars(&self, addr: IpAddr, mac: &str, key: &str, variant: ProtocolVariant, names: &[VarName], values: &[Value]) -> Result<CommandResponsePack> {
+        GreeClient::<T>::setvars(self, addr, mac, key, variant, names, values).await
+    }
+
+    async fn getvars_sub(&self, addr: IpAddr, mac: &str, sub: &str, key: &str, variant: ProtocolVariant, vars: &[&str]) -> Result<StatusResponsePack> {
+        GreeClient::<T>::getvars_sub(self, addr, mac, sub, key, variant, vars).await
+    }
+
+    async fn setvars_sub(&self, addr: IpAddr, mac: &str, sub: &str, key: &str, variant: ProtocolVariant, names: &[VarName], values: &[Value]) -> Result<CommandResponsePack> {
+        GreeClient::<T>::setvars_sub(self, addr, mac, sub, key, variant, names, values).await
+    }
+
+    async fn sub_list(&self, addr: IpAddr, mac: &str, key: &str, variant: ProtocolVariant) -> Result<Vec<SubDeviceInfo>> {
+        GreeClient::<T>::sub_list(self, addr, mac, key, variant).await
+    }
+
+    async fn device_info(&self, addr: IpAddr, mac: &str, key: &str, variant: ProtocolVariant) -> Result<DeviceInfoPack> {
+        GreeClient::<T>::device_info(self, addr, mac, key, variant).await
+    }
+
+    async fn exchange_pack(&self, addr: IpAddr, mac: &str, key: &str, variant: ProtocolVariant, pack: &Value) -> Result<Value> {
+        GreeClient::<T>::exchange_pack(self, addr, mac, key, variant, pack).await
+    }
+
+    fn net_stats(&self) -> HashMap<MacAddr, NetStats> {
+        self.stats()
+    }
+}
+
+
+struct GreeInternal<C: GreeClientApi> {
+    c: C,
     cfg: GreeConfig,
-    scan_ts: Option<Instant>,
+    /// Network state. Only locked for short periods, never across `.await`.
+    s: std::sync::Mutex<GreeState>,
+    /// Time of the last scan. Locked for the duration of a scan, so that concurrent callers do not scan simultaneously.
+    scan_ts: Mutex<Option<Instant>>,
+    /// Per-device locks, serializing the operations on each device
+    device_locks: std::sync::Mutex<HashMap<MacAddr, Arc<Mutex<()>>>>,
+    announcements: std::sync::Mutex<Option<Announcements>>,
+    subscribers: std::sync::Mutex<Vec<mpsc::UnboundedSender<GreeEvent>>>,
+    watches: std::sync::Mutex<HashMap<(MacAddr, VarName), watch::Sender<Value>>>,
 }
 
-impl GreeInternal {
-    pub async fn new(cfg: GreeConfig) -> Result<Self> { 
-        Ok(Self { 
-            c: GreeClient::new(cfg.client_config).await?,
-            s: GreeState::new(),
+/// Presence announcements received by a listener task, which is aborted when this is dropped
+struct Announcements {
+    r: mpsc::UnboundedReceiver<(IpAddr, GenericMessage)>,
+    task: JoinHandle<()>,
+}
+
+impl Drop for Announcements {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+impl<C: GreeClientApi> GreeInternal<C> {
+    pub fn new(cfg: GreeConfig, c: C) -> Self { 
+        let mut s = GreeState::new();
+        s.preset(&cfg.devices);
+        if let Some(store) = &cfg.store {
+            match store.load() {
+                Ok(bindings) => s.restore(bindings),
+                Err(e) => error!("store load: {e}"),
+            }
+        }
+        Self { 
+            c,
             cfg,
-            scan_ts: None,
-        })
+            s: std::sync::Mutex::new(s),
+            scan_ts: Mutex::new(None),
+            device_locks: Default::default(),
+            announcements: Default::default(),
+            subscribers: Default::default(),
+            watches: Default::default(),
+        }
+    }
+
+    /// Sends the events to the subscribers and updates the watches. Subscribers and watches which have dropped 
+    /// their receivers are removed.
+    fn emit(&self, events: Vec<GreeEvent>) {
+        if events.is_empty() { return }
+        let mut watches = lock(&self.watches);
+        for e in &events {
+            trace!("event: {e:?}");
+            if let GreeEvent::VarChanged { mac, name, new, .. } = e {
+                let k = (mac.clone(), *name);
+                if watches.get(&k).is_some_and(|w| w.send(new.clone()).is_err()) {
+                    watches.remove(&k);
+                }
+            }
+        }
+        lock(&self.subscribers).retain(|s| events.iter().all(|e| s.send(e.clone()).is_ok()));
+    }
+
+    /// Starts receiving presence announcements on a separate socket bound to `addr`
+    async fn listen(&self, addr: SocketAddr) -> Result<()> {
+        // the previous listener is stopped first, as it may be bound to the same address
+        lock(&self.announcements).take();
+        let s = UdpSocket::bind(addr).await?;
+        debug!("Listening for announcements at {:?}", s.local_addr());
+        let (send, r) = mpsc::unbounded_channel();
+        let (buffer_size, lenient) = (self.cfg.client_config.buffer_size, self.cfg.client_config.lenient);
+        let task = rt::spawn(async move {
+            let mut b = vec![0u8; buffer_size];
+            loop {
+                let (len, addr) = match s.recv_from(&mut b).await {
+                    Ok(la) => la,
+                    Err(e) => { error!("listen: {e}"); break }
+                };
+                match with_lenient(lenient, || serde_json::from_slice::<GenericMessage>(&b[..len])) {
+                    Ok(gm) => if send.send((addr.ip(), GenericMessage { lenient, ..gm })).is_err() { break },
+                    Err(e) => debug!("[{addr}] listen: {e}"),
+                }
+            }
+        });
+        *lock(&self.announcements) = Some(Announcements { r, task });
+        Ok(())
+    }
+
+    /// Merges the presence announcements received so far into the state
+    fn announcements_ind(&self) {
+        let mut announcements = lock(&self.announcements);
+        let Some(Announcements { r, .. }) = announcements.as_mut() else { return };
+        let mut events = vec![];
+        let mut moved = false;
+        let mut s = lock(&self.s);
+        while let Ok((ip, gm)) = r.try_recv() {
+            match handle_response::<ScanResponsePack>(ip, &gm, self.cfg.client_config.generic_key(gm.variant()), gm.variant()) {
+                Ok(pack) if pack.t == "dev" => {
+                    let diff = s.announce_ind(ip, &gm, pack);
+                    moved |= !diff.ip_changed.is_empty();
+                    events.extend(diff.events())
+                }
+                Ok(pack) => debug!("[{ip}] announcement ignored: {}", pack.t),
+                Err(e) => debug!("[{ip}] announcement: {e}"),
+            }
+        }
+        if self.cfg.name_aliases { s.update_aliases() }
+        drop(s);
+        drop(announcements);
+        // keep the stored IPs current, so that a restart does not address the old ones
+        if moved { self.persist() }
+        self.emit(events);
+    }
+
+    /// Resolves the target into a MAC address, see [GreeState::resolve]
+    fn resolve(&self, target: &str) -> MacAddr {
+        lock(&self.s).resolve(&self.cfg, target).to_owned()
+    }
+
+    /// Saves the bindings to the store, if any
+    fn persist(&self) {
+        if let Some(store) = &self.cfg.store {
+            // copied out, so that the state is not locked while saving
+            let bindings = lock(&self.s).bindings();
+            if let Err(e) = store.save(&bindings) { error!("store save: {e}") }
+        }
     }
 
-    async fn scan(&mut self, forced: bool) -> Result<()> {
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip(self), ret))]
+    async fn scan(&self, forced: bool) -> ScanOutcome {
+        self.announcements_ind();
+        let mut scan_ts = self.scan_ts.lock().await;
         let now = Instant::now();
 
-        let allow = match self.scan_ts {
+        let allow = match *scan_ts {
             None => true,
             Some(w) if now >= w + self.cfg.max_scan_age => true,
             Some(w) if now >= w + self.cfg.min_scan_age && forced => true,
             _ => false
         };
         if allow {
-            let result = self.c.scan().await?;
-            self.scan_ts = Some(Instant::now());
-            self.s.scan_ind(result);
-        } 
-        Ok(())
+            let result = match &self.cfg.discovery {
+                Discovery::Broadcast => self.c.scan().await,
+                Discovery::Unicast(ips) => self.c.scan_range(ips).await,
+            };
+            let result = match result {
+                Ok(result) => result,
+                Err(e) => return ScanOutcome::Failed(e.with_context(Operation::Scan, None, None)),
+            };
+            let (found, duration) = (result.len(), now.elapsed());
+            *scan_ts = Some(Instant::now());
+            drop(scan_ts);
+            let diff = {
+                let mut s = lock(&self.s);
+                let mut diff = s.scan_ind(result, self.cfg.max_missed_scans);
+                if let Some(max_age) = self.cfg.max_device_age { diff.removed.extend(s.evict_stale(max_age, Instant::now())) }
+                diff
+            };
+            if self.cfg.name_aliases { lock(&self.s).update_aliases() }
+            if !diff.is_empty() { debug!("scan: {diff:?}") }
+            self.persist();
+            self.emit(diff.events());
+            if self.cfg.eager_bind {
+                let binds = diff.added.iter().map(|(mac, _)| async move {
+                    if let Err(e) = self.apply(mac, &mut Op::<SimpleNetVar>::Bind).await { debug!("[{mac}] eager bind: {e}") }
+                });
+                join_all(binds).await;
+            }
+            ScanOutcome::Performed { found, duration, diff }
+        } else {
+            ScanOutcome::SkippedTooRecent
+        }
+    }
+
+    /// Scan performed on behalf of other operations; skipped if [GreeConfig::manual_discovery] is set
+    async fn implicit_scan(&self, forced: bool) -> ScanOutcome {
+        if self.cfg.manual_discovery { return ScanOutcome::SkippedManual }
+        let outcome = self.scan(forced).await;
+        match &outcome {
+            ScanOutcome::Performed { found, duration, .. } => debug!("implicit scan: {found} device(s) in {duration:?}"),
+            ScanOutcome::Failed(e) => debug!("implicit scan: {e}"),
+            _ => (),
+        }
+        outcome
     }
 
-    async fn bindc(mac: &str, dev: &mut Device, c: &GreeClient) -> Result<()> {
+    /// Lock serializing the operations on the device
+    fn device_lock(&self, mac: &str) -> Arc<Mutex<()>> {
+        lock(&self.device_locks).entry(mac.into()).or_default().clone()
+    }
+
+    /// Binds the device if not bound yet. The protocol variant is negotiated: if bind fails with the device's
+    /// current variant, it is retried with the other one.
+    async fn bindc(mac: &str, dev: &mut Device, c: &C) -> Result<()> {
         if dev.key.is_none() {
-            let pack = c.bind(dev.ip, mac).await?;
-            dev.bind_ind(pack);
+            let variant = dev.variant;
+            let (pack, variant) = match c.bind(dev.ip, mac, variant).await {
+                Ok(pack) => (pack, variant),
+                Err(e) => {
+                    debug!("[{mac}] bind with {variant:?} failed: {e}; retrying with {:?}", variant.other());
+                    (c.bind(dev.ip, mac, variant.other()).await?, variant.other())
+                }
+            };
+            dev.bind_ind(pack, variant);
         }
         Ok(())
     }
 
-    async fn net_read<T: NetVar>(mac: &str, dev: &Device, c: &GreeClient, vars: &mut NetVarBag<T>) -> Result<()> {
+    /// Reads the pending variables, `max_cols` at most per request (all at once, if 0)
+    async fn net_read<T: NetVar>(mac: &str, sub: Option<&str>, dev: &Device, c: &C, vars: &mut NetVarBag<T>, learned: &mut VarValues, max_cols: usize) -> Result<()> {
         let key = dev.key.as_ref().ok_or_else(|| Error::mac_not_bound(mac))?;
-        let names: Vec<VarName> = vars
-            .iter()
-            .filter_map(|(name, nv)| if nv.is_net_read_pending() { Some(*name) } else { None })
-            .collect();
+        let names: Vec<VarName> = vars.read_pending().collect();
         if names.is_empty() { return Ok(()) }
-        let pack = c.getvars(dev.ip, mac, key, &names).await?;
-        for (n, v) in pack.cols.into_iter().zip(pack.dat.into_iter()) { 
-            if let Some(nv) = vars::name_of(&n).and_then(|n| vars.get_mut(n)) {
-                nv.net_set(v);
+        let chunk = if max_cols == 0 { names.len() } else { max_cols };
+        for names in names.chunks(chunk) {
+            let pack = match sub {
+                Some(sub) => c.getvars_sub(dev.ip, mac, sub, key, dev.variant, names).await?,
+                None => c.getvars(dev.ip, mac, key, dev.variant, names).await?,
+            };
+            for (n, v) in pack.cols.into_iter().zip(pack.dat) { 
+                if let Some(n) = vars::name_of(&n) {
+                    learned.insert(n, v.clone());
+                    if let Some(nv) = vars.get_mut(n) {
+                        nv.net_set(v);
+                    }
+                }
             }
         }
         Ok(())
     }
 
-    async fn net_write<T: NetVar>(mac: &str, dev: &Device, c: &GreeClient, vars: &mut NetVarBag<T>) -> Result<()> {
+    async fn net_write<T: NetVar>(mac: &str, sub: Option<&str>, dev: &Device, c: &C, vars: &mut NetVarBag<T>, learned: &mut VarValues) -> Result<()> {
         let key = dev.key.as_ref().ok_or_else(|| Error::mac_not_bound(mac))?;
 
-        let mut names = vec![];
-        let mut values = vec![];
-        for (n, nv) in vars.iter() {
-            if nv.is_net_write_pending() {
-                names.push(*n);
-                values.push(nv.net_get().clone());
-            }
-        }
+        let (names, values): (Vec<VarName>, Vec<Value>) = vars.write_pending().map(|(n, v)| (n, v.clone())).unzip();
         if names.is_empty() { return Ok(()) }
-        let pack = c.setvars(dev.ip, mac, key, &names, &values).await?;
-        for (n, v) in pack.opt.into_iter().zip(pack.p.into_iter()) {
-            if let Some(nv) = vars::name_of(&n).and_then(|n| vars.get_mut(&n)) {
-                nv.clear_net_write_pending();
-                nv.net_set(v);
+        let pack = match sub {
+            Some(sub) => c.setvars_sub(dev.ip, mac, sub, key, dev.variant, &names, &values).await?,
+            None => c.setvars(dev.ip, mac, key, dev.variant, &names, &values).await?,
+        };
+        for (n, v) in pack.opt.into_iter().zip(pack.p) {
+            if let Some(n) = vars::name_of(&n) {
+                learned.insert(n, v.clone());
+                if let Some(nv) = vars.get_mut(n) {
+                    nv.clear_net_write_pending();
+                    nv.net_set(v);
+                }
             }
         }
         Ok(())
     }
 
-    async fn apply_dev<T: NetVar>(mac: &str, dev: &mut Device, c: &GreeClient, op: &mut Op<'_, T>) -> Result<()> {
-        Self::bindc(mac, dev, c).await?;
+    async fn net_raw(mac: &str, unit: &str, dev: &Device, c: &C, pack_type: &str, body: &mut Value) -> Result<()> {
+        let key = dev.key.as_ref().ok_or_else(|| Error::mac_not_bound(mac))?;
+        *body = c.exchange_pack(dev.ip, mac, key, dev.variant, &raw_pack(pack_type, unit, body)?).await?;
+        Ok(())
+    }
+
+    /// sets the device clock after a bind; failures are only logged, as the bind itself has succeeded
+    async fn sync_time_after_bind(mac: &str, dev: &Device, c: &C) {
+        let Some(key) = &dev.key else { return };
+        let time = vars::local_time();
+        match c.setvars(dev.ip, mac, key, dev.variant, &[vars::TIME], &[Value::String(time.clone())]).await {
+            Ok(_) => debug!("[{mac}] clock set to {time}"),
+            Err(e) => error!("[{mac}] setting clock: {e}"),
+        }
+    }
+
+    async fn apply_bound<T: NetVar>(mac: &str, sub: Option<&str>, dev: &mut Device, c: &C, op: &mut Op<'_, T>, learned: &mut VarValues, max_cols: usize) -> Result<()> {
+        let (unit, ip) = (sub.unwrap_or(mac), Some(dev.ip));
+        Self::bindc(mac, dev, c).await.map_err(|e| e.with_context(Operation::Bind, Some(mac), ip))?;
         match op {
             Op::Bind => Ok(()),
-            Op::NetRead(vars) => Self::net_read(mac, dev, c, *vars).await,
-            Op::NetWrite(vars) => Self::net_write(mac, dev, c, *vars).await
+            Op::NetRead(vars) => Self::net_read(mac, sub, dev, c, *vars, learned, max_cols).await
+                .map_err(|e| e.with_context(Operation::Get, Some(unit), ip)),
+            Op::NetWrite(vars) => Self::net_write(mac, sub, dev, c, *vars, learned).await
+                .map_err(|e| e.with_context(Operation::Set, Some(unit), ip)),
+            Op::Raw { pack_type, body } => Self::net_raw(mac, unit, dev, c, pack_type, body).await
+                .map_err(|e| e.with_context(Operation::Raw, Some(unit), ip)),
         }
     }
 
-    async fn apply<T: NetVar>(&mut self, target: &str, op: &mut Op<'_, T>) -> Result<()> {
-        let mac = self.cfg.aliases.get(target).map(|s| s.as_str()).unwrap_or(target);
-        let dev = self.s.devices.get_mut(mac).ok_or_else(||Error::not_found(target))?;
-        Self::apply_dev(mac, dev, &self.c, op).await
+    /// applies Op to the device, binding if needed; if the stored key is rejected, rebinds and retries once
+    async fn apply_dev<T: NetVar>(mac: &str, sub: Option<&str>, dev: &mut Device, c: &C, op: &mut Op<'_, T>, learned: &mut VarValues, max_cols: usize) -> Result<()> {
+        let was_bound = dev.key.is_some();
+        match Self::apply_bound(mac, sub, dev, c, op, learned, max_cols).await {
+            Err(e) if was_bound && e.is_key_failure() => {
+                debug!("[{mac}] key rejected ({e}), rebinding");
+                dev.key = None;
+                Self::apply_bound(mac, sub, dev, c, op, learned, max_cols).await
+            }
+            r => r
+        }
     }
 
-    /// applies Op to target; retries after forced scan on failure
-    async fn apply_retrying<T: NetVar>(&mut self, target: &str, mut op: Op<'_, T>) -> Result<()> {
-        let () = self.scan(false).await?;
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip_all, fields(op = op.name(), device = target), err))]
+    async fn apply<T: NetVar>(&self, target: &str, op: &mut Op<'_, T>) -> Result<()> {
+        self.announcements_ind();
+        let unit = self.resolve(target);
+        // sub-units of multi-split bridges are addressed through the bridge
+        let (mac, sub) = match lock(&self.s).parent_of(&unit) {
+            Some(parent) => (parent.clone(), Some(unit.clone())),
+            None => (unit.clone(), None),
+        };
+        let device_lock = self.device_lock(&mac);
+        let _serialized = device_lock.lock().await;
+        // work on a copy, so that the state is not locked during network I/O
+        let mut dev = {
+            let s = lock(&self.s);
+            let dev = s.devices.get(&mac).ok_or_else(|| Error::not_found(target))?;
+            if let Op::NetWrite(vars) = &mut *op {
+                self.cfg.set_tem_range(&unit).check(*vars)?;
+                if let Some(caps) = dev.capabilities.as_ref().filter(|_| sub.is_none()) { caps.check(&mac, *vars)? }
+                if let Some(eco) = s.eco_profiles.get(&unit) { eco.enforce(*vars) }
+            }
+            if let Op::NetRead(vars) = &mut *op {
+                s.skip_empty_reads(&unit, self.cfg.empty_read_threshold, *vars);
+            }
+            if !dev.may_retry(Instant::now()) {
+                return Err(Error::offline(&mac))
+            }
+            dev.clone()
+        };
+        let wait = dev.command_wait(Instant::now(), self.cfg.min_command_gap);
+        if !wait.is_zero() {
+            trace!("[{mac}] delayed by {wait:?}");
+            rt::sleep(wait).await;
+        }
+        let old_key = dev.key.clone();
+        let mut learned = VarValues::new();
+        let r = Self::apply_dev(&mac, sub.as_deref(), &mut dev, &self.c, op, &mut learned, self.cfg.max_read_cols).await;
+        let bound = dev.key.is_some();
+        let rebound = bound && dev.key != old_key;
+        if rebound && r.is_ok() && self.cfg.sync_time_on_bind {
+            Self::sync_time_after_bind(&mac, &dev, &self.c).await;
+        }
+        let mut events = {
+            let mut s = lock(&self.s);
+            // the device may have been evicted by a concurrent scan
+            let reachability = s.devices.get_mut(&mac).and_then(|d| {
+                d.key = dev.key;
+                d.variant = dev.variant;
+                d.last_command = Some(Instant::now());
+                match &r {
+                    Ok(_) => d.seen_ind(&mac, Instant::now()),
+                    Err(e) if e.is_timeout() => d.timeout_ind(&mac, &self.cfg, Instant::now()),
+                    Err(_) => None,
+                }
+            });
+            s.count_empty_reads(&unit, &learned, self.cfg.empty_read_threshold);
+            let mut events = s.learn(&unit, learned);
+            events.extend(reachability);
+            events
+        };
+        match &r {
+            Err(e) if !bound => events.push(GreeEvent::BindFailed { mac, error: e.to_string() }),
+            _ if rebound => self.persist(),
+            _ => (),
+        }
+        self.emit(events);
+        r
+    }
+
+    /// applies Op to target; retries after forced scan on failure. Provisioned devices are never scanned for.
+    async fn apply_retrying<T: NetVar>(&self, target: &str, mut op: Op<'_, T>) -> Result<()> {
+        if self.is_provisioned(target) {
+            return self.apply(target, &mut op).await
+        }
+        self.implicit_scan(false).await.into_result()?;
+        self.list_unknown_sub_device(target).await;
         let r = self.apply(target, &mut op).await;
-        if r.is_ok() { return r }
-        let () = self.scan(true).await?;        
+        if r.is_ok() || matches!(&r, Err(e) if matches!(e.root(), Error::Offline(_))) { return r }
+        self.implicit_scan(true).await.into_result()?;        
         self.apply(target, &mut op).await
     }
 
+    /// Records that a write to the target (every member, if a group) was requested, see [GreeState::last_write]
+    fn write_requested(&self, target: &str) {
+        let now = SystemTime::now();
+        let mut s = lock(&self.s);
+        for mac in self.units_of(&s, target) { s.last_write.insert(mac, now); }
+    }
+
+    /// MAC addresses of the target: of every member, if a group
+    fn units_of(&self, s: &GreeState, target: &str) -> Vec<MacAddr> {
+        match self.cfg.group_members(target) {
+            Some(members) => members.into_iter().map(|m| s.resolve(&self.cfg, m)).collect(),
+            None => vec![s.resolve(&self.cfg, target)],
+        }
+    }
+
+    /// Drops the keys of the target (every member, if a group; the bridge, if a sub-unit), persisting the bindings
+    fn forget_keys(&self, target: &str) {
+        {
+            let mut s = lock(&self.s);
+            for unit in self.units_of(&s, target) {
+                let mac = s.parent_of(&unit).cloned().unwrap_or(unit);
+                if let Some(dev) = s.devices.get_mut(&mac) { dev.key = None }
+            }
+        }
+        self.persist();
+    }
+
+    /// Lists the sub-units of the multi-split bridge, binding it if needed, and keeps them in the state
+    async fn list_sub_devices(&self, mac: &str) -> Result<Vec<SubDeviceInfo>> {
+        self.apply(mac, &mut Op::<SimpleNetVar>::Bind).await?;
+        let device_lock = self.device_lock(mac);
+        let _serialized = device_lock.lock().await;
+        let dev = self.with_device(mac, |dev| dev.clone())?;
+        let key = dev.key.as_ref().ok_or_else(|| Error::mac_not_bound(mac))?;
+        let subs = self.c.sub_list(dev.ip, mac, key, dev.variant).await?;
+        debug!("[{mac}] sub-units: {:?}", subs.iter().map(|sub| &sub.mac).collect::<Vec<_>>());
+        if let Some(dev) = lock(&self.s).devices.get_mut(mac) { dev.sub_devices = subs.clone() }
+        Ok(subs)
+    }
+
+    /// Queries the details of the device, binding it if needed
+    async fn device_info(&self, mac: &str) -> Result<DeviceInfoPack> {
+        self.apply(mac, &mut Op::<SimpleNetVar>::Bind).await?;
+        let device_lock = self.device_lock(mac);
+        let _serialized = device_lock.lock().await;
+        let dev = self.with_device(mac, |dev| dev.clone())?;
+        let key = dev.key.as_ref().ok_or_else(|| Error::mac_not_bound(mac))?;
+        self.c.device_info(dev.ip, mac, key, dev.variant).await
+    }
+
+    /// Reads `Pow` from the device (the bridge, if a sub-unit), binding it if needed, and records the round-trip 
+    /// time in [Device::ping]
+    async fn ping(&self, unit: &str) -> Result<Duration> {
+        let mac = lock(&self.s).parent_of(unit).cloned().unwrap_or_else(|| unit.into());
+        self.apply(&mac, &mut Op::<SimpleNetVar>::Bind).await?;
+        let device_lock = self.device_lock(&mac);
+        let _serialized = device_lock.lock().await;
+        let dev = self.with_device(&mac, |dev| dev.clone())?;
+        let key = dev.key.as_ref().ok_or_else(|| Error::mac_not_bound(&mac))?;
+        let started = Instant::now();
+        let r = self.c.getvars(dev.ip, &mac, key, dev.variant, &[vars::POW]).await.map(|_| started.elapsed());
+        let event = lock(&self.s).devices.get_mut(&mac).and_then(|d| {
+            d.ping.record(r.as_ref().ok().copied());
+            match &r {
+                Ok(_) => d.seen_ind(&mac, Instant::now()),
+                Err(e) if e.is_timeout() => d.timeout_ind(&mac, &self.cfg, Instant::now()),
+                Err(_) => None,
+            }
+        });
+        self.emit(event.into_iter().collect());
+        r
+    }
+
+    /// If the target is not a known device, lists the sub-units of the bridges not listed yet, in case it is one of 
+    /// them. Failures are only logged; the target is reported as not found then.
+    async fn list_unknown_sub_device(&self, target: &str) {
+        let unit = &self.resolve(target);
+        let bridges = {
+            let s = lock(&self.s);
+            if s.devices.contains_key(unit) || s.parent_of(unit).is_some() { return }
+            s.unlisted_bridges()
+        };
+        for mac in bridges {
+            if let Err(e) = self.list_sub_devices(&mac).await { error!("[{mac}] listing sub-units: {e}") }
+        }
+    }
+
+    fn is_provisioned(&self, target: &str) -> bool {
+        let s = lock(&self.s);
+        s.is_provisioned(&s.resolve(&self.cfg, target))
+    }
+
     fn with_device<R>(&self, target: &str, f: impl FnOnce(&Device) -> R) -> Result<R> {
-        let mac = self.cfg.aliases.get(target).map(|s| s.as_str()).unwrap_or(target);
-        let dev = self.s.devices.get(mac).ok_or_else(||Error::not_found(target))?;
+        let s = lock(&self.s);
+        let dev = s.devices.get(&s.resolve(&self.cfg, target)).ok_or_else(||Error::not_found(target))?;
         Ok(f(dev))    
     }
 
     /// applies f to the target's state; retries after forced scan on failure (i.e. if device not found)
-    async fn with_device_retrying<R>(&mut self, target: &str, f: impl Fn(&Device) -> R) -> Result<R> {
-        let () = self.scan(false).await?;
+    async fn with_device_retrying<R>(&self, target: &str, f: impl Fn(&Device) -> R) -> Result<R> {
+        if self.is_provisioned(target) {
+            return self.with_device(target, &f)
+        }
+        self.implicit_scan(false).await.into_result()?;
         let r = self.with_device(target, &f);
         if r.is_ok() { return r }
-        let () = self.scan(true).await?;        
+        self.implicit_scan(true).await.into_result()?;        
         self.with_device(target, &f)
     }
 
@@ -232,56 +985,605 @@ impl GreeInternal {
 /// 
 /// It maintains consistent network state through periodically re-scanning the network. See the crate level documentation 
 /// for the explanation of the re-scanning rules.
-pub struct Gree {
-    g: GreeInternal,
+/// 
+/// All the methods take `&self`, so `Gree` may be shared between tasks (e.g. in an `Arc`). Operations on the same 
+/// device are serialized, while operations on different devices may run concurrently. 
+pub struct Gree<C: GreeClientApi = GreeClient> {
+    g: GreeInternal<C>,
 }
 
 impl Gree {
-
     /// Creates a new Gree client from configuration
     pub async fn new(cfg: GreeConfig) -> Result<Self> { 
-        Ok(Self { g: GreeInternal::new(cfg).await? })
+        let c = GreeClient::new(cfg.client_config.clone()).await?;
+        let listen_addr = cfg.listen_addr;
+        let gree = Self::with_client(cfg, c);
+        if let Some(addr) = listen_addr { gree.listen(addr).await? }
+        Ok(gree)
+    }
+}
+
+impl<C: GreeClientApi> Gree<C> {
+    /// Creates a new Gree client from configuration and a low-level client. `client_config` of the configuration is not used.
+    pub fn with_client(cfg: GreeConfig, c: C) -> Self { 
+        Self { g: GreeInternal::new(cfg, c) }
     }
 
     /// Calls `f` with the current state
-    pub async fn with_state<R>(&mut self, f: impl Fn(&GreeState) -> R) -> Result<R> {
-        self.g.scan(false).await?;
-        Ok(f(&self.g.s))
+    pub async fn with_state<R>(&self, f: impl Fn(&GreeState) -> R) -> Result<R> {
+        self.g.implicit_scan(false).await.into_result()?;
+        Ok(f(&lock(&self.g.s)))
     }
 
     /// Calls `f` with the device specified as `target`
     /// 
     /// Performs forced scan if the device was not found.
-    pub async fn with_device<R>(&mut self, target: &String, f: impl Fn(&Device) -> R) -> Result<R> {
+    pub async fn with_device<R>(&self, target: &str, f: impl Fn(&Device) -> R) -> Result<R> {
         self.g.with_device_retrying(target, f).await
     }
 
     /// Reads pending variables from the network
-    pub async fn net_read<T: NetVar>(&mut self, target: &str, vars: &mut NetVarBag<T>) -> Result<()> { 
-        self.g.apply_retrying(target, Op::NetRead(vars)).await 
+    pub async fn net_read<T: NetVar>(&self, target: &str, vars: &mut NetVarBag<T>) -> Result<()> { 
+        self.execute(target, Op::NetRead(vars)).await 
+    }
+
+    /// Reads pending variables, serving those whose last known values (see [Gree::cached_value]) are younger than 
+    /// `max_age` from the cache and only reading the rest from the network. Does not access the network if all the 
+    /// values are fresh.
+    pub async fn net_read_cached<T: NetVar>(&self, target: &str, vars: &mut NetVarBag<T>, max_age: Duration) -> Result<()> {
+        let mac = self.g.resolve(target);
+        lock(&self.g.s).read_cached(&mac, vars, max_age);
+        if vars.read_pending().next().is_none() { return Ok(()) }
+        self.net_read(target, vars).await
     }
 
     /// Writes pending variables to the network, and fills the netvar bag with the values returned from the network
-    pub async fn net_write<T: NetVar>(&mut self, target: &str, vars: &mut NetVarBag<T>)  -> Result<()> {
-        self.g.apply_retrying(target, Op::NetWrite(vars)).await
+    /// 
+    /// If the target is a group (see [GreeConfig::groups]), the variables are written to every member concurrently; 
+    /// the bag is not filled then.
+    pub async fn net_write<T: NetVar>(&self, target: &str, vars: &mut NetVarBag<T>)  -> Result<()> {
+        self.execute(target, Op::NetWrite(vars)).await
+    }
+
+    /// Reads pending variables from several targets concurrently. Returns the result for each target, in order.
+    pub async fn net_read_many<'t, T: NetVar + 't>(&self, reqs: impl IntoIterator<Item = (&'t str, &'t mut NetVarBag<T>)>) -> Vec<Result<()>> {
+        join_all(reqs.into_iter().map(|(target, vars)| self.net_read(target, vars))).await
+    }
+
+    /// Writes pending variables to several targets concurrently. Returns the result for each target, in order.
+    pub async fn net_write_many<'t, T: NetVar + 't>(&self, reqs: impl IntoIterator<Item = (&'t str, &'t mut NetVarBag<T>)>) -> Vec<Result<()>> {
+        join_all(reqs.into_iter().map(|(target, vars)| self.net_write(target, vars))).await
+    }
+
+    /// Executes the operation specified. Binds and writes to a group are performed on every member concurrently, 
+    /// failing with `Error::Group` if any of the members fails; groups can not be read.
+    pub async fn execute<T: NetVar>(&self, target: &str, op: Op<'_, T>)  -> Result<()> {
+        if let Op::NetWrite(_) = &op { self.g.write_requested(target) }
+        let Some(members) = self.g.cfg.group_members(target) else { return self.g.apply_retrying(target, op).await };
+        match op {
+            Op::Bind => {
+                let results = join_all(members.iter().map(|m| self.g.apply_retrying(m, Op::<SimpleNetVar>::Bind))).await;
+                group_result(members.into_iter().zip(results))
+            }
+            Op::NetWrite(vars) => {
+                let mut bags: Vec<_> = members.iter().map(|_| net_var_bag_of_writes(vars)).collect();
+                let results = join_all(members.iter().zip(bags.iter_mut()).map(|(m, bag)| self.g.apply_retrying(m, Op::NetWrite(bag)))).await;
+                let r = group_result(members.into_iter().zip(results));
+                if r.is_ok() { vars.values_mut().for_each(|v| v.clear_net_write_pending()) }
+                r
+            }
+            Op::NetRead(_) => Err(Error::Config(format!("`{target}` is a group, which can not be read"))),
+            Op::Raw { .. } => Err(Error::Config(format!("`{target}` is a group, raw packs are sent to single devices only"))),
+        }
     }
 
-    /// Executes the operation specified
-    pub async fn execute<T: NetVar>(&mut self, target: &str, op: Op<'_, T>)  -> Result<()> {
-        self.g.apply_retrying(target, op).await
+    /// Spawns a background task reading `poll_vars` (see [GreeConfig::poll_vars]) of every known device each `interval`, 
+    /// which keeps the last known values (see [Gree::last_known_values]) up to date. Writes queued with 
+    /// [Gree::queue_set] are flushed on each tick as well. If [GreeConfig::history] is set, `history_vars` are read 
+    /// too and recorded. The policies set in [GreeConfig::policies] are evaluated for every device after it is polled.
+    /// 
+    /// The instance may be used by other tasks concurrently. Abort the task returned to stop polling.
+    pub fn spawn_poller(gree: Arc<Self>, interval: Duration) -> JoinHandle<()> where C: 'static {
+        rt::spawn(async move {
+            let started = SystemTime::now();
+            let mut fired = HashMap::new();
+            let mut ticker = rt::Interval::new(interval);
+            loop {
+                ticker.tick().await;
+                if let Err(e) = gree.flush_all().await { debug!("poll: flush: {e}") }
+                let cfg = &gree.g.cfg;
+                let history_vars = if cfg.history.is_some() { &cfg.history_vars[..] } else { &[] };
+                let names: Vec<VarName> = cfg.poll_vars.iter().chain(history_vars).copied().collect();
+                for (mac, r) in gree.poll_all(&names).await {
+                    let bag = match r {
+                        Ok(bag) => bag,
+                        Err(e) => { debug!("[{mac}] poll: {e}"); continue }
+                    };
+                    if let Some(history) = &cfg.history {
+                        let samples = history_samples(&mac, history_vars, &bag);
+                        if let Err(e) = history.record(&samples) { error!("[{mac}] history: {e}") }
+                    }
+                    if !cfg.policies.is_empty() { gree.run_policies(&mac, started, &mut fired).await }
+                }
+            }
+        })
+    }
+
+    /// Reads the variables from every device known, scanning first if due. The devices are read concurrently, 
+    /// rather than one after another. Returns the variables read, or the error, by device.
+    pub async fn poll_all(&self, names: &[VarName]) -> HashMap<MacAddr, Result<NetVarBag<SimpleNetVar>>> {
+        if let ScanOutcome::Failed(e) = self.g.implicit_scan(false).await { error!("poll: scan: {e}") }
+        let macs: Vec<MacAddr> = lock(&self.g.s).devices.keys().cloned().collect();
+        let reads = macs.iter().map(|mac| async move {
+            let mut bag: NetVarBag<SimpleNetVar> = names.iter().map(|n| (*n, SimpleNetVar::new())).collect();
+            self.net_read(mac, &mut bag).await.map(|()| bag)
+        });
+        macs.iter().cloned().zip(join_all(reads).await).collect()
+    }
+
+    /// Evaluates the policies for the device, writing what they return. A policy that fired is not evaluated again 
+    /// until a write is requested; `fired` keeps the time of the last requested write each policy fired after.
+    async fn run_policies(&self, mac: &MacAddr, started: SystemTime, fired: &mut HashMap<(MacAddr, usize), Option<SystemTime>>) {
+        let cfg = &self.g.cfg;
+        let (values, last_write) = {
+            let s = lock(&self.g.s);
+            (s.last_known_values(mac), s.last_write.get(mac).copied())
+        };
+        let ctx = PolicyContext {
+            mac,
+            values: &values,
+            idle: last_write.unwrap_or(started).elapsed().unwrap_or_default(),
+            set_tem_range: cfg.set_tem_range(mac),
+        };
+        for (i, policy) in cfg.policies.iter().enumerate() {
+            if fired.get(&(mac.clone(), i)) == Some(&last_write) { continue }
+            let Some(scene) = policy.evaluate(&ctx) else { continue };
+            debug!("[{mac}] policy {policy:?} fired: {:?}", scene.values);
+            // written directly, so that it does not count as a requested write
+            match self.g.apply_retrying(mac, Op::NetWrite(&mut scene.to_net_var_bag())).await {
+                Ok(()) => { fired.insert((mac.clone(), i), last_write); }
+                Err(e) => error!("[{mac}] policy: {e}"),
+            }
+        }
     }
 
-    /// Performs explicit scan
-    pub async fn scan(&mut self) -> Result<()> { 
+    /// Last known values of the target's variables, as learned from reads, writes and polling. Does not access the network.
+    pub fn last_known_values(&self, target: &str) -> VarValues {
+        let s = lock(&self.g.s);
+        s.last_known_values(&s.resolve(&self.g.cfg, target))
+    }
+
+    /// Last known value of a variable of the target, with the time it was learned. Does not access the network.
+    pub fn cached_value(&self, target: &str, name: VarName) -> Option<VarValue> {
+        let s = lock(&self.g.s);
+        s.values.get(&s.resolve(&self.g.cfg, target))?.get(name).cloned()
+    }
+
+    /// Subscribes to state change events: devices discovered or lost during scans, variable values changed 
+    /// (as learned from reads and writes) and bind failures.
+    /// 
+    /// Events are only produced while `Gree` is in use; they are not a substitute for reading the devices.
+    pub fn subscribe(&self) -> mpsc::UnboundedReceiver<GreeEvent> {
+        let (tx, rx) = mpsc::unbounded_channel();
+        lock(&self.g.subscribers).push(tx);
+        rx
+    }
+
+    /// Watches a variable of the target. The receiver is updated whenever a new value is learned from the network,
+    /// be it from reads, writes or background polling (see [Gree::spawn_poller]). 
+    /// 
+    /// The initial value is the last known one, or `Value::Null` if none is known.
+    pub fn watch(&self, target: &str, name: VarName) -> watch::Receiver<Value> {
+        let mac = self.g.resolve(target);
+        let initial = lock(&self.g.s).values.get(&mac).and_then(|v| v.get(name)).map(|v| v.value.clone()).unwrap_or(Value::Null);
+        lock(&self.g.watches).entry((mac, name)).or_insert_with(|| watch::channel(initial).0).subscribe()
+    }
+
+    /// Starts the passive discovery listener: presence announcements (scan responses sent by the devices spontaneously) 
+    /// received on `addr` are merged into the state, so that new devices appear without waiting for the next scan. 
+    /// Devices typically announce themselves to the Gree port (7000).
+    /// 
+    /// Started by `Gree::new` if [GreeConfig::listen_addr] is set.
+    pub async fn listen(&self, addr: SocketAddr) -> Result<()> {
+        self.g.listen(addr).await
+    }
+
+    /// Performs explicit scan. Returns whether the scan was performed (it is bypassed if younger than `min_scan_age`), 
+    /// and if so, the changes it has made to the devices known. See [ScanOutcome::into_result].
+    pub async fn scan(&self) -> ScanOutcome { 
         self.g.scan(true).await 
     }
 
+    /// Reads the typed state of the air conditioner in one request
+    pub async fn read_state(&self, target: &str) -> Result<AcState> {
+        let mut bag = net_var_bag_for_ac_state();
+        self.net_read(target, &mut bag).await?;
+        AcState::from_net_var_bag(&bag)
+    }
+
+    /// Writes the changes to the state of the air conditioner in one command. A fan level is checked against the mode 
+    /// of operation (the one being set, or the current one), as in [Gree::set_fan_level].
+    pub async fn apply(&self, target: &str, delta: AcStateDelta) -> Result<()> {
+        if delta.is_empty() { return Ok(()) }
+        if let Some(level) = delta.fan {
+            match delta.mode {
+                Some(mode) => level.check_mode(&mode.into())?,
+                None => {
+                    let mut bag: NetVarBag<SimpleNetVar> = [(vars::MOD, SimpleNetVar::new())].into_iter().collect();
+                    self.net_read(target, &mut bag).await?;
+                    level.check_mode(bag[vars::MOD].user_get())?;
+                }
+            }
+        }
+        self.net_write(target, &mut delta.to_net_var_bag()).await
+    }
+
+    /// Reads all the variables of the target (see [DeviceSnapshot::vars]) in one request
+    pub async fn snapshot(&self, target: &str) -> Result<DeviceSnapshot> {
+        let mut bag: NetVarBag<SimpleNetVar> = DeviceSnapshot::vars().map(|n| (n, SimpleNetVar::new())).collect();
+        self.net_read(target, &mut bag).await?;
+        let taken_at = SystemTime::now();
+        let (mac, ip) = self.with_device(target, |dev| (MacAddr::from(&dev.scan_result.mac), dev.ip)).await?;
+        Ok(DeviceSnapshot { mac, ip, taken_at, values: bag.to_json() })
+    }
+
+    /// Probes the variables the device supports (see [Capabilities]) and records them in [Device::capabilities], so 
+    /// that subsequent writes to unsupported variables are refused
+    pub async fn probe_capabilities(&self, target: &str) -> Result<Capabilities> {
+        let mut bag: NetVarBag<SimpleNetVar> = DeviceSnapshot::vars().map(|n| (n, SimpleNetVar::new())).collect();
+        self.net_read(target, &mut bag).await?;
+        let caps = Capabilities::from_net_var_bag(&bag);
+        let mac = self.g.resolve(target);
+        if let Some(dev) = lock(&self.g.s).devices.get_mut(&mac) {
+            dev.capabilities = Some(caps.clone());
+        }
+        Ok(caps)
+    }
+
+    /// Writes the scene named (see [GreeConfig::scenes]) to the target in one command. The target may be a group.
+    pub async fn apply_scene(&self, target: &str, scene: &str) -> Result<()> {
+        let scene = self.g.cfg.scenes.get(scene).ok_or_else(|| Error::not_found(scene))?;
+        self.net_write(target, &mut scene.to_net_var_bag()).await
+    }
+
+    /// Sets the target's clock to the host's local time. The target may be a group. See also 
+    /// [GreeConfig::sync_time_on_bind].
+    pub async fn sync_time(&self, target: &str) -> Result<()> {
+        let mut bag: NetVarBag<SimpleNetVar> = [(vars::TIME, SimpleNetVar::from_value(Value::String(vars::local_time())))].into_iter().collect();
+        self.net_write(target, &mut bag).await
+    }
+
+    /// Fluent facade for the target (MAC address or alias), see [DeviceHandle]. Does not access the network.
+    pub fn device(&self, target: &str) -> DeviceHandle<'_, C> {
+        DeviceHandle { gree: self, target: target.to_owned() }
+    }
+
     /// Performs explicit bind
     /// 
     /// Note that this method is rarely needed, as binds are usually performed under-the-hood when necessary.
-    pub async fn bind(&mut self, target: &String) -> Result<()> { 
-        self.g.apply_retrying(target, Op::<SimpleNetVar>::Bind).await 
+    pub async fn bind(&self, target: &str) -> Result<()> { 
+        self.execute(target, Op::<SimpleNetVar>::Bind).await 
+    }
+
+    /// Drops the key of the target (every member, if a group) and binds it afresh, e.g. after the device was
+    /// factory-reset. If the bind fails, the target is left unbound.
+    pub async fn rebind(&self, target: &str) -> Result<()> {
+        self.g.forget_keys(target);
+        self.bind(target).await
+    }
+
+    /// Queries the device details, including the firmware and hardware ids not carried by the scan response
+    pub async fn device_info(&self, target: &str) -> Result<DeviceInfoPack> {
+        if !self.g.is_provisioned(target) { self.g.implicit_scan(false).await.into_result()?; }
+        self.g.device_info(&self.g.resolve(target)).await
+    }
+
+    /// Forgets the target (MAC address or alias) along with its key, last known values and pending writes, e.g. after 
+    /// the device was decommissioned. [GreeEvent::DeviceRemoved] is emitted. The device is added back if it answers a 
+    /// scan later; provisioned devices are added back on restart.
+    pub fn remove_device(&self, target: &str) -> Result<()> {
+        let mac = self.g.resolve(target);
+        lock(&self.g.s).remove(&mac).ok_or_else(|| Error::not_found(target))?;
+        lock(&self.g.device_locks).remove(&mac);
+        self.g.persist();
+        self.g.emit(vec![GreeEvent::DeviceRemoved { mac }]);
+        Ok(())
+    }
+
+    /// Image of the state (devices, keys, capabilities and last known values), for warm-starting with 
+    /// [Gree::restore_state_snapshot] after a restart. It serializes with the keys.
+    pub fn state_snapshot(&self) -> GreeStateSnapshot {
+        lock(&self.g.s).to_snapshot()
+    }
+
+    /// Replaces the state with the snapshot taken by [Gree::state_snapshot]. The devices are used right away, without the 
+    /// initial scan; they are verified by the operations on them and by the scans due after `max_scan_age`. The 
+    /// devices provisioned in [GreeConfig::devices] are added if missing, and the bindings persisted.
+    pub async fn restore_state_snapshot(&self, snapshot: GreeStateSnapshot) {
+        {
+            let mut s = lock(&self.g.s);
+            *s = GreeState::from_snapshot(snapshot);
+            s.preset(&self.g.cfg.devices);
+            if self.g.cfg.name_aliases { s.update_aliases() }
+        }
+        *self.g.scan_ts.lock().await = Some(Instant::now());
+        self.g.persist();
+    }
+
+    /// Bindings (keys) of the bound devices, sorted by MAC address, e.g. for moving them to another host with 
+    /// [Gree::import_bindings]. They serialize to JSON.
+    pub fn export_bindings(&self) -> Vec<DeviceBinding> {
+        let mut bindings: Vec<_> = lock(&self.g.s).bindings().into_values().collect();
+        bindings.sort_by(|a, b| a.mac.cmp(&b.mac));
+        bindings
+    }
+
+    /// Imports bindings exported by [Gree::export_bindings] or obtained by other tools, so that the devices need not be 
+    /// rebound. The keys of the devices known are replaced. The bindings are persisted, if [GreeConfig::store] is set.
+    pub fn import_bindings(&self, bindings: Vec<DeviceBinding>) -> Result<()> {
+        lock(&self.g.s).import(bindings)?;
+        self.g.persist();
+        Ok(())
+    }
+
+    /// Statistics of the devices known, by MAC address: their reachability, the counters of the exchanges with them 
+    /// (if the low-level client keeps them, see [GreeClientApi::net_stats]) and their [PingStats]. The report 
+    /// serializes to JSON, e.g. for exposing it to operators.
+    pub fn stats(&self) -> std::collections::BTreeMap<MacAddr, DeviceStats> {
+        let net = self.g.c.net_stats();
+        lock(&self.g.s).stats(&net)
+    }
+
+    /// Checks that the target answers, reading a single variable, and returns the round-trip time of the read (the 
+    /// retries included, see [GreeClientConfig::retry]). The time is also recorded in the [PingStats] of the device 
+    /// (of the bridge, for a sub-unit), and the device is marked as seen or as having missed a request, like in the 
+    /// other operations.
+    pub async fn ping(&self, target: &str) -> Result<Duration> {
+        if !self.g.is_provisioned(target) { self.g.implicit_scan(false).await.into_result()?; }
+        self.g.ping(&self.g.resolve(target)).await
+    }
+
+    /// Lists the sub-units of the multi-split bridge (see [ScanResponsePack::sub_cnt]). The units may be used as targets 
+    /// by their MAC addresses; the requests are routed through the bridge. Bridges are listed on demand when an unknown
+    /// target is used, so this method is only needed to discover the units.
+    pub async fn sub_devices(&self, target: &str) -> Result<Vec<SubDeviceInfo>> {
+        if !self.g.is_provisioned(target) { self.g.implicit_scan(false).await.into_result()?; }
+        self.g.list_sub_devices(&self.g.resolve(target)).await
+    }
+
+    /// Reads a single variable as a typed value, e.g. [vars::Mod]
+    pub async fn get<V: TryFrom<Value, Error = Error>>(&self, target: &str, name: VarName) -> Result<V> {
+        let mut bag: NetVarBag<SimpleNetVar> = [(name, SimpleNetVar::new())].into_iter().collect();
+        self.net_read(target, &mut bag).await?;
+        V::try_from(bag[name].user_get().clone())
+    }
+
+    /// Writes a single variable from a typed value, e.g. `vars::Mod::Cool`
+    pub async fn set(&self, target: &str, name: VarName, value: impl Into<Value>) -> Result<()> {
+        let mut bag: NetVarBag<SimpleNetVar> = [(name, SimpleNetVar::from_value(value.into()))].into_iter().collect();
+        self.net_write(target, &mut bag).await
+    }
+
+    /// Queues a write of a single variable, without accessing the network. Queued writes are sent by [Gree::flush], 
+    /// writes to the same variable being coalesced, so that only the last value is sent.
+    pub fn queue_set(&self, target: &str, name: VarName, value: impl Into<Value>) {
+        let mac = self.g.resolve(target);
+        lock(&self.g.s).queue_write(&mac, name, value.into());
+    }
+
+    /// Sends the writes queued for the target (see [Gree::queue_set]) in a single command. Writes which fail are 
+    /// re-queued.
+    pub async fn flush(&self, target: &str) -> Result<()> {
+        let mac = self.g.resolve(target);
+        let Some(writes) = lock(&self.g.s).pending.remove(&mac) else { return Ok(()) };
+        let mut bag: NetVarBag<SimpleNetVar> = writes.iter().map(|(n, v)| (*n, SimpleNetVar::from_value(v.clone()))).collect();
+        let r = self.net_write(&mac, &mut bag).await;
+        if r.is_err() { lock(&self.g.s).requeue_writes(&mac, writes) }
+        r
+    }
+
+    /// Sends the writes queued for all the devices (see [Gree::queue_set]). Returns the last error, if any, after 
+    /// trying all the devices.
+    pub async fn flush_all(&self) -> Result<()> {
+        let macs: Vec<MacAddr> = lock(&self.g.s).pending.keys().cloned().collect();
+        let mut r = Ok(());
+        for mac in macs {
+            if let Err(e) = self.flush(&mac).await {
+                debug!("[{mac}] flush: {e}");
+                r = Err(e);
+            }
+        }
+        r
     }
 
+    /// Applies the eco profile to the target: turns `SvSt` on, reduces the fan and clamps `SetTem`.
+    /// 
+    /// While the profile is active, all `SetTem` writes to the target are clamped into the profile's range. If the 
+    /// target is a group, the profile is applied to every member concurrently.
+    pub async fn apply_eco_profile(&self, target: &str, profile: EcoProfile) -> Result<()> {
+        let Some(members) = self.g.cfg.group_members(target) else { return self.apply_eco_profile_to(target, profile).await };
+        let results = join_all(members.iter().map(|m| self.apply_eco_profile_to(m, profile.clone()))).await;
+        group_result(members.into_iter().zip(results))
+    }
+
+    async fn apply_eco_profile_to(&self, target: &str, profile: EcoProfile) -> Result<()> {
+        let mut bag: NetVarBag<SimpleNetVar> = [(vars::MOD, SimpleNetVar::new()), (vars::SET_TEM, SimpleNetVar::new())].into_iter().collect();
+        self.net_read(target, &mut bag).await?;
+        profile.fan_level.check_mode(bag[vars::MOD].user_get())?;
+        self.net_write(target, &mut profile.to_net_var_bag(bag[vars::SET_TEM].user_get())).await?;
+        let mac = self.g.resolve(target);
+        lock(&self.g.s).eco_profiles.insert(mac, profile);
+        Ok(())
+    }
+
+    /// Removes the eco profile from the target (or every member of the group), turning `SvSt` off. Fan level and 
+    /// `SetTem` are left as is.
+    pub async fn remove_eco_profile(&self, target: &str) -> Result<()> {
+        let Some(members) = self.g.cfg.group_members(target) else { return self.remove_eco_profile_from(target).await };
+        let results = join_all(members.iter().map(|m| self.remove_eco_profile_from(m))).await;
+        group_result(members.into_iter().zip(results))
+    }
+
+    async fn remove_eco_profile_from(&self, target: &str) -> Result<()> {
+        let mut bag: NetVarBag<SimpleNetVar> = [(vars::SV_ST, SimpleNetVar::from_value(vars::SvSt::Off.into()))].into_iter().collect();
+        self.net_write(target, &mut bag).await?;
+        let mac = self.g.resolve(target);
+        lock(&self.g.s).eco_profiles.remove(&mac);
+        Ok(())
+    }
+
+    /// Reads the fan level, decoded from `WdSpd`, `Tur` and `Quiet`
+    pub async fn get_fan_level(&self, target: &str) -> Result<vars::FanLevel> {
+        let mut bag = net_var_bag_for_fan_level();
+        self.net_read(target, &mut bag).await?;
+        net_var_bag_to_fan_level(&bag)
+    }
+
+    /// Sets the fan level, checking it against the current mode of operation and writing `WdSpd`, `Tur` and `Quiet` at once
+    pub async fn set_fan_level(&self, target: &str, level: vars::FanLevel) -> Result<()> {
+        let mut bag: NetVarBag<SimpleNetVar> = [(vars::MOD, SimpleNetVar::new())].into_iter().collect();
+        self.net_read(target, &mut bag).await?;
+        level.check_mode(bag[vars::MOD].user_get())?;
+        self.net_write(target, &mut net_var_bag_from_fan_level(level)).await
+    }
+
+    /// Reads the set temperature, decoded from `SetTem`, `TemRec` and `TemUn`
+    pub async fn get_temperature(&self, target: &str) -> Result<vars::Temperature> {
+        let mut bag = net_var_bag_for_temperature();
+        self.net_read(target, &mut bag).await?;
+        net_var_bag_to_temperature(&bag)
+    }
+
+    /// Sets the temperature, writing `SetTem`, `TemRec` and `TemUn` at once. The unit shown by the device is switched 
+    /// to the one of the temperature.
+    pub async fn set_temperature(&self, target: &str, t: impl Into<vars::Temperature>) -> Result<()> {
+        self.net_write(target, &mut net_var_bag_from_temperature(t.into())).await
+    }
+
+}
+
+/// Fluent facade for a single device, see [Gree::device]
+/// 
+/// Builds the netvar bags for the common operations, e.g. `gree.device("living").set_mode(vars::Mode::Cool).await?`.
+pub struct DeviceHandle<'g, C: GreeClientApi = GreeClient> {
+    gree: &'g Gree<C>,
+    target: String,
 }
 
+impl<'g, C: GreeClientApi> DeviceHandle<'g, C> {
+    /// The target (MAC address or alias) the handle was created for
+    pub fn target(&self) -> &str {
+        &self.target
+    }
+
+    /// Turns the device on
+    pub async fn power_on(&self) -> Result<()> {
+        self.gree.set(&self.target, vars::POW, vars::OnOff::On).await
+    }
+
+    /// Turns the device off
+    pub async fn power_off(&self) -> Result<()> {
+        self.gree.set(&self.target, vars::POW, vars::OnOff::Off).await
+    }
+
+    /// Sets the mode of operation
+    pub async fn set_mode(&self, mode: vars::Mode) -> Result<()> {
+        self.gree.set(&self.target, vars::MOD, mode).await
+    }
+
+    /// Sets the temperature, e.g. `vars::Celsius(22)` or `vars::Temperature::Fahrenheit(72.0)`, see [Gree::set_temperature]
+    pub async fn set_temperature(&self, t: impl Into<vars::Temperature>) -> Result<()> {
+        self.gree.set_temperature(&self.target, t).await
+    }
+
+    /// Reads the set temperature, see [Gree::get_temperature]
+    pub async fn temperature(&self) -> Result<vars::Temperature> {
+        self.gree.get_temperature(&self.target).await
+    }
+
+    /// Sets the fan level, see [Gree::set_fan_level]
+    pub async fn set_fan_level(&self, level: vars::FanLevel) -> Result<()> {
+        self.gree.set_fan_level(&self.target, level).await
+    }
+
+    /// Reads a single variable as a typed value, see [Gree::get]
+    pub async fn get<V: TryFrom<Value, Error = Error>>(&self, name: VarName) -> Result<V> {
+        self.gree.get(&self.target, name).await
+    }
+
+    /// Writes a single variable, see [Gree::set]
+    pub async fn set(&self, name: VarName, value: impl Into<Value>) -> Result<()> {
+        self.gree.set(&self.target, name, value).await
+    }
+
+    /// Reads the typed state, see [Gree::read_state]
+    pub async fn state(&self) -> Result<AcState> {
+        self.gree.read_state(&self.target).await
+    }
+
+    /// Writes the changes to the state, see [Gree::apply]
+    pub async fn apply(&self, delta: AcStateDelta) -> Result<()> {
+        self.gree.apply(&self.target, delta).await
+    }
+
+    /// Reads all the variables, see [Gree::snapshot]
+    pub async fn snapshot(&self) -> Result<DeviceSnapshot> {
+        self.gree.snapshot(&self.target).await
+    }
+
+    /// Probes the variables the device supports, see [Gree::probe_capabilities]
+    pub async fn probe_capabilities(&self) -> Result<Capabilities> {
+        self.gree.probe_capabilities(&self.target).await
+    }
+
+    /// The variables the device supports, if probed (see [Device::capabilities])
+    pub async fn capabilities(&self) -> Result<Option<Capabilities>> {
+        self.gree.with_device(&self.target, |dev| dev.capabilities.clone()).await
+    }
+
+    /// Writes the scene named, see [Gree::apply_scene]
+    pub async fn apply_scene(&self, scene: &str) -> Result<()> {
+        self.gree.apply_scene(&self.target, scene).await
+    }
+
+    /// Sets the clock to the host's local time, see [Gree::sync_time]
+    pub async fn sync_time(&self) -> Result<()> {
+        self.gree.sync_time(&self.target).await
+    }
+
+    /// Programs the unit's built-in off-timer (see [vars::TMR_OFF]) to turn it off after the delay, rounded up to whole 
+    /// minutes. Fails with `InvalidVar` if the unit has no timer.
+    pub async fn turn_off_in(&self, after: Duration) -> Result<()> {
+        self.program_timer(vars::TMR_OFF, after).await
+    }
+
+    /// Programs the unit's built-in on-timer (see [vars::TMR_ON]) to turn it on after the delay, rounded up to whole 
+    /// minutes. Fails with `InvalidVar` if the unit has no timer.
+    pub async fn turn_on_in(&self, after: Duration) -> Result<()> {
+        self.program_timer(vars::TMR_ON, after).await
+    }
+
+    /// Disables both built-in timers
+    pub async fn cancel_timers(&self) -> Result<()> {
+        let mut bag: NetVarBag<SimpleNetVar> = [vars::TMR_ON, vars::TMR_OFF].into_iter().map(|n| (n, SimpleNetVar::from_value(0.into()))).collect();
+        self.gree.net_write(&self.target, &mut bag).await
+    }
+
+    async fn program_timer(&self, name: VarName, after: Duration) -> Result<()> {
+        let minutes = vars::timer_minutes(name, after)?;
+        // units without the timer report an empty string rather than failing the read
+        let mut bag: NetVarBag<SimpleNetVar> = [(name, SimpleNetVar::new())].into_iter().collect();
+        self.gree.net_read(&self.target, &mut bag).await?;
+        if !bag[name].user_get().is_number() {
+            return Err(Error::InvalidVar(format!("{name} (not supported by {})", self.target)))
+        }
+        self.gree.set(&self.target, name, minutes).await
+    }
+
+    /// Reads the variables listed in [GreeConfig::poll_vars] in one request
+    pub async fn status(&self) -> Result<VarValues> {
+        let mut bag = net_var_bag_from_names(self.gree.g.cfg.poll_vars.iter())?;
+        self.gree.net_read(&self.target, &mut bag).await?;
+        Ok(bag.to_json())
+    }
+}
@@ -0,0 +1,392 @@
+//! Gree command line interface (requires `cli`)
+//!
+//! Uses the async client if `tokio` is enabled and the sync one otherwise; `serve` requires `server`, `tui` requires
+//! `tui`.
+
+use std::{net::{IpAddr, Ipv4Addr}, process::ExitCode, time::Duration};
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use serde_derive::Serialize;
+use gree::{*, vars::VarName};
+#[cfg(feature = "tokio")]
+use gree::async_client::*;
+#[cfg(not(feature = "tokio"))]
+use gree::sync_client::*;
+
+#[cfg(feature = "tui")]
+mod tui;
+
+#[derive(Debug, Parser)]
+#[command(name = "gree", version, about = "Gree Command Line Interface")]
+struct Cli {
+    /// Broadcast address the scans are sent to
+    #[arg(long, short = 'a', global = true, default_value_t = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 255)))]
+    bcast: IpAddr,
+    /// Maximum number of devices a scan waits for
+    #[arg(long, short = 'c', global = true, default_value_t = 10)]
+    count: usize,
+    /// Output format
+    #[arg(long, short = 'o', global = true, value_enum, default_value_t = Output::Plain)]
+    output: Output,
+    #[command(subcommand)]
+    command: Command,
+}
+
+/// How the results are printed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Output {
+    /// Tab-separated fields, `NAME=VALUE` for the variables
+    Plain,
+    /// Aligned columns with a header
+    Table,
+    /// JSON, for piping into `jq` and the like
+    Json,
+}
+
+/// A device found by a scan, as printed
+#[derive(Debug, Serialize)]
+struct ScanResult {
+    ip: IpAddr,
+    #[serde(flatten)]
+    pack: ScanResponsePack,
+}
+
+/// The device addressed by the low-level commands
+#[derive(Debug, Args)]
+struct DeviceArgs {
+    /// IP address of the device
+    #[arg(long, short = 'i')]
+    ip: IpAddr,
+    /// MAC address of the device
+    #[arg(long, short = 'm')]
+    mac: String,
+    /// The device uses the GCM protocol variant
+    #[arg(long, short = 'G')]
+    gcm: bool,
+}
+
+impl DeviceArgs {
+    fn variant(&self) -> ProtocolVariant {
+        if self.gcm { ProtocolVariant::V2 } else { ProtocolVariant::V1 }
+    }
+}
+
+/// Configuration of the high-level client, used by the commands addressing the devices by MAC address or alias
+#[derive(Debug, Args)]
+struct GreeArgs {
+    /// Device alias, e.g. `living=000cc0000000`
+    #[arg(long, short = 'A', value_parser = parse_alias)]
+    alias: Vec<(String, MacAddr)>,
+    /// Register the names the devices advertise as aliases, e.g. `living-room`
+    #[arg(long, short = 'N')]
+    name_aliases: bool,
+    /// Configuration file (see `gree::config`); overrides `--bcast` and `--count`
+    #[arg(long, short = 'C')]
+    config: Option<std::path::PathBuf>,
+}
+
+impl GreeArgs {
+    fn config(self, cc: GreeClientConfig) -> Result<GreeConfig> {
+        let mut cfg = match &self.config {
+            Some(path) => GreeConfig::from_path(path)?,
+            None => GreeConfig { client_config: cc, ..Default::default() },
+        };
+        cfg.aliases.extend(self.alias);
+        cfg.name_aliases |= self.name_aliases;
+        Ok(cfg)
+    }
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Scans for devices
+    Scan,
+    /// Binds the device, printing its key
+    Bind {
+        #[command(flatten)]
+        device: DeviceArgs,
+    },
+    /// Reads the variables, e.g. `get -i 10.0.0.2 -m 000cc0000000 -k <key> Pow SetTem`
+    Get {
+        #[command(flatten)]
+        device: DeviceArgs,
+        /// Device key, as printed by `bind`
+        #[arg(long, short = 'k')]
+        key: String,
+        #[arg(required = true, value_parser = parse_name)]
+        names: Vec<VarName>,
+    },
+    /// Writes the variables, e.g. `set -i 10.0.0.2 -m 000cc0000000 -k <key> Pow=on SetTem=23`
+    Set {
+        #[command(flatten)]
+        device: DeviceArgs,
+        /// Device key, as printed by `bind`
+        #[arg(long, short = 'k')]
+        key: String,
+        #[arg(required = true, value_parser = parse_var)]
+        vars: Vec<(VarName, Value)>,
+    },
+    /// Reads the variables repeatedly, printing the changes, e.g. `watch --dev living --name TemSen,Pow --interval 10s`
+    Watch {
+        /// Device (MAC address or alias)
+        #[arg(long, short = 'd')]
+        dev: String,
+        /// Variables to read
+        #[arg(long, short = 'n', required = true, value_delimiter = ',', value_parser = parse_name)]
+        name: Vec<VarName>,
+        /// Time between the reads, e.g. `10s`, `500ms`, `5m`
+        #[arg(long, short = 'i', default_value = "10s", value_parser = parse_interval)]
+        interval: Duration,
+        /// Print all the values on every read, not only the changed ones
+        #[arg(long, short = 'f')]
+        full: bool,
+        #[command(flatten)]
+        gree: GreeArgs,
+    },
+    /// Shows a dashboard of the devices, which may be switched on and off and have their set temperature adjusted
+    #[cfg(feature = "tui")]
+    Tui {
+        /// Time between the polls of the devices, e.g. `10s`
+        #[arg(long, short = 'i', default_value = "10s", value_parser = parse_interval)]
+        interval: Duration,
+        #[command(flatten)]
+        gree: GreeArgs,
+    },
+    /// Serves the HTTP REST service (see `gree::server`)
+    #[cfg(feature = "server")]
+    Serve {
+        /// Address to listen at
+        #[arg(long, short = 'l', default_value = "127.0.0.1:7777")]
+        listen: std::net::SocketAddr,
+        #[command(flatten)]
+        gree: GreeArgs,
+    },
+}
+
+fn parse_name(s: &str) -> std::result::Result<VarName, String> {
+    vars::name_of(s).ok_or_else(|| format!("unknown variable `{s}`"))
+}
+
+fn parse_var(s: &str) -> std::result::Result<(VarName, Value), String> {
+    let (name, value) = s.split_once('=').ok_or_else(|| format!("`{s}` is not NAME=VALUE"))?;
+    let name = parse_name(name)?;
+    let value = vars::parse_value(name, value).map_err(|e| e.to_string())?;
+    Ok((name, value))
+}
+
+fn parse_alias(s: &str) -> std::result::Result<(String, MacAddr), String> {
+    let (alias, mac) = s.split_once('=').ok_or_else(|| format!("`{s}` is not ALIAS=MAC"))?;
+    Ok((alias.to_owned(), mac.parse().map_err(|e: Error| e.to_string())?))
+}
+
+fn parse_interval(s: &str) -> std::result::Result<Duration, String> {
+    let split = s.find(|c: char| !c.is_ascii_digit() && c != '.').unwrap_or(s.len());
+    let (n, unit) = s.split_at(split);
+    let n: f64 = n.parse().map_err(|_| format!("`{s}` is not a duration"))?;
+    let secs = match unit {
+        "ms" => n / 1000.0,
+        "" | "s" => n,
+        "m" => n * 60.0,
+        "h" => n * 3600.0,
+        _ => return Err(format!("`{s}`: unknown unit `{unit}` (expected ms, s, m or h)"))
+    };
+    Duration::try_from_secs_f64(secs).map_err(|e| format!("`{s}`: {e}"))
+}
+
+fn print_json(value: &impl serde::Serialize) -> Result<()> {
+    println!("{}", serde_json::to_string_pretty(value)?);
+    Ok(())
+}
+
+fn print_table<const N: usize>(header: [&str; N], rows: Vec<[String; N]>) {
+    let mut widths = header.map(str::len);
+    for row in &rows {
+        for (w, cell) in widths.iter_mut().zip(row) {
+            *w = (*w).max(cell.len());
+        }
+    }
+    let print_row = |row: &[&str]| {
+        let line: Vec<String> = row.iter().zip(widths).map(|(cell, w)| format!("{cell:w$}")).collect();
+        println!("{}", line.join("  ").trim_end());
+    };
+    print_row(&header);
+    for row in &rows {
+        print_row(&row.each_ref().map(String::as_str));
+    }
+}
+
+fn print_scan(output: Output, devs: Vec<(IpAddr, ScanResponsePack)>) -> Result<()> {
+    match output {
+        Output::Plain => devs.iter().for_each(|(ip, pack)| println!("{ip}\t{}\t{}", pack.mac, pack.name)),
+        Output::Table => print_table(["IP", "MAC", "NAME", "MODEL", "VERSION"],
+            devs.into_iter().map(|(ip, p)| [ip.to_string(), p.mac, p.name, p.model, p.ver]).collect()),
+        Output::Json => print_json(&devs.into_iter().map(|(ip, pack)| ScanResult { ip, pack }).collect::<Vec<_>>())?,
+    }
+    Ok(())
+}
+
+fn print_bind(output: Output, pack: &BindResponsePack) -> Result<()> {
+    match output {
+        Output::Plain => println!("{}", pack.key),
+        Output::Table => print_table(["MAC", "KEY"], vec![[pack.mac.clone(), pack.key.clone()]]),
+        Output::Json => print_json(pack)?,
+    }
+    Ok(())
+}
+
+fn print_values(output: Output, names: &[String], values: &[Value]) -> Result<()> {
+    match output {
+        Output::Plain => names.iter().zip(values).for_each(|(name, value)| println!("{name}={value}")),
+        Output::Table => print_table(["NAME", "VALUE"],
+            names.iter().zip(values).map(|(name, value)| [name.clone(), value.to_string()]).collect()),
+        Output::Json => print_json(&names.iter().zip(values).collect::<std::collections::BTreeMap<_, _>>())?,
+    }
+    Ok(())
+}
+
+/// Tracks the values read by `watch`, printing the changes
+struct Watcher {
+    output: Output,
+    full: bool,
+    /// Width of the name column in the table output
+    width: usize,
+    last: VarValues,
+}
+
+impl Watcher {
+    fn new(output: Output, full: bool, names: &[VarName]) -> Self {
+        let width = names.iter().map(|n| n.len()).max().unwrap_or_default().max(4);
+        if output == Output::Table {
+            println!("{:19}  {:width$}  VALUE", "TIME", "NAME");
+        }
+        Self { output, full, width, last: VarValues::new() }
+    }
+
+    fn update(&mut self, names: &[VarName], values: VarValues) -> Result<()> {
+        let now = chrono::Local::now();
+        let changed: Vec<(VarName, &Value)> = names.iter()
+            .filter_map(|name| values.get(name).map(|v| (*name, v)))
+            .filter(|(name, v)| self.full || self.last.get(name) != Some(*v))
+            .collect();
+        if changed.is_empty() { return Ok(()) }
+        match self.output {
+            Output::Plain => {
+                let time = now.format("%Y-%m-%d %H:%M:%S");
+                changed.iter().for_each(|(name, value)| println!("{time}\t{name}={value}"))
+            }
+            Output::Table => {
+                let (time, width) = (now.format("%Y-%m-%d %H:%M:%S"), self.width);
+                changed.iter().for_each(|(name, value)| println!("{time}  {name:width$}  {value}"))
+            }
+            Output::Json => {
+                let values: std::collections::BTreeMap<_, _> = changed.into_iter().collect();
+                println!("{}", serde_json::json!({ "time": now.to_rfc3339(), "values": values }));
+            }
+        }
+        self.last = values;
+        Ok(())
+    }
+}
+
+fn client_config(cli: &Cli) -> GreeClientConfig {
+    GreeClientConfig { bcast_addr: cli.bcast, max_count: cli.count, ..Default::default() }
+}
+
+#[cfg(feature = "tokio")]
+#[tokio::main(flavor = "current_thread")]
+async fn run(cli: Cli) -> Result<()> {
+    let cc = client_config(&cli);
+    let client = || GreeClient::new(cc.clone());
+    match cli.command {
+        Command::Scan => {
+            let devs = client().await?.scan().await?;
+            print_scan(cli.output, devs.into_iter().map(|(ip, _, pack)| (ip, pack)).collect())?;
+        }
+        Command::Bind { device } => {
+            let r = client().await?.bind(device.ip, &device.mac, device.variant()).await?;
+            print_bind(cli.output, &r)?;
+        }
+        Command::Get { device, key, names } => {
+            let r = client().await?.getvars(device.ip, &device.mac, &key, device.variant(), &names).await?;
+            print_values(cli.output, &r.cols, &r.dat)?;
+        }
+        Command::Set { device, key, vars } => {
+            let (names, values): (Vec<VarName>, Vec<Value>) = vars.into_iter().unzip();
+            let r = client().await?.setvars(device.ip, &device.mac, &key, device.variant(), &names, &values).await?;
+            print_values(cli.output, &r.opt, &r.p)?;
+        }
+        Command::Watch { dev, name, interval, full, gree } => {
+            let g = Gree::new(gree.config(cc)?).await?;
+            let mut watcher = Watcher::new(cli.output, full, &name);
+            loop {
+                let mut bag = net_var_bag_from_names(name.iter())?;
+                match g.net_read(&dev, &mut bag).await {
+                    Ok(()) => watcher.update(&name, bag.to_json())?,
+                    Err(e) => eprintln!("error: {e}"),
+                }
+                tokio::time::sleep(interval).await;
+            }
+        }
+        #[cfg(feature = "tui")]
+        Command::Tui { interval, gree } => {
+            let mut cfg = gree.config(cc)?;
+            for name in [vars::POW, vars::SET_TEM, vars::TEM_SEN] {
+                if !cfg.poll_vars.contains(&name) { cfg.poll_vars.push(name) }
+            }
+            tui::run(Gree::new(cfg).await?, interval).await?;
+        }
+        #[cfg(feature = "server")]
+        Command::Serve { listen, gree } => {
+            gree::server::serve(Gree::new(gree.config(cc)?).await?, listen).await;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(not(feature = "tokio"))]
+fn run(cli: Cli) -> Result<()> {
+    let cc = client_config(&cli);
+    let client = || GreeClient::new(cc.clone());
+    match cli.command {
+        Command::Scan => {
+            let devs = client()?.scan()?;
+            print_scan(cli.output, devs.into_iter().map(|(ip, _, pack)| (ip, pack)).collect())?;
+        }
+        Command::Bind { device } => {
+            let r = client()?.bind(device.ip, &device.mac, device.variant())?;
+            print_bind(cli.output, &r)?;
+        }
+        Command::Get { device, key, names } => {
+            let r = client()?.getvars(device.ip, &device.mac, &key, device.variant(), &names)?;
+            print_values(cli.output, &r.cols, &r.dat)?;
+        }
+        Command::Set { device, key, vars } => {
+            let (names, values): (Vec<VarName>, Vec<Value>) = vars.into_iter().unzip();
+            let r = client()?.setvars(device.ip, &device.mac, &key, device.variant(), &names, &values)?;
+            print_values(cli.output, &r.opt, &r.p)?;
+        }
+        Command::Watch { dev, name, interval, full, gree } => {
+            let g = Gree::new(gree.config(cc)?)?;
+            let mut watcher = Watcher::new(cli.output, full, &name);
+            loop {
+                let mut bag = net_var_bag_from_names(name.iter())?;
+                match g.net_read(&dev, &mut bag) {
+                    Ok(()) => watcher.update(&name, bag.to_json())?,
+                    Err(e) => eprintln!("error: {e}"),
+                }
+                std::thread::sleep(interval);
+            }
+        }
+    }
+    Ok(())
+}
+
+fn main() -> ExitCode {
+    env_logger::init();
+    match run(Cli::parse()) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("error: {e}");
+            ExitCode::FAILURE
+        }
+    }
+}
@@ -0,0 +1,215 @@
+//! Terminal dashboard (requires `tui`)
+//!
+//! Shows the devices known to the high-level client along with their state, kept up to date by the background poller
+//! and redrawn on every [GreeEvent]. Keys: `↑`/`↓` select a device, `space` toggles its power, `+`/`-` adjust its set
+//! temperature, `r` scans, `q` quits.
+
+use std::{sync::Arc, time::Duration};
+use ratatui::{
+    DefaultTerminal, Frame,
+    crossterm::event::{self, Event, KeyCode, KeyEventKind},
+    layout::{Constraint, Layout},
+    style::{Modifier, Style},
+    widgets::{Block, Paragraph, Row, Table, TableState},
+};
+use tokio::sync::mpsc;
+use gree::{*, async_client::*, vars::OnOff};
+
+/// How often the screen is redrawn when nothing happens
+const REDRAW_INTERVAL: Duration = Duration::from_secs(1);
+/// How often the key reader checks whether the dashboard is gone
+const KEY_POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+/// A row of the device table
+struct DeviceRow {
+    mac: MacAddr,
+    name: String,
+    ip: String,
+    online: bool,
+    values: VarValues,
+}
+
+impl DeviceRow {
+    fn power(&self) -> Option<bool> {
+        self.values.get(vars::POW)?.as_i64().map(|p| p != 0)
+    }
+
+    fn set_temperature(&self) -> Option<i64> {
+        self.values.get(vars::SET_TEM)?.as_i64()
+    }
+
+    /// The room temperature in degrees Celsius, from `TemSen` (0 when the unit does not report it)
+    fn room_temperature(&self) -> Option<i64> {
+        vars::room_temperature(self.values.get(vars::TEM_SEN)?)
+    }
+}
+
+/// What the dashboard reacts to
+enum Input {
+    Key(KeyCode),
+    /// A [GreeEvent] was produced
+    Changed,
+    /// Outcome of a command, shown in the status line
+    Status(String),
+    Tick,
+}
+
+struct App {
+    gree: Arc<Gree>,
+    rows: Vec<DeviceRow>,
+    table: TableState,
+    status: String,
+    tx: mpsc::UnboundedSender<Input>,
+}
+
+impl App {
+    async fn refresh(&mut self) -> Result<()> {
+        let mut rows = self.gree.with_state(|state| state.devices.values().map(|dev| DeviceRow {
+            mac: MacAddr::from(&dev.scan_result.mac),
+            name: dev.scan_result.name.clone(),
+            ip: dev.ip.to_string(),
+            online: dev.online,
+            values: state.last_known_values(&dev.scan_result.mac),
+        }).collect::<Vec<_>>()).await?;
+        rows.sort_by(|a, b| a.name.cmp(&b.name).then_with(|| a.mac.cmp(&b.mac)));
+        self.rows = rows;
+        if self.table.selected().is_none() && !self.rows.is_empty() {
+            self.table.select(Some(0));
+        }
+        Ok(())
+    }
+
+    fn selected(&self) -> Option<&DeviceRow> {
+        self.rows.get(self.table.selected()?)
+    }
+
+    /// Runs the command in the background, reporting its outcome in the status line
+    fn command(&self, what: String, f: impl std::future::Future<Output = Result<()>> + Send + 'static) {
+        let tx = self.tx.clone();
+        tokio::spawn(async move {
+            let status = match f.await {
+                Ok(()) => format!("{what}: ok"),
+                Err(e) => format!("{what}: {e}"),
+            };
+            let _ = tx.send(Input::Status(status));
+        });
+    }
+
+    fn toggle_power(&self) {
+        let Some(row) = self.selected() else { return };
+        let power = if row.power() == Some(true) { OnOff::Off } else { OnOff::On };
+        let (gree, mac) = (self.gree.clone(), row.mac.clone());
+        self.command(format!("{} power {power}", row.name), async move { gree.set(&mac, vars::POW, power).await });
+    }
+
+    fn adjust_set_temperature(&self, by: i64) {
+        let Some(row) = self.selected() else { return };
+        let Some(current) = row.set_temperature() else { return };
+        let range = SetTemRange::default();
+        let t = (current + by).clamp(range.min as i64, range.max as i64);
+        let (gree, mac) = (self.gree.clone(), row.mac.clone());
+        self.command(format!("{} set temperature {t}", row.name), async move { gree.set(&mac, vars::SET_TEM, t).await });
+    }
+
+    fn scan(&self) {
+        let gree = self.gree.clone();
+        self.command("scan".to_owned(), async move { gree.scan().await.into_result().map(|_| ()) });
+    }
+
+    fn draw(&mut self, frame: &mut Frame) {
+        let [main, status] = Layout::vertical([Constraint::Min(0), Constraint::Length(1)]).areas(frame.area());
+        let header = Row::new(["NAME", "MAC", "IP", "STATUS", "POWER", "SET", "ROOM"])
+            .style(Style::new().add_modifier(Modifier::BOLD));
+        let rows = self.rows.iter().map(|row| {
+            let or_dash = |v: Option<String>| v.unwrap_or_else(|| "-".to_owned());
+            Row::new([
+                row.name.clone(),
+                row.mac.to_string(),
+                row.ip.clone(),
+                if row.online { "online" } else { "offline" }.to_owned(),
+                or_dash(row.power().map(|p| if p { "on" } else { "off" }.to_owned())),
+                or_dash(row.set_temperature().map(|t| format!("{t}°C"))),
+                or_dash(row.room_temperature().map(|t| format!("{t}°C"))),
+            ])
+        });
+        let widths = [
+            Constraint::Min(12), Constraint::Length(12), Constraint::Length(15), Constraint::Length(7),
+            Constraint::Length(5), Constraint::Length(5), Constraint::Length(5),
+        ];
+        let table = Table::new(rows, widths)
+            .header(header)
+            .block(Block::bordered().title(" gree — ↑/↓ select, space power, +/- set temperature, r scan, q quit "))
+            .row_highlight_style(Style::new().add_modifier(Modifier::REVERSED));
+        frame.render_stateful_widget(table, main, &mut self.table);
+        frame.render_widget(Paragraph::new(self.status.as_str()), status);
+    }
+
+    /// Handles the input, returning false to quit
+    async fn handle(&mut self, input: Input) -> Result<bool> {
+        match input {
+            Input::Key(KeyCode::Char('q') | KeyCode::Esc) => return Ok(false),
+            Input::Key(KeyCode::Up | KeyCode::Char('k')) => self.table.select_previous(),
+            Input::Key(KeyCode::Down | KeyCode::Char('j')) => self.table.select_next(),
+            Input::Key(KeyCode::Char(' ') | KeyCode::Enter) => self.toggle_power(),
+            Input::Key(KeyCode::Char('+') | KeyCode::Char('=')) => self.adjust_set_temperature(1),
+            Input::Key(KeyCode::Char('-')) => self.adjust_set_temperature(-1),
+            Input::Key(KeyCode::Char('r')) => self.scan(),
+            Input::Key(_) | Input::Changed | Input::Tick => (),
+            Input::Status(status) => self.status = status,
+        }
+        self.refresh().await?;
+        Ok(true)
+    }
+}
+
+/// Reads the terminal events on a blocking thread until the receiving side is gone
+fn spawn_key_reader(tx: mpsc::UnboundedSender<Input>) {
+    tokio::task::spawn_blocking(move || {
+        while !tx.is_closed() {
+            match event::poll(KEY_POLL_INTERVAL).and_then(|ready| if ready { event::read().map(Some) } else { Ok(None) }) {
+                Ok(Some(Event::Key(key))) if key.kind == KeyEventKind::Press => {
+                    if tx.send(Input::Key(key.code)).is_err() { break }
+                }
+                Ok(_) => (),
+                Err(e) => { log::error!("tui: {e}"); break }
+            }
+        }
+    });
+}
+
+async fn event_loop(terminal: &mut DefaultTerminal, app: &mut App, rx: &mut mpsc::UnboundedReceiver<Input>) -> Result<()> {
+    app.refresh().await?;
+    loop {
+        terminal.draw(|frame| app.draw(frame))?;
+        let Some(input) = rx.recv().await else { return Ok(()) };
+        if !app.handle(input).await? { return Ok(()) }
+    }
+}
+
+/// Runs the dashboard until the user quits. The devices are polled every `interval`.
+pub async fn run(gree: Gree, interval: Duration) -> Result<()> {
+    let gree = Arc::new(gree);
+    let poller = Gree::spawn_poller(gree.clone(), interval);
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    let mut events = gree.subscribe();
+    let events_tx = tx.clone();
+    let forwarder = tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(REDRAW_INTERVAL);
+        loop {
+            let input = tokio::select! {
+                e = events.recv() => match e { Some(_) => Input::Changed, None => break },
+                _ = ticker.tick() => Input::Tick,
+            };
+            if events_tx.send(input).is_err() { break }
+        }
+    });
+    spawn_key_reader(tx.clone());
+    let mut app = App { gree, rows: vec![], table: TableState::default(), status: String::new(), tx };
+    let mut terminal = ratatui::init();
+    let r = event_loop(&mut terminal, &mut app, &mut rx).await;
+    ratatui::restore();
+    poller.abort();
+    forwarder.abort();
+    drop(rx);
+    r
+}
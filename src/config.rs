@@ -0,0 +1,361 @@
+//! Loading [GreeConfig] from configuration files
+//! 
+//! Example (TOML; JSON files have the same structure):
+//! 
+//! ```toml
+//! bcast_addr = "192.168.0.255"
+//! # other subnets to be scanned along
+//! extra_bcast_addrs = ["192.168.1.255"]
+//! # alternatively, broadcast to all the local networks (requires `auto-bcast` feature)
+//! # auto_bcast = true
+//! # scan these addresses (or CIDR ranges) directly instead of broadcasting, see `Discovery::Unicast`
+//! # scan_addrs = ["192.168.0.0/24", "10.0.0.7"]
+//! max_count = 5
+//! # durations are in seconds
+//! # a scan ends when no more devices respond within scan_timeout
+//! scan_timeout = 3
+//! # alternatively, collect the responses for the whole scan_timeout ("full_window"), or until no more devices respond 
+//! # within scan_grace ("until_quiet"); see `ScanMode`
+//! # scan_mode = "until_quiet"
+//! # scan_grace = 0.5
+//! exchange_timeout = 3
+//! bind_timeout = 3
+//! # alternatively, recv_timeout sets all the three timeouts at once
+//! # recv_timeout = 1.5
+//! # requests unanswered within the timeout are retried, see `RetryPolicy`
+//! retry_attempts = 3
+//! retry_base_delay = 0.1
+//! retry_jitter = 0.05
+//! min_scan_age = 60
+//! max_scan_age = 86400
+//! # only scan when requested explicitly
+//! # manual_discovery = true
+//! # a device is evicted after missing this many consecutive scans
+//! max_missed_scans = 3
+//! # also evict the devices which have not answered for this long
+//! # max_device_age = 604800
+//! # a device is considered offline after this many consecutive timeouts, and retried with exponential backoff
+//! offline_threshold = 3
+//! offline_backoff = 10
+//! max_offline_backoff = 600
+//! # minimum time between operations on the same device
+//! min_command_gap = 0.1
+//! # set the device clock to the host's local time after each bind
+//! sync_time_on_bind = true
+//! # bind the devices discovered right after the scan
+//! eager_bind = true
+//! # SetTem values outside the range are rejected before sending
+//! set_tem_range = { min = 16, max = 30 }
+//! # variables reported empty by a device this many times in a row are no longer requested from it; 0 (the default)
+//! # disables
+//! # empty_read_threshold = 3
+//! # split the reads of more variables into several requests, for firmwares truncating larger responses; 0 disables
+//! # max_read_cols = 8
+//! # bindings are persisted to this file, see `FileStateStore`
+//! store = "/var/lib/gree/bindings.json"
+//! # encrypt the keys stored with the passphrase read from this file (requires `encrypted-store` feature), see 
+//! # `EncryptedStateStore`
+//! # store_passphrase_file = "/etc/gree/passphrase"
+//! # values read by the background poller are appended to this CSV file (or SQLite database, if it ends with .db, 
+//! # which requires the `sqlite` feature), see `HistorySink`
+//! history = "/var/lib/gree/history.csv"
+//! history_vars = ["TemSen", "SetTem"]
+//! # receive presence announcements here, see `Gree::listen`
+//! # listen_addr = "0.0.0.0:7000"
+//! # vendor-specific variables, see `vars::register`
+//! extra_vars = ["Dmod"]
+//! # variables read by the background poller
+//! poll_vars = ["Pow", "Mod", "SetTem"]
+//! 
+//! # register the names the devices advertise as aliases, e.g. `living-room`
+//! name_aliases = true
+//! # accept integers sent as strings etc. by the firmwares of some rebranded units
+//! # lenient = true
+//! # `uid` and `i` fields of the requests, for interop testing; `seq` is "default", "increment" or a number
+//! # uid = 0
+//! # seq = "increment"
+//! # generic key of regional models shipping with a different default key
+//! # generic_key = "0123456789abcdef"
+//! 
+//! [aliases]
+//! living = "000cc0000001"
+//! 
+//! # writes to a group are fanned out to the members (MAC addresses or aliases)
+//! [groups]
+//! upstairs = ["living", "000cc0000002"]
+//! 
+//! # see `Gree::apply_scene`; values are validated on load
+//! [scenes.night]
+//! Pow = 1
+//! Mod = "cool"
+//! SetTem = 26
+//! Quiet = 1
+//! 
+//! # SetTem ranges of particular devices, overriding set_tem_range
+//! [set_tem_ranges]
+//! 000cc0000002 = { min = 8, max = 30 }
+//! 
+//! [devices.000cc0000001]
+//! ip = "192.168.0.10"
+//! # key and variant are optional
+//! key = "0123456789abcdef"
+//! variant = "V2"
+//! ```
+
+use std::{collections::HashMap, net::{IpAddr, SocketAddr}, path::{Path, PathBuf}, sync::Arc, time::Duration};
+use serde_derive::Deserialize;
+use crate::*;
+
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+struct ConfigFile {
+    bind_addr: Option<SocketAddr>,
+    bcast_addr: Option<IpAddr>,
+    auto_bcast: bool,
+    extra_bcast_addrs: Vec<IpAddr>,
+    scan_addrs: Vec<String>,
+    buffer_size: Option<usize>,
+    lenient: bool,
+    uid: Option<i32>,
+    seq: Option<Value>,
+    generic_key: Option<String>,
+    max_count: Option<usize>,
+    recv_timeout: Option<f64>,
+    scan_timeout: Option<f64>,
+    scan_mode: Option<String>,
+    scan_grace: Option<f64>,
+    exchange_timeout: Option<f64>,
+    bind_timeout: Option<f64>,
+    retry_attempts: Option<u32>,
+    retry_base_delay: Option<f64>,
+    retry_jitter: Option<f64>,
+    min_scan_age: Option<f64>,
+    max_scan_age: Option<f64>,
+    manual_discovery: bool,
+    max_missed_scans: Option<u32>,
+    max_device_age: Option<f64>,
+    offline_threshold: Option<u32>,
+    offline_backoff: Option<f64>,
+    max_offline_backoff: Option<f64>,
+    min_command_gap: Option<f64>,
+    sync_time_on_bind: bool,
+    eager_bind: bool,
+    set_tem_range: Option<SetTemRange>,
+    set_tem_ranges: HashMap<MacAddr, SetTemRange>,
+    empty_read_threshold: Option<u32>,
+    max_read_cols: usize,
+    store: Option<PathBuf>,
+    store_passphrase_file: Option<PathBuf>,
+    history: Option<PathBuf>,
+    history_vars: Option<Vec<String>>,
+    listen_addr: Option<SocketAddr>,
+    extra_vars: Vec<String>,
+    poll_vars: Option<Vec<String>>,
+    name_aliases: bool,
+    aliases: HashMap<String, String>,
+    groups: HashMap<String, Vec<String>>,
+    scenes: HashMap<String, HashMap<String, Value>>,
+    devices: HashMap<MacAddr, DeviceConfig>,
+}
+
+/// File store, keeping the keys encrypted with the passphrase read from `passphrase_file` if any (the first line, 
+/// trailing whitespace trimmed)
+fn state_store(path: PathBuf, passphrase_file: Option<PathBuf>) -> Result<Arc<dyn StateStore>> {
+    let store = FileStateStore::new(path);
+    let Some(passphrase_file) = passphrase_file else { return Ok(Arc::new(store)) };
+    #[cfg(feature = "encrypted-store")]
+    {
+        let passphrase = std::fs::read_to_string(passphrase_file)?;
+        let passphrase = passphrase.lines().next().unwrap_or_default().trim_end();
+        if passphrase.is_empty() { return Err(Error::Config("empty store passphrase".to_owned())) }
+        Ok(Arc::new(EncryptedStateStore::new(store, passphrase)))
+    }
+    #[cfg(not(feature = "encrypted-store"))]
+    Err(Error::Config(format!("store passphrase {passphrase_file:?} requires the `encrypted-store` feature")))
+}
+
+/// The duration of `v` seconds; negative, NaN and overflowing values are rejected
+fn secs(name: &str, v: f64) -> Result<Duration> {
+    Duration::try_from_secs_f64(v).map_err(|e| Error::Config(format!("`{name}`: {e}")))
+}
+
+/// CSV sink, or SQLite sink if the path ends with `.db`
+fn history_sink(path: PathBuf) -> Result<Arc<dyn HistorySink>> {
+    if path.extension().is_some_and(|e| e == "db") {
+        #[cfg(feature = "sqlite")]
+        return Ok(Arc::new(SqliteHistorySink::open(path)?));
+        #[cfg(not(feature = "sqlite"))]
+        return Err(Error::Config(format!("history database {path:?} requires the `sqlite` feature")));
+    }
+    Ok(Arc::new(CsvHistorySink::new(path)))
+}
+
+impl TryFrom<ConfigFile> for GreeConfig {
+    type Error = Error;
+    fn try_from(f: ConfigFile) -> Result<Self> {
+        let mut cfg = GreeConfig::default();
+        let cc = &mut cfg.client_config;
+        if f.auto_bcast { *cc = GreeClientConfig::auto_bcast()? }
+        if let Some(v) = f.bind_addr { cc.bind_addr = v }
+        if let Some(v) = f.bcast_addr { cc.bcast_addr = v }
+        cc.extra_bcast_addrs.extend(f.extra_bcast_addrs);
+        if let Some(v) = f.buffer_size { cc.buffer_size = v }
+        cc.lenient = f.lenient;
+        if let Some(v) = f.uid { cc.uid = v }
+        cc.generic_key = f.generic_key;
+        cc.seq = match &f.seq {
+            None => SeqMode::Default,
+            Some(Value::String(m)) if m == "default" => SeqMode::Default,
+            Some(Value::String(m)) if m == "increment" => SeqMode::Increment,
+            Some(v) => SeqMode::Fixed(v.as_i64().and_then(|i| i.try_into().ok())
+                .ok_or_else(|| Error::Config(format!("invalid seq `{v}`")))?),
+        };
+        if let Some(v) = f.max_count { cc.max_count = v }
+        if let Some(v) = f.recv_timeout.map(|v| secs("recv_timeout", v)).transpose()? {
+            (cc.scan_timeout, cc.exchange_timeout, cc.bind_timeout) = (v, v, v)
+        }
+        if let Some(v) = f.scan_timeout { cc.scan_timeout = secs("scan_timeout", v)? }
+        let grace = f.scan_grace.map(|v| secs("scan_grace", v)).transpose()?.unwrap_or(ScanMode::DEFAULT_GRACE);
+        cc.scan_mode = match f.scan_mode.as_deref() {
+            None | Some("first_n") => ScanMode::FirstN,
+            Some("full_window") => ScanMode::FullWindow,
+            Some("until_quiet") => ScanMode::UntilQuiet { grace },
+            Some(m) => return Err(Error::Config(format!("unknown scan_mode `{m}`"))),
+        };
+        if let Some(v) = f.exchange_timeout { cc.exchange_timeout = secs("exchange_timeout", v)? }
+        if let Some(v) = f.bind_timeout { cc.bind_timeout = secs("bind_timeout", v)? }
+        if let Some(v) = f.retry_attempts { cc.retry.attempts = v.max(1) }
+        if let Some(v) = f.retry_base_delay { cc.retry.base_delay = secs("retry_base_delay", v)? }
+        if let Some(v) = f.retry_jitter { cc.retry.jitter = secs("retry_jitter", v)? }
+        if !f.scan_addrs.is_empty() {
+            let mut ips = vec![];
+            for a in &f.scan_addrs { ips.extend(ip_range(a)?) }
+            cfg.discovery = Discovery::Unicast(ips);
+        }
+        if let Some(v) = f.min_scan_age { cfg.min_scan_age = secs("min_scan_age", v)? }
+        if let Some(v) = f.max_scan_age { cfg.max_scan_age = secs("max_scan_age", v)? }
+        cfg.manual_discovery = f.manual_discovery;
+        if let Some(v) = f.max_missed_scans { cfg.max_missed_scans = v }
+        cfg.max_device_age = f.max_device_age.map(|v| secs("max_device_age", v)).transpose()?;
+        if let Some(v) = f.offline_threshold { cfg.offline_threshold = v }
+        if let Some(v) = f.offline_backoff { cfg.offline_backoff = secs("offline_backoff", v)? }
+        if let Some(v) = f.max_offline_backoff { cfg.max_offline_backoff = secs("max_offline_backoff", v)? }
+        if let Some(v) = f.min_command_gap { cfg.min_command_gap = secs("min_command_gap", v)? }
+        cfg.sync_time_on_bind = f.sync_time_on_bind;
+        cfg.eager_bind = f.eager_bind;
+        if let Some(v) = f.set_tem_range { cfg.set_tem_range = v }
+        cfg.set_tem_ranges = f.set_tem_ranges;
+        if let Some(v) = f.empty_read_threshold { cfg.empty_read_threshold = v }
+        cfg.max_read_cols = f.max_read_cols;
+        cfg.listen_addr = f.listen_addr;
+        if let Some(v) = f.store { cfg.store = Some(state_store(v, f.store_passphrase_file)?) }
+        if let Some(v) = f.history { cfg.history = Some(history_sink(v)?) }
+        for n in &f.extra_vars { vars::register(n)?; }
+        if let Some(v) = f.poll_vars {
+            cfg.poll_vars = v.iter()
+                .map(|n| vars::name_of(n).ok_or_else(|| Error::Config(format!("unknown variable `{n}` in poll_vars"))))
+                .collect::<Result<_>>()?;
+        }
+        if let Some(v) = f.history_vars {
+            cfg.history_vars = v.iter()
+                .map(|n| vars::name_of(n).ok_or_else(|| Error::Config(format!("unknown variable `{n}` in history_vars"))))
+                .collect::<Result<_>>()?;
+        }
+        cfg.name_aliases = f.name_aliases;
+        cfg.aliases = f.aliases.into_iter().map(|(alias, mac)| Ok((alias, mac.parse()?))).collect::<Result<_>>()?;
+        cfg.groups = f.groups;
+        for (name, values) in f.scenes {
+            // values may be given as numbers or strings, both are parsed the same way
+            let values: Vec<(String, String)> = values.into_iter()
+                .map(|(n, v)| (n, v.as_str().map(str::to_owned).unwrap_or_else(|| v.to_string())))
+                .collect();
+            let scene = Scene::parse(values.iter().map(|(n, v)| (n, v)))
+                .map_err(|e| Error::Config(format!("scene `{name}`: {e}")))?;
+            cfg.scenes.insert(name, scene);
+        }
+        cfg.devices = f.devices;
+        Ok(cfg)
+    }
+}
+
+impl GreeConfig {
+    /// Loads configuration from a file. Files with `.toml` extension are parsed as TOML (requires `toml` feature), 
+    /// all other files as JSON. Settings missing from the file have their default values.
+    /// 
+    /// See [config](crate::config) module docs for the file format.
+    pub fn from_path(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path)?;
+        if path.extension().is_some_and(|e| e.eq_ignore_ascii_case("toml")) {
+            Self::from_toml_str(&text)
+        } else {
+            Self::from_json_str(&text)
+        }
+    }
+
+    /// Parses configuration from a JSON string
+    pub fn from_json_str(s: &str) -> Result<Self> {
+        let f: ConfigFile = serde_json::from_str(s)?;
+        f.try_into()
+    }
+
+    /// Parses configuration from a TOML string (requires `toml` feature)
+    #[cfg(feature = "toml")]
+    pub fn from_toml_str(s: &str) -> Result<Self> {
+        let f: ConfigFile = toml::from_str(s).map_err(|e| Error::Config(e.to_string()))?;
+        f.try_into()
+    }
+
+    /// Parses configuration from a TOML string (requires `toml` feature)
+    #[cfg(not(feature = "toml"))]
+    pub fn from_toml_str(_: &str) -> Result<Self> {
+        Err(Error::Config("TOML support requires `toml` feature".to_owned()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The TOML example of the module docs
+    #[cfg(feature = "toml")]
+    fn doc_example() -> String {
+        include_str!("config.rs").lines()
+            .filter_map(|l| l.strip_prefix("//!"))
+            .skip_while(|l| l.trim() != "```toml").skip(1)
+            .take_while(|l| l.trim() != "```")
+            .map(|l| l.strip_prefix(' ').unwrap_or(l))
+            .collect::<Vec<_>>().join("\n")
+    }
+
+    #[cfg(feature = "toml")]
+    #[test]
+    fn doc_example_loads() {
+        let text = doc_example();
+        let cfg = GreeConfig::from_toml_str(&text).unwrap();
+        assert_eq!(cfg.client_config.bcast_addr, IpAddr::from([192, 168, 0, 255]));
+        assert_eq!(cfg.client_config.retry.base_delay, Duration::from_millis(100));
+        assert_eq!(cfg.min_command_gap, Duration::from_millis(100));
+        assert_eq!(cfg.aliases["living"], "000cc0000001");
+        assert!(cfg.scenes.contains_key("night"));
+        assert_eq!(cfg.devices.len(), 1);
+
+        // JSON files have the same structure
+        let json = serde_json::to_string(&toml::from_str::<Value>(&text).unwrap()).unwrap();
+        let cfg = GreeConfig::from_json_str(&json).unwrap();
+        assert_eq!(cfg.offline_backoff, Duration::from_secs(10));
+        assert_eq!(cfg.groups["upstairs"], ["living", "000cc0000002"]);
+    }
+
+    #[test]
+    fn invalid_durations_are_rejected() {
+        for v in ["-1", "1e300"] {
+            let e = GreeConfig::from_json_str(&format!("{{\"scan_timeout\": {v}}}")).unwrap_err();
+            assert!(matches!(&e, Error::Config(m) if m.starts_with("`scan_timeout`")), "{e}");
+        }
+        let e = GreeConfig::from_json_str("{\"max_device_age\": -0.5}").unwrap_err();
+        assert!(matches!(&e, Error::Config(m) if m.starts_with("`max_device_age`")), "{e}");
+        let cfg = GreeConfig::from_json_str("{\"scan_timeout\": 0.25}").unwrap();
+        assert_eq!(cfg.client_config.scan_timeout, Duration::from_millis(250));
+    }
+}
@@ -0,0 +1,318 @@
+//! Virtual Gree device emulator (requires `emulator`)
+//!
+//! Answers scan, bind, status and cmd packs on behalf of any number of simulated devices sharing one UDP socket,
+//! as well as keyed dev packs and subList packs (on behalf of multi-split bridges), so that `GreeClient` and `Gree`
+//! can be exercised without real hardware.
+//!
+//! Example usage:
+//!
+//! ```no_run
+//! # use gree::{*, emulator::*, sync_client::*};
+//! # fn main() -> Result<()> {
+//! let emu = Emulator::start(EmulatorConfig::default(), vec![EmulatedDevice::new("000cc0000001", "livingroom")])?;
+//! let mut cfg = GreeConfig::default();
+//! cfg.client_config.bcast_addr = emu.local_addr().ip();
+//! let mut gree = Gree::new(cfg)?;
+//! gree.set("000cc0000001", vars::POW, vars::OnOff::On)?;
+//! emu.with_device("000cc0000001", |dev| assert_eq!(dev.vars[vars::POW], 1));
+//! # Ok(())
+//! # }
+//! ```
+
+#![cfg(feature = "emulator")]
+
+use std::{net::{UdpSocket, SocketAddr, IpAddr, Ipv4Addr}, collections::HashMap, sync::{Arc, Mutex, atomic::{AtomicBool, Ordering}}, thread::JoinHandle, time::Duration};
+use serde_json::{json, Value};
+use crate::*;
+
+/// Emulator configuration
+#[derive(Debug, Clone, Copy)]
+pub struct EmulatorConfig {
+    /// Socket addr to bind to. The port should normally be the Gree port (7000), as this is where clients send to.
+    pub bind_addr: SocketAddr,
+    /// Recv datagram buffer size
+    pub buffer_size: usize,
+    /// How often the emulator thread checks whether it should stop
+    pub poll_interval: Duration,
+}
+
+impl EmulatorConfig {
+    pub const DEFAULT_BUFFER_SIZE: usize = 2048;
+    pub const DEFAULT_POLL_INTERVAL: Duration = Duration::from_millis(100);
+}
+
+impl Default for EmulatorConfig {
+    fn default() -> Self {
+        Self {
+            bind_addr: (Ipv4Addr::LOCALHOST, PORT).into(),
+            buffer_size: Self::DEFAULT_BUFFER_SIZE,
+            poll_interval: Self::DEFAULT_POLL_INTERVAL,
+        }
+    }
+}
+
+/// Faults to be injected into a simulated device's communication
+#[derive(Debug, Clone, Default)]
+pub struct Faults {
+    /// Number of subsequent requests to be dropped silently
+    pub drop: usize,
+    /// Drop all the requests, as if the device was offline
+    pub offline: bool,
+    /// Encrypt responses with a wrong key until the next bind, as if the device had been bound by another client
+    pub bad_key: bool,
+    /// Result code to report in bind, status and cmd responses instead of 200
+    pub result_code: Option<i32>,
+}
+
+/// A simulated device
+#[derive(Debug, Clone)]
+pub struct EmulatedDevice {
+    /// MAC address
+    pub mac: String,
+    /// Device name, as reported in the scan response
+    pub name: String,
+    /// Key handed out on bind
+    pub key: String,
+    /// Protocol variant the device speaks
+    pub variant: ProtocolVariant,
+    /// Generic key of the firmware, if not the variant's one (see [GreeClientConfig::generic_key])
+    pub generic_key: Option<String>,
+    /// Current values of the variables
+    pub vars: HashMap<String, Value>,
+    /// Faults to inject
+    pub faults: Faults,
+    /// Sub-units, if the device is a multi-split bridge. Their keys, variants and faults are not used.
+    pub sub_devices: Vec<EmulatedDevice>,
+}
+
+impl EmulatedDevice {
+    pub const DEFAULT_KEY: &'static str = "0123456789abcdef";
+
+    /// Creates a device with default key, protocol variant and variable values
+    pub fn new(mac: &str, name: &str) -> Self {
+        let vars = [
+            (vars::POW, 0), (vars::MOD, 1), (vars::SET_TEM, 24), (vars::TEM_UN, 0), (vars::WD_SPD, 0),
+            (vars::AIR, 0), (vars::BLO, 0), (vars::HEALTH, 0), (vars::SWH_SLP, 0), (vars::LIG, 1),
+            (vars::SWING_LF_RIG, 0), (vars::SW_UP_DN, 0), (vars::QUIET, 0), (vars::TUR, 0), (vars::ST_HT, 0),
+            (vars::HEAT_COOL_TYPE, 0), (vars::TEM_REC, 0), (vars::SV_ST, 0), (vars::TEM_SEN, 65), (vars::TMR_ON, 0), (vars::TMR_OFF, 0),
+            (vars::ANTI_DIRECT_BLOW, 0), (vars::LIG_SEN, 0), (vars::SLP_MOD, 0), (vars::BUZZER, 0), (vars::SELF_CLEAN, 0),
+        ].into_iter().map(|(n, v)| (n.to_owned(), Value::from(v))).collect();
+        Self {
+            mac: mac.into(),
+            name: name.to_owned(),
+            key: Self::DEFAULT_KEY.to_owned(),
+            variant: ProtocolVariant::V1,
+            generic_key: None,
+            vars,
+            faults: Faults::default(),
+            sub_devices: vec![],
+        }
+    }
+
+    /// Returns true if the request is to be dropped, according to the faults
+    fn drops(&mut self) -> bool {
+        if self.faults.offline { return true }
+        if self.faults.drop > 0 {
+            self.faults.drop -= 1;
+            return true
+        }
+        false
+    }
+
+    fn result_code(&self) -> i32 {
+        self.faults.result_code.unwrap_or(R_OK)
+    }
+
+    fn generic_key(&self) -> String {
+        self.generic_key.clone().unwrap_or_else(|| self.variant.generic_key().to_owned())
+    }
+
+    fn out_key(&self, key: &str) -> String {
+        if self.faults.bad_key { "fedcba9876543210".to_owned() } else { key.to_owned() }
+    }
+
+    fn respond(&self, pack: Value, key: &str, i: i32) -> Result<Vec<u8>> {
+        let (pack, tag) = encode_pack(serde_json::to_vec(&pack)?, self.out_key(key).as_bytes(), self.variant)?;
+        let mut m = json!({ "t": "pack", "i": i, "uid": 0, "cid": self.mac, "tcid": "", "pack": pack });
+        if let Some(tag) = tag { m["tag"] = tag.into() }
+        Ok(serde_json::to_vec(&m)?)
+    }
+
+    fn scan(&self) -> Result<Vec<u8>> {
+        let pack = json!({
+            "t": "dev", "cid": self.mac, "bc": "", "brand": "gree", "catalog": "gree", "mac": self.mac, "mid": "10001",
+            "model": "gree", "name": self.name, "lock": 0, "series": "gree", "vender": "1", "ver": "V1.1.13",
+            "subCnt": self.sub_devices.len(),
+        });
+        self.respond(pack, &self.generic_key(), 1)
+    }
+
+    fn handle_pack(&mut self, gm: &GenericMessage) -> Result<Vec<u8>> {
+        if let Ok(pack) = decode_pack(&gm.pack, &gm.tag, &self.key, self.variant) {
+            if let Ok(pack) = serde_json::from_str::<Value>(&pack) {
+                return self.handle_keyed(pack)
+            }
+        }
+        let generic_key = self.generic_key();
+        let pack: Value = serde_json::from_str(&decode_pack(&gm.pack, &gm.tag, &generic_key, self.variant)?)?;
+        match pack["t"].as_str() {
+            Some("bind") => {
+                self.faults.bad_key = false;
+                let pack = json!({ "t": "bindok", "mac": self.mac, "key": self.key, "r": self.result_code() });
+                self.respond(pack, &generic_key, 1)
+            }
+            _ => Err(Error::invalid_value("t", &pack["t"].to_string()))
+        }
+    }
+
+    /// The device itself, or its sub-unit `mac` addressed in a pack
+    fn unit(&mut self, mac: Option<&str>) -> Result<&mut EmulatedDevice> {
+        match mac {
+            Some(mac) if mac != self.mac => self.sub_devices.iter_mut().find(|sub| sub.mac == mac).ok_or_else(|| Error::not_found(mac)),
+            _ => Ok(self),
+        }
+    }
+
+    fn handle_keyed(&mut self, pack: Value) -> Result<Vec<u8>> {
+        let strings = |v: &Value| -> Vec<String> {
+            v.as_array().map(|a| a.iter().filter_map(|s| s.as_str().map(|s| s.to_owned())).collect()).unwrap_or_default()
+        };
+        let r = self.result_code();
+        match pack["t"].as_str() {
+            Some("status") => {
+                let unit = self.unit(pack["mac"].as_str())?;
+                let cols = strings(&pack["cols"]);
+                let dat: Vec<Value> = cols.iter().map(|c| unit.vars.get(c).cloned().unwrap_or_else(|| "".into())).collect();
+                let pack = json!({ "t": "dat", "mac": unit.mac, "r": r, "cols": cols, "dat": dat });
+                self.respond(pack, &self.key, 0)
+            }
+            Some("cmd") => {
+                let unit = self.unit(pack["sub"].as_str())?;
+                let opt = strings(&pack["opt"]);
+                let p = pack["p"].as_array().cloned().unwrap_or_default();
+                for (n, v) in opt.iter().zip(p.iter()) {
+                    unit.vars.insert(n.clone(), v.clone());
+                }
+                let pack = json!({ "t": "res", "mac": unit.mac, "r": r, "opt": opt, "p": p, "val": p });
+                self.respond(pack, &self.key, 0)
+            }
+            Some("dev") => {
+                let pack = json!({
+                    "t": "dev", "cid": self.mac, "mac": self.mac, "mid": "10001", "model": "gree", "name": self.name,
+                    "brand": "gree", "series": "gree", "vender": "1", "ver": "V1.1.13", "hid": "362001000762+U-EMULATORV1.00.bin",
+                });
+                self.respond(pack, &self.key, 0)
+            }
+            Some("subList") => {
+                let list: Vec<Value> = self.sub_devices.iter().map(|sub| json!({ "mac": sub.mac, "mid": "10001", "name": sub.name })).collect();
+                let pack = json!({ "t": "subList", "mac": self.mac, "r": r, "c": list.len(), "i": 0, "list": list });
+                self.respond(pack, &self.key, 0)
+            }
+            _ => Err(Error::invalid_value("t", &pack["t"].to_string()))
+        }
+    }
+}
+
+type Devices = Arc<Mutex<Vec<EmulatedDevice>>>;
+
+/// Gree device emulator
+///
+/// Serves the simulated devices from a background thread, which is stopped when the emulator is dropped.
+pub struct Emulator {
+    devices: Devices,
+    socket: UdpSocket,
+    local_addr: SocketAddr,
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Emulator {
+    /// Binds the socket and starts serving the devices
+    pub fn start(cfg: EmulatorConfig, devices: Vec<EmulatedDevice>) -> Result<Self> {
+        let s = UdpSocket::bind(cfg.bind_addr)?;
+        s.set_read_timeout(Some(cfg.poll_interval))?;
+        let local_addr = s.local_addr()?;
+        trace!("Emulator bound to: {local_addr}");
+        let socket = s.try_clone()?;
+        let devices = Arc::new(Mutex::new(devices));
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread = {
+            let (devices, stop) = (devices.clone(), stop.clone());
+            std::thread::spawn(move || Self::serve(s, devices, stop, cfg.buffer_size))
+        };
+        Ok(Self { devices, socket, local_addr, stop, thread: Some(thread) })
+    }
+
+    /// Address the emulator is bound to
+    pub fn local_addr(&self) -> SocketAddr { self.local_addr }
+
+    /// Calls `f` with the simulated device, e.g. to inspect or change its variables or faults
+    pub fn with_device<R>(&self, mac: &str, f: impl FnOnce(&mut EmulatedDevice) -> R) -> Option<R> {
+        lock(&self.devices).iter_mut().find(|d| d.mac == mac).map(f)
+    }
+
+    /// Adds a simulated device
+    pub fn add_device(&self, device: EmulatedDevice) {
+        lock(&self.devices).push(device)
+    }
+
+    /// Sends the device's scan response to `to` unsolicited, as devices announcing their presence do
+    pub fn announce(&self, mac: &str, to: SocketAddr) -> Result<()> {
+        let r = self.with_device(mac, |dev| dev.scan()).ok_or_else(|| Error::not_found(mac))??;
+        self.socket.send_to(&r, to)?;
+        Ok(())
+    }
+
+    /// Removes a simulated device
+    pub fn remove_device(&self, mac: &str) -> Option<EmulatedDevice> {
+        let mut devices = lock(&self.devices);
+        let pos = devices.iter().position(|d| d.mac == mac)?;
+        Some(devices.remove(pos))
+    }
+
+    fn serve(s: UdpSocket, devices: Devices, stop: Arc<AtomicBool>, buffer_size: usize) {
+        let mut b = vec![0u8; buffer_size];
+        while !stop.load(Ordering::Relaxed) {
+            let (len, addr) = match s.recv_from(&mut b) {
+                Ok(la) => la,
+                Err(e) if matches!(e.kind(), std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut) => continue,
+                Err(e) => { error!("Emulator recv: {e}"); break }
+            };
+            trace!("Emulator [{}] raw: {}", addr, String::from_utf8_lossy(&b[..len]));
+            let gm: GenericMessage = match serde_json::from_slice(&b[..len]) {
+                Ok(gm) => gm,
+                Err(e) => { error!("Emulator [{addr}]: {e}"); continue }
+            };
+            for r in Self::handle(&devices, addr.ip(), &gm) {
+                match r.and_then(|r| Ok(s.send_to(&r, addr)?)) {
+                    Ok(_) => (),
+                    Err(e) => error!("Emulator [{addr}]: {e}"),
+                }
+            }
+        }
+    }
+
+    fn handle(devices: &Devices, addr: IpAddr, gm: &GenericMessage) -> Vec<Result<Vec<u8>>> {
+        let mut devices = lock(devices);
+        match gm.t.as_str() {
+            "scan" => devices.iter_mut().filter_map(|d| if d.drops() { None } else { Some(d.scan()) }).collect(),
+            "pack" => devices.iter_mut()
+                .filter(|d| d.mac == gm.tcid)
+                .filter_map(|d| if d.drops() { None } else { Some(d.handle_pack(gm)) })
+                .collect(),
+            other => {
+                debug!("Emulator [{addr}]: ignored `{other}`");
+                vec![]
+            }
+        }
+    }
+}
+
+impl Drop for Emulator {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(t) = self.thread.take() {
+            let _ = t.join();
+        }
+    }
+}
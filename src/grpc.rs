@@ -0,0 +1,138 @@
+//! gRPC control service (requires `grpc`)
+//!
+//! Exposes an async [Gree] over gRPC, as service `gree.v1.GreeControl` declared in `proto/gree.proto`:
+//!
+//! * `Scan` - performs a scan, returns the devices known
+//! * `List` - returns the devices known
+//! * `Get` - reads the variables, returns their values
+//! * `Set` - writes the variables, returns the values reported by the device
+//! * `Subscribe` - streams the [GreeEvent]s
+//!
+//! The values are passed as text: `SetRequest` takes the values accepted by the REST service (e.g. `1`, `on`,
+//! `cool`), and `Values` carries the JSON of the values reported (e.g. `1`). Errors are mapped to status codes by
+//! [status]. Clients in Rust may use [proto::gree_control_client::GreeControlClient].
+//!
+//! Example usage:
+//!
+//! ```no_run
+//! # use gree::{*, async_client::*};
+//! # #[tokio::main]
+//! # async fn main() -> Result<()> {
+//! let gree = Gree::new(GreeConfig::default()).await?;
+//! gree::grpc::serve(gree, ([127, 0, 0, 1], 7778)).await?;
+//! # Ok(())
+//! # }
+//! ```
+
+#![cfg(feature = "grpc")]
+
+use std::{net::SocketAddr, pin::Pin, sync::Arc};
+use tokio_stream::{Stream, StreamExt, wrappers::UnboundedReceiverStream};
+use tonic::{Request, Response, Status};
+use crate::{*, async_client::*};
+
+/// The messages and the service code, generated from `proto/gree.proto`
+pub mod proto {
+    tonic::include_proto!("gree.v1");
+}
+
+/// gRPC status corresponding to the error, as the REST service maps the errors to HTTP: `INVALID_ARGUMENT` 
+/// for the errors in the request, `NOT_FOUND` for unknown devices, `UNAVAILABLE` for devices not answering, and 
+/// `INTERNAL` for the rest
+pub fn status(e: &Error) -> Status {
+    let message = e.to_string();
+    match e.root() {
+        Error::NotFound(_) => Status::not_found(message),
+        Error::InvalidVar(_) | Error::InvalidValue(..) | Error::InvalidMacAddr(_) | Error::Config(_) => Status::invalid_argument(message),
+        Error::Offline(_) => Status::unavailable(message),
+        e if e.is_timeout() => Status::unavailable(message),
+        _ => Status::internal(message)
+    }
+}
+
+/// The service implementation, serving a shared `Gree` instance
+pub struct GreeControlService<C: GreeClientApi> {
+    gree: Arc<Gree<C>>,
+}
+
+impl<C: GreeClientApi + 'static> GreeControlService<C> {
+    pub fn new(gree: Arc<Gree<C>>) -> Self {
+        Self { gree }
+    }
+
+    /// The tonic service, for adding to a larger `tonic::transport::Server`
+    pub fn into_server(self) -> proto::gree_control_server::GreeControlServer<Self> {
+        proto::gree_control_server::GreeControlServer::new(self)
+    }
+
+    async fn device_list(&self) -> Result<proto::DeviceList> {
+        self.gree.with_state(|state| proto::DeviceList {
+            devices: state.iter_sorted().map(|(_, dev)| proto::Device {
+                mac: dev.scan_result.mac.clone(),
+                ip: dev.ip.to_string(),
+                name: dev.scan_result.name.clone(),
+                bound: dev.key.is_some(),
+                provisioned: dev.provisioned,
+            }).collect()
+        }).await
+    }
+}
+
+fn values<T: NetVar>(bag: &NetVarBag<T>) -> proto::Values {
+    proto::Values { values: bag.to_json().into_iter().map(|(name, value)| (name.to_owned(), value.to_string())).collect() }
+}
+
+fn event(e: GreeEvent) -> proto::Event {
+    let json = serde_json::to_value(&e).unwrap_or_default();
+    let field = |name: &str| json[name].as_str().unwrap_or_default().to_owned();
+    proto::Event { event: field("event"), mac: field("mac"), json: json.to_string() }
+}
+
+#[tonic::async_trait]
+impl<C: GreeClientApi + 'static> proto::gree_control_server::GreeControl for GreeControlService<C> {
+    async fn scan(&self, _request: Request<proto::ScanRequest>) -> std::result::Result<Response<proto::DeviceList>, Status> {
+        self.gree.scan().await.into_result().map_err(|e| status(&e))?;
+        self.device_list().await.map(Response::new).map_err(|e| status(&e))
+    }
+
+    async fn list(&self, _request: Request<proto::ListRequest>) -> std::result::Result<Response<proto::DeviceList>, Status> {
+        self.device_list().await.map(Response::new).map_err(|e| status(&e))
+    }
+
+    async fn get(&self, request: Request<proto::GetRequest>) -> std::result::Result<Response<proto::Values>, Status> {
+        let request = request.into_inner();
+        let mut bag = net_var_bag_from_names(request.names.iter()).map_err(|e| status(&e))?;
+        self.gree.net_read(&request.target, &mut bag).await.map_err(|e| status(&e))?;
+        Ok(Response::new(values(&bag)))
+    }
+
+    async fn set(&self, request: Request<proto::SetRequest>) -> std::result::Result<Response<proto::Values>, Status> {
+        let request = request.into_inner();
+        let mut bag = net_var_bag_from_nvs(request.values.iter()).map_err(|e| status(&e))?;
+        self.gree.net_write(&request.target, &mut bag).await.map_err(|e| status(&e))?;
+        Ok(Response::new(values(&bag)))
+    }
+
+    type SubscribeStream = Pin<Box<dyn Stream<Item = std::result::Result<proto::Event, Status>> + Send>>;
+
+    async fn subscribe(&self, _request: Request<proto::SubscribeRequest>) -> std::result::Result<Response<Self::SubscribeStream>, Status> {
+        let events = UnboundedReceiverStream::new(self.gree.subscribe()).map(event).map(Ok);
+        Ok(Response::new(Box::pin(events)))
+    }
+}
+
+/// Serves `gree` at `addr` until failing
+pub async fn serve<C: GreeClientApi + 'static>(gree: Gree<C>, addr: impl Into<SocketAddr>) -> Result<()> {
+    serve_shared(Arc::new(gree), addr).await
+}
+
+/// Serves the shared `Gree` instance at `addr` until failing. Other tasks (e.g. the poller, see
+/// [Gree::spawn_poller]) may use the instance concurrently.
+pub async fn serve_shared<C: GreeClientApi + 'static>(gree: Arc<Gree<C>>, addr: impl Into<SocketAddr>) -> Result<()> {
+    let addr = addr.into();
+    debug!("Serving gRPC at {addr}");
+    tonic::transport::Server::builder()
+        .add_service(GreeControlService::new(gree).into_server())
+        .serve(addr).await
+        .map_err(|e| Error::Io(std::io::Error::other(e)))
+}
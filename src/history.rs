@@ -0,0 +1,101 @@
+//! Historical logging of variable values
+//!
+//! The background poller (`async_client::Gree::spawn_poller`) hands the values of the variables selected by
+//! [GreeConfig::history_vars](crate::GreeConfig::history_vars) (by default `TemSen`, which is offset by 40, see
+//! [vars::TEM_SEN]) to the sink set in [GreeConfig::history](crate::GreeConfig::history), so that e.g. the room
+//! temperature may be graphed without an external stack. [CsvHistorySink] appends them to a CSV file;
+//! [SqliteHistorySink] (requires `sqlite`) inserts them into an SQLite table.
+
+use std::{fmt::Debug, io::Write, path::PathBuf, time::SystemTime};
+use chrono::{DateTime, SecondsFormat, Utc};
+use crate::{*, state::MacAddr};
+
+/// A value of a variable read at a point in time
+#[derive(Debug, Clone, PartialEq)]
+pub struct HistorySample {
+    pub mac: MacAddr,
+    pub name: VarName,
+    pub value: Value,
+    /// When the value was read
+    pub at: SystemTime,
+}
+
+/// Storage backend for the history of variable values
+pub trait HistorySink: Debug + Send + Sync {
+    /// Appends the samples
+    fn record(&self, samples: &[HistorySample]) -> Result<()>;
+}
+
+/// [HistorySink] appending the samples to a CSV file, one per line: `at,mac,name,value`, where `at` is an RFC 3339
+/// UTC timestamp. The header is written when the file is created.
+#[derive(Debug, Clone)]
+pub struct CsvHistorySink {
+    path: PathBuf,
+}
+
+impl CsvHistorySink {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+}
+
+fn csv_field(s: &str) -> std::borrow::Cow<'_, str> {
+    if s.contains([',', '"', '\n', '\r']) { format!("\"{}\"", s.replace('"', "\"\"")).into() } else { s.into() }
+}
+
+impl HistorySink for CsvHistorySink {
+    fn record(&self, samples: &[HistorySample]) -> Result<()> {
+        let mut lines = String::new();
+        for s in samples {
+            let at = DateTime::<Utc>::from(s.at).to_rfc3339_opts(SecondsFormat::Secs, true);
+            let value = match &s.value { Value::String(v) => v.clone(), v => v.to_string() };
+            lines += &format!("{at},{},{},{}\n", csv_field(&s.mac), csv_field(s.name), csv_field(&value));
+        }
+        let mut f = std::fs::OpenOptions::new().create(true).append(true).open(&self.path)?;
+        if f.metadata()?.len() == 0 { f.write_all(b"at,mac,name,value\n")? }
+        f.write_all(lines.as_bytes())?;
+        Ok(())
+    }
+}
+
+/// [HistorySink] inserting the samples into the `history` table of an SQLite database, which is created if needed.
+/// `at` is stored as milliseconds since the Unix epoch. Requires `sqlite`.
+#[cfg(feature = "sqlite")]
+#[derive(Debug)]
+pub struct SqliteHistorySink {
+    conn: std::sync::Mutex<rusqlite::Connection>,
+}
+
+#[cfg(feature = "sqlite")]
+impl SqliteHistorySink {
+    /// Opens the database at `path`, creating it if needed
+    pub fn open(path: impl AsRef<std::path::Path>) -> Result<Self> {
+        let conn = rusqlite::Connection::open(path)?;
+        conn.execute_batch("CREATE TABLE IF NOT EXISTS history (at INTEGER NOT NULL, mac TEXT NOT NULL, name TEXT NOT NULL, value);
+            CREATE INDEX IF NOT EXISTS history_at ON history (at);")?;
+        Ok(Self { conn: std::sync::Mutex::new(conn) })
+    }
+}
+
+#[cfg(feature = "sqlite")]
+impl HistorySink for SqliteHistorySink {
+    fn record(&self, samples: &[HistorySample]) -> Result<()> {
+        use rusqlite::types::Value as SqlValue;
+        let mut conn = lock(&self.conn);
+        let tx = conn.transaction()?;
+        {
+            let mut insert = tx.prepare_cached("INSERT INTO history (at, mac, name, value) VALUES (?1, ?2, ?3, ?4)")?;
+            for s in samples {
+                let at = s.at.duration_since(SystemTime::UNIX_EPOCH).unwrap_or_default().as_millis() as i64;
+                let value = match &s.value {
+                    Value::Number(n) => n.as_i64().map(SqlValue::Integer).or(n.as_f64().map(SqlValue::Real)).unwrap_or(SqlValue::Null),
+                    Value::String(v) => SqlValue::Text(v.clone()),
+                    v => SqlValue::Text(v.to_string()),
+                };
+                insert.execute((at, s.mac.as_str(), s.name, value))?;
+            }
+        }
+        tx.commit()?;
+        Ok(())
+    }
+}
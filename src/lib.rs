@@ -1,12 +1,13 @@
 //! Controlling Gree Smart air conditioning units via Rust
 //! 
 //! This crate defines two clients, `GreeClient` and `Gree`, for each of two programming styles (synchronous and asynchronous). 
-//! Asynchronous clients require `tokio` feature.
+//! Asynchronous clients require `tokio`, `async-std` or `smol` feature.
 //! 
 //! * `GreeClient` is a low-level API
 //! * `Gree` is a high-level Gree protocol client. It maintains network state and provides a kind of automated workflow. 
 //! 
-//! See documentation under [sync_client] and [async_client].
+//! See documentation under [sync_client] and [async_client]. With `tokio`, a `Gree` may also be run as a background 
+//! service driven through a clonable handle, see [service].
 //!
 //! ## `Gree` high-level client
 //! 
@@ -14,16 +15,58 @@
 //! within `net_read`/`net_write`/`with_device` if necessary. More precisely:
 //! 
 //! * Bind is invoked if the [Device]'s `key` field is needed but empty
+//!   - the [ProtocolVariant] is negotiated during bind: if the bind fails with the device's current variant, the other one is tried
+//!   - if a response cannot be decrypted with the stored key (see [Error::is_key_failure]), the key is dropped, the device is 
+//!     rebound and the operation retried once
 //! * Scan is invoked if one of the following holds:
 //!   - the last scan performed is older than `max_scan_age`
 //!   - `net_read`/`net_write`/`with_device` is called against a device that is missing from the internal state
 //!   - there was a network error communicating with the device
 //!   - the scan was invoked explicitly
 //! * Scan is always bypassed if the last scan performed is younger than `min_scan_age`
+//! * Scan is never invoked implicitly for devices provisioned in [GreeConfig::devices], which are never evicted by scans either
+//! * Scan is never invoked implicitly at all if [GreeConfig::manual_discovery] is set
+//! * Scan results are merged into the devices known; a device is only evicted after missing [GreeConfig::max_missed_scans] 
+//!   consecutive scans, or not answering for [GreeConfig::max_device_age]. `Gree::remove_device` forgets a device on 
+//!   request
+//! * A known device reported at a new IP address (by a scan or an announcement, see [GreeConfig::listen_addr]) is updated 
+//!   in place, keeping its key; [GreeEvent::IpChanged] is emitted and the stored bindings are updated
+//! * A device that has not answered [GreeConfig::offline_threshold] consecutive requests is considered offline: operations 
+//!   on it fail with `Error::Offline` immediately, and are retried with exponential backoff (see [Device::online])
+//! 
+//! Operations on a device are serialized and spaced at least [GreeConfig::min_command_gap] apart. Writes may also be 
+//! queued with `queue_set` and sent later with `flush`, consecutive writes to the same variable being coalesced.
+//! 
+//! The sub-units of multi-split bridges (see [ScanResponsePack::sub_cnt]) are addressed by their own MAC addresses; 
+//! the requests are routed through the bridge, whose sub-units are listed on demand.
+//! 
+//! Device bindings (keys) may be persisted across restarts by setting [GreeConfig::store], see [StateStore]. The values 
+//! read by the background poller may be recorded by setting [GreeConfig::history], see [HistorySink]. With `tokio`, a
+//! `thermostat` may hold a room temperature measured by an external sensor. The datagrams exchanged with the devices 
+//! may be recorded and replayed offline, see [recording]. Tools talking to the devices on their own may reuse the 
+//! encoding of the messages and packs, see [protocol].
 //! 
 //! ## Features
 //! 
 //! * `tokio` - enable asynchronous clients with `tokio`
+//! * `async-std`, `smol` - enable asynchronous clients with `async-std` or `smol` instead. The other modules requiring 
+//!   `tokio` are not available with these. If several runtimes are enabled, `tokio` is preferred, then `async-std`.
+//! * `emulator` - enable the virtual Gree device `emulator`, for testing without real hardware
+//! * `toml` - enable loading [GreeConfig] from TOML files, see [config]
+//! * `server` - enable the embeddable HTTP REST `server` (implies `tokio`)
+//! * `auto-bcast` - enable detecting broadcast addresses from the local interfaces, see [GreeClientConfig::auto_bcast]
+//! * `sqlite` - enable recording the history of variable values into SQLite, see [SqliteHistorySink]
+//! * `scheduler` - enable the weekly time table executing timed commands, see `scheduler` (implies `tokio`)
+//! * `cloud` - enable controlling the devices through the Gree+ cloud API, see `cloud` (implies `tokio`)
+//! * `relay` - enable tunneling the datagrams to devices on a remote LAN through a relay agent, see `relay` 
+//!   (implies `tokio` and `auto-bcast`)
+//! * `cli` - build the `gree` command line binary (uses the async client if `tokio` is enabled)
+//! * `tui` - add the terminal dashboard `gree tui` to the binary (implies `cli` and `tokio`)
+//! * `grpc` - enable the gRPC control service `grpc`, declared in `proto/gree.proto` (implies `tokio`)
+//! * `encrypted-store` - enable encrypting the device keys persisted with a passphrase, see [EncryptedStateStore]
+//! * `tracing` - instrument the clients with `tracing` spans (scan, bind, getvars/setvars and the underlying exchanges, 
+//!   as well as `Gree`'s high-level operations) carrying the device MAC, IP and operation as fields. The crate keeps logging 
+//!   via `log`; install `tracing-log`'s `LogTracer` to have the log records attributed to the spans.
 //! 
 //! ## See also
 //! 
@@ -31,24 +74,120 @@
 
 mod apdu;
 mod state;
+mod store;
+mod history;
+mod policy;
+mod rt;
+pub mod config;
 pub mod sync_client;
 pub mod async_client;
+pub mod service;
+pub mod emulator;
+pub mod server;
+pub mod scheduler;
+pub mod thermostat;
+pub mod cloud;
+pub mod relay;
+pub mod grpc;
+pub mod recording;
+pub mod protocol;
 
 
-pub use apdu::vars;
+pub use apdu::{vars, ProtocolVariant, GenericMessage, ScanResponsePack, BindResponsePack, StatusResponsePack, CommandResponsePack, SubListResponsePack, SubDeviceInfo, DeviceInfoPack, ResultCode, R_OK, R_KEY_REJECTED};
 pub use state::*;
+pub use store::*;
+pub use history::*;
+pub use policy::*;
 pub use serde_json::Value;
 
 use apdu::{*, vars::VarName};
 use log::{trace, debug, error};
 
+/// Locks the mutex, ignoring poisoning: the state guarded is kept consistent by the lock holders, so it stays usable 
+/// after a panic elsewhere
+fn lock<T>(m: &std::sync::Mutex<T>) -> std::sync::MutexGuard<'_, T> {
+    m.lock().unwrap_or_else(|e| e.into_inner())
+}
+
 //pub type Error = Box<dyn std::error::Error>;
 pub type Result<T> = std::result::Result<T, Error>;
 
 const GENERIC_KEY: &str = "a3K8Bx%2r8Y7#xDh";
+const GENERIC_KEY_GCM: &str = "{yxAHAY_Lm6pbC/<";
 const PORT: u16 = 7000;
 
+/// Reason a received pack was rejected before (or after) decryption
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum InvalidPack {
+    /// Ciphertext length is zero or not a multiple of the AES block size
+    Length(usize),
+    /// PKCS#7 padding is malformed
+    Padding,
+    /// GCM tag is not 16 bytes long
+    TagLength(usize),
+    /// Key is not 16 bytes long
+    KeyLength(usize),
+}
+
+impl std::fmt::Display for InvalidPack {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Length(n) => write!(f, "invalid length {n}"),
+            Self::Padding => write!(f, "invalid padding"),
+            Self::TagLength(n) => write!(f, "invalid tag length {n}"),
+            Self::KeyLength(n) => write!(f, "invalid key length {n}"),
+        }
+    }
+}
+
+/// Network operation an error occurred in, see [ErrorContext]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operation {
+    Scan,
+    Bind,
+    Get,
+    Set,
+    /// Exchange of an arbitrary pack, see `Op::Raw`
+    Raw,
+}
+
+impl std::fmt::Display for Operation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Scan => "scan",
+            Self::Bind => "bind",
+            Self::Get => "get",
+            Self::Set => "set",
+            Self::Raw => "raw",
+        })
+    }
+}
+
+/// Where a network-path error occurred, see [Error::Context]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ErrorContext {
+    pub op: Operation,
+    /// The device addressed; `None` for scans
+    pub mac: Option<MacAddr>,
+    /// The address the request was sent to, if known
+    pub ip: Option<std::net::IpAddr>,
+}
+
+impl std::fmt::Display for ErrorContext {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.op)?;
+        if let Some(mac) = &self.mac { write!(f, " {mac}")? }
+        match (&self.mac, &self.ip) {
+            (Some(_), Some(ip)) => write!(f, " ({ip})"),
+            (None, Some(ip)) => write!(f, " {ip}"),
+            _ => Ok(())
+        }
+    }
+}
+
 #[derive(Debug)]
+#[non_exhaustive]
 pub enum Error {
     SerDe(serde_json::Error),
     Base64Decode(base64::DecodeError),
@@ -57,21 +196,105 @@ pub enum Error {
     RecvTimeout,
     RecvDisconnected,
     ParseInt(std::num::ParseIntError),
+    Crypto,
+    InvalidPack(InvalidPack),
+    Config(String),
 
     ResponseTimeout,
-    MacNotBound(String),
+    MacNotBound(MacAddr),
     NotFound(String),
+    Offline(MacAddr),
+    /// The device reported a result code other than 200
+    DeviceError { mac: MacAddr, code: i32 },
+    /// A string is not a MAC address, see [MacAddr]
+    InvalidMacAddr(String),
     InvalidVar(String),
     InvalidValue(VarName, String),
+    /// An operation on a group failed for some of the members, see [GreeConfig::groups]
+    Group(Vec<(MacAddr, Error)>),
+    /// The SQLite history sink failed, see [SqliteHistorySink]
+    #[cfg(feature = "sqlite")]
+    Sqlite(rusqlite::Error),
+    /// The cloud API reported an error, see `cloud::CloudClient`
+    Cloud(String),
+    /// The relay connection failed: a malformed frame, or the authentication failed, see `relay`
+    Relay(String),
+    /// An HTTP request to the cloud API failed
+    #[cfg(feature = "cloud")]
+    Http(reqwest::Error),
+    /// An error on the network path, with the operation and the device it occurred on. The clients attach the context
+    /// to the errors of scans and of the exchanges with the devices. Match [Error::root] to tell the errors apart 
+    /// regardless of the context, e.g. `matches!(e.root(), Error::ResponseTimeout)`.
+    Context(ErrorContext, Box<Error>),
 }
 
 impl Error {
     pub fn response_timeout() -> Self { Self::ResponseTimeout }
-    pub fn mac_not_bound(mac: &str) -> Self { Self::MacNotBound(mac.to_owned()) }
+    pub fn mac_not_bound(mac: &str) -> Self { Self::MacNotBound(mac.into()) }
     pub fn not_found(id: &str) -> Self { Self::NotFound(id.to_owned()) }
-    pub fn invalid_var(id: &str) -> Self { Self::NotFound(id.to_owned()) }
+    pub fn invalid_var(id: &str) -> Self { Self::InvalidVar(id.to_owned()) }
     pub fn invalid_value(var: VarName, value: &str) -> Self { Self::InvalidValue(var, value.to_owned()) }
     pub fn receiver_disconnected() -> Self { Self::RecvDisconnected }
+    pub fn offline(mac: &str) -> Self { Self::Offline(mac.into()) }
+
+    /// Attaches the context to the error, unless it carries one already (the innermost context is the most precise)
+    pub fn with_context(self, op: Operation, mac: Option<&str>, ip: Option<std::net::IpAddr>) -> Self {
+        match self {
+            Self::Context(..) => self,
+            e => Self::Context(ErrorContext { op, mac: mac.map(MacAddr::from), ip }, Box::new(e)),
+        }
+    }
+
+    /// The context of the error, if any
+    pub fn context(&self) -> Option<&ErrorContext> {
+        match self {
+            Self::Context(ctx, _) => Some(ctx),
+            _ => None
+        }
+    }
+
+    /// The error without its context
+    pub fn root(&self) -> &Error {
+        match self {
+            Self::Context(_, e) => e.root(),
+            e => e
+        }
+    }
+
+    /// The error without its context, by value
+    pub fn into_root(self) -> Error {
+        match self {
+            Self::Context(_, e) => e.into_root(),
+            e => e
+        }
+    }
+
+    /// True if the error indicates that the device did not answer. Of the I/O errors, only those of the kinds 
+    /// `TimedOut` and `WouldBlock` are timeouts; others (e.g. a broadcast denied, or the network unreachable) are not 
+    /// retried.
+    pub fn is_timeout(&self) -> bool { 
+        match self.root() {
+            Self::Io(e) => matches!(e.kind(), std::io::ErrorKind::TimedOut | std::io::ErrorKind::WouldBlock),
+            Self::RecvTimeout | Self::ResponseTimeout => true,
+            _ => false,
+        }
+    }
+
+    /// True if the error indicates that the device did not accept the key, i.e. the response could not be decrypted, 
+    /// or the device reported one of [R_KEY_REJECTED]. A response which decrypts but does not parse is not a key 
+    /// failure.
+    pub fn is_key_failure(&self) -> bool { 
+        match self.root() {
+            Self::Crypto | Self::InvalidPack(_) | Self::Base64Decode(_) => true,
+            Self::DeviceError { code, .. } => R_KEY_REJECTED.contains(code),
+            _ => false,
+        }
+    }
+}
+
+/// Runs `f`, attaching the context to its error (see [Error::with_context])
+fn in_context<T>(op: Operation, mac: Option<&str>, ip: Option<std::net::IpAddr>, f: impl FnOnce() -> Result<T>) -> Result<T> {
+    f().map_err(|e| e.with_context(op, mac, ip))
 }
 
 impl From<serde_json::Error> for Error {
@@ -86,6 +309,12 @@ impl From<base64::DecodeError> for Error {
     }
 }
 
+impl From<InvalidPack> for Error {
+    fn from(value: InvalidPack) -> Self {
+        Self::InvalidPack(value)
+    }
+}
+
 impl From<std::io::Error> for Error {
     fn from(value: std::io::Error) -> Self {
         Self::Io(value)
@@ -104,6 +333,20 @@ impl From<std::sync::mpsc::RecvTimeoutError> for Error {
     }
 }
 
+#[cfg(feature = "sqlite")]
+impl From<rusqlite::Error> for Error {
+    fn from(value: rusqlite::Error) -> Self {
+        Self::Sqlite(value)
+    }
+}
+
+#[cfg(feature = "cloud")]
+impl From<reqwest::Error> for Error {
+    fn from(value: reqwest::Error) -> Self {
+        Self::Http(value)
+    }
+}
+
 impl From<std::num::ParseIntError> for Error {
     fn from(value: std::num::ParseIntError) -> Self {
         Self::ParseInt(value)
@@ -115,20 +358,53 @@ impl std::fmt::Display for Error {
         match self {
             Self::Base64Decode(e) => write!(f, "Base64Decode: {e}"),
             Self::SerDe(e) => write!(f, "SerDe: {e}"),
-            Self::Io(e) => write!(f, "Base64Decode: {e}"),
+            Self::Io(e) => write!(f, "Io: {e}"),
             Self::Send => write!(f, "Send"),
             Self::RecvTimeout => write!(f, "RecvTimeout"),
             Self::RecvDisconnected => write!(f, "RecvDisconnected"),
 
             Self::ParseInt(e) => write!(f, "ParseInt: {e}"),
+            Self::Crypto => write!(f, "Crypto"),
+            Self::InvalidPack(e) => write!(f, "InvalidPack: {e}"),
+            Self::Config(s) => write!(f, "Config: {s}"),
 
             Self::ResponseTimeout => write!(f, "ResponseTimeout"),
             Self::MacNotBound(s) => write!(f, "MacNotBound: {s}"),
             Self::NotFound(s) => write!(f, "NotFound: {s}"),
+            Self::Offline(s) => write!(f, "Offline: {s}"),
+            Self::DeviceError { mac, code } => write!(f, "DeviceError: {mac} reported {code}"),
+            Self::InvalidMacAddr(s) => write!(f, "InvalidMacAddr: {s}"),
             Self::InvalidVar(s) => write!(f, "InvalidVar: {s}"),
             Self::InvalidValue(n, s) => write!(f, "InvalidValue for {n}: {s}"),
+            Self::Group(errors) => {
+                write!(f, "Group:")?;
+                for (mac, e) in errors { write!(f, " [{mac}] {e};")? }
+                Ok(())
+            }
+            #[cfg(feature = "sqlite")]
+            Self::Sqlite(e) => write!(f, "Sqlite: {e}"),
+            Self::Cloud(s) => write!(f, "Cloud: {s}"),
+            Self::Relay(s) => write!(f, "Relay: {s}"),
+            #[cfg(feature = "cloud")]
+            Self::Http(e) => write!(f, "Http: {e}"),
+            Self::Context(ctx, e) => write!(f, "{ctx}: {e}"),
         }
     }
 }
 
-impl std::error::Error for Error { }
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::SerDe(e) => Some(e),
+            Self::Base64Decode(e) => Some(e),
+            Self::Io(e) => Some(e),
+            Self::ParseInt(e) => Some(e),
+            #[cfg(feature = "sqlite")]
+            Self::Sqlite(e) => Some(e),
+            #[cfg(feature = "cloud")]
+            Self::Http(e) => Some(e),
+            Self::Context(_, e) => Some(e.as_ref()),
+            _ => None
+        }
+    }
+}
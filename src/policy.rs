@@ -0,0 +1,95 @@
+//! Energy-saving policies
+//!
+//! A [Policy] is a rule evaluated by the background poller (`async_client::Gree::spawn_poller`) for every device
+//! after each poll, e.g. "if no write was requested for 4 hours and the room is within 22..=24°C, enable `SvSt`".
+//! Policies are set in [GreeConfig::policies](crate::GreeConfig::policies). Rules may be implemented as trait objects
+//! or as closures wrapped in [FnPolicy]; [IdlePolicy] covers the common case.
+//!
+//! A policy sees the last known values of the device (see [GreeState::values]), so the variables it looks at should
+//! be among [GreeConfig::poll_vars](crate::GreeConfig::poll_vars). Writes made by the policies do not count as
+//! requested writes, and a policy fires at most once until a write is requested again.
+
+use std::{fmt::Debug, ops::RangeInclusive, time::Duration};
+use crate::{*, state::MacAddr};
+
+/// What a [Policy] is evaluated against
+#[derive(Debug, Clone)]
+pub struct PolicyContext<'a> {
+    pub mac: &'a MacAddr,
+    /// Last known values of the device's variables
+    pub values: &'a VarValues,
+    /// Time since a write to the device was last requested, or since the poller was started if none was
+    pub idle: Duration,
+    /// Range `SetTem` may be written within, see [GreeConfig::set_tem_range](crate::GreeConfig::set_tem_range)
+    pub set_tem_range: SetTemRange,
+}
+
+impl PolicyContext<'_> {
+    /// The room temperature in degrees Celsius, from `TemSen` (see [vars::TEM_SEN])
+    pub fn room_temperature(&self) -> Option<i64> {
+        vars::room_temperature(self.values.get(vars::TEM_SEN)?)
+    }
+
+    /// The set temperature in degrees Celsius
+    pub fn set_temperature(&self) -> Option<i64> {
+        self.values.get(vars::SET_TEM)?.as_i64()
+    }
+}
+
+/// A rule evaluated by the background poller, see module docs
+pub trait Policy: Debug + Send + Sync {
+    /// The variables to write to the device, if the policy fires
+    fn evaluate(&self, ctx: &PolicyContext) -> Option<Scene>;
+}
+
+/// [Policy] evaluating a closure
+pub struct FnPolicy<F>(pub F);
+
+impl<F> Debug for FnPolicy<F> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("FnPolicy")
+    }
+}
+
+impl<F: Fn(&PolicyContext) -> Option<Scene> + Send + Sync> Policy for FnPolicy<F> {
+    fn evaluate(&self, ctx: &PolicyContext) -> Option<Scene> {
+        (self.0)(ctx)
+    }
+}
+
+/// What an [IdlePolicy] does when it fires
+#[derive(Debug, Clone, PartialEq)]
+pub enum IdleAction {
+    /// Writes the variables
+    Set(Scene),
+    /// Changes `SetTem` by the number of degrees given, within the `SetTem` range
+    AdjustSetTem(i8),
+}
+
+/// [Policy] firing when no write was requested for `idle` and the room temperature is within `band`
+#[derive(Debug, Clone, PartialEq)]
+pub struct IdlePolicy {
+    pub idle: Duration,
+    /// Room temperature in degrees Celsius (see [PolicyContext::room_temperature]); any if `None`
+    pub band: Option<RangeInclusive<i64>>,
+    pub action: IdleAction,
+}
+
+impl Policy for IdlePolicy {
+    fn evaluate(&self, ctx: &PolicyContext) -> Option<Scene> {
+        if ctx.idle < self.idle { return None }
+        if let Some(band) = &self.band {
+            if !band.contains(&ctx.room_temperature()?) { return None }
+        }
+        match &self.action {
+            IdleAction::Set(scene) => Some(scene.clone()),
+            IdleAction::AdjustSetTem(by) => {
+                let range = ctx.set_tem_range;
+                let current = ctx.set_temperature()?;
+                let t = (current + *by as i64).clamp(range.min as i64, range.max as i64);
+                if t == current { return None }
+                Some(Scene { values: [(vars::SET_TEM, t.into())].into_iter().collect() })
+            }
+        }
+    }
+}
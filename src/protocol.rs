@@ -0,0 +1,33 @@
+//! Encoding and decoding of the messages exchanged with the devices, for tools talking to the devices on their own
+//!
+//! A datagram carries a JSON message: [GenericOutMessage] when sent to a device, [GenericMessage] when received. Except
+//! for the scan ([scan_request]) and the announcements, the message carries a pack, encrypted with AES-ECB
+//! ([ProtocolVariant::V1]) or AES-GCM ([ProtocolVariant::V2]) and base64-encoded. Binds are encrypted with the generic key
+//! of the variant ([ProtocolVariant::generic_key]), the other packs with the key the device hands out in the bind
+//! response.
+//!
+//! The request builders (e.g. [status_request]) return the message ready to be serialized and sent to port 7000;
+//! [handle_response] decrypts and parses the pack of a response. [encode_pack] and [decode_pack] work on the packs
+//! alone.
+//!
+//! ```
+//! # use gree::{*, protocol::*};
+//! # fn run() -> Result<()> {
+//! let key = ProtocolVariant::V1.generic_key();
+//! let request = status_request("f4911e000000", key, ProtocolVariant::V1, &["Pow", "SetTem"])?;
+//! let datagram = serde_json::to_vec(&request)?;
+//! # let _ = datagram;
+//! let pack = decode_pack(&request.pack, "", key, ProtocolVariant::V1)?;
+//! assert_eq!(pack, r#"{"cols":["Pow","SetTem"],"mac":"f4911e000000","t":"status"}"#);
+//! # Ok(())
+//! # }
+//! # run().unwrap();
+//! ```
+
+pub use crate::apdu::{
+    GenericMessage, GenericOutMessage, ProtocolVariant, SCAN_MESSAGE, scan_request, is_response,
+    bind_request, ScanResponsePack, BindResponsePack, R_OK, R_KEY_REJECTED, ResultCode, check_result,
+    status_request, sub_status_request, StatusResponsePack, setvar_request, sub_setvar_request, CommandResponsePack,
+    device_info_request, DeviceInfoPack, sub_list_request, SubListResponsePack, SubDeviceInfo, raw_request,
+    handle_response, encode_pack, decode_pack, encode_request, decode_response, encode_request_gcm, decode_response_gcm,
+};
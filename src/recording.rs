@@ -0,0 +1,380 @@
+//! Recording and replaying the datagrams exchanged with the devices
+//!
+//! The datagrams a low-level client sends and receives may be observed undecoded by setting a hook with 
+//! `GreeClient::on_raw_packet`; [RawPacketLogger] is a hook writing them to a file, as hex dumps or JSON lines.
+//!
+//! [Recorder] wraps the transport of a low-level client and writes every datagram sent and received to a file, one
+//! JSON object ([RecordedDatagram]) per line, with a timestamp and, for bind responses, the device key the response
+//! carries, so that the recorded packs may be decrypted later. [Replayer] is a transport feeding a recording back:
+//! the datagrams recorded as received are delivered in order, each batch once the request preceding it in the
+//! recording is sent. The requests sent are compared against the recorded ones, the differences being collected as
+//! [Mismatch]es. Protocol problems observed with a particular firmware may thus be reproduced offline, and the
+//! recordings kept as regression tests.
+//!
+//! Both are implemented for the transports of the sync client, and of the async client if it is enabled. The
+//! recordings contain the device keys, so they should be shared with care.
+//!
+//! Example usage:
+//!
+//! ```no_run
+//! # use std::sync::Arc;
+//! # use gree::{*, sync_client::*, recording::*};
+//! # fn run() -> Result<()> {
+//! let cfg = GreeClientConfig::default();
+//! // record
+//! let socket = std::net::UdpSocket::bind(cfg.bind_addr)?;
+//! socket.set_broadcast(true)?;
+//! let c = GreeClient::with_transport(cfg.clone(), Recorder::create(socket, "session.jsonl")?);
+//! c.scan()?;
+//! // replay
+//! let replayer = Arc::new(Replayer::load("session.jsonl")?);
+//! let c = GreeClient::with_transport(cfg, replayer.clone());
+//! c.scan()?;
+//! assert!(replayer.mismatches().is_empty());
+//! # Ok(())
+//! # }
+//! ```
+
+use std::{collections::VecDeque, io::{BufRead, BufWriter, Write}, net::SocketAddr, path::Path, sync::{Arc, Condvar, Mutex, PoisonError, RwLock}, time::{Duration, SystemTime}};
+use serde_derive::{Serialize, Deserialize};
+use crate::*;
+use crate::sync_client::Transport as SyncTransport;
+#[cfg(any(feature = "tokio", feature = "async-std", feature = "smol"))]
+use crate::async_client::Transport as AsyncTransport;
+
+/// Direction of a recorded datagram
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Direction {
+    Sent,
+    Received,
+}
+
+impl Direction {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Sent => "sent",
+            Self::Received => "received",
+        }
+    }
+}
+
+/// Hook called with every datagram a low-level client sends or receives, see `GreeClient::on_raw_packet`
+pub type RawPacketHook = Arc<dyn Fn(Direction, SocketAddr, &[u8]) + Send + Sync>;
+
+/// The hook of a client, shared with its receive loop
+#[derive(Clone, Default)]
+pub(crate) struct RawPacketTap(Arc<RwLock<Option<RawPacketHook>>>);
+
+impl RawPacketTap {
+    pub(crate) fn set(&self, hook: Option<RawPacketHook>) {
+        *self.0.write().unwrap_or_else(PoisonError::into_inner) = hook;
+    }
+
+    pub(crate) fn call(&self, dir: Direction, addr: SocketAddr, buf: &[u8]) {
+        // called outside the lock, so that the hook may replace itself
+        let hook = self.0.read().unwrap_or_else(PoisonError::into_inner).clone();
+        if let Some(hook) = hook { hook(dir, addr, buf) }
+    }
+}
+
+/// A line of a recording
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RecordedDatagram {
+    /// When the datagram was sent or received, in milliseconds since the Unix epoch
+    pub ts: u64,
+    pub dir: Direction,
+    /// Destination of a sent datagram, source of a received one
+    pub addr: SocketAddr,
+    /// The datagram, which is JSON text; invalid UTF-8 is replaced
+    pub data: String,
+    /// The device key carried by a bind response
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub key: Option<String>,
+}
+
+impl RecordedDatagram {
+    fn new(dir: Direction, addr: SocketAddr, buf: &[u8]) -> Self {
+        let ts = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).map(|d| d.as_millis() as u64).unwrap_or(0);
+        let key = if dir == Direction::Received { bind_key(buf) } else { None };
+        Self { ts, dir, addr, data: String::from_utf8_lossy(buf).into_owned(), key }
+    }
+}
+
+/// The key carried by `buf` if it is a bind response, which is encrypted with the generic key
+fn bind_key(buf: &[u8]) -> Option<String> {
+    let gm: GenericMessage = serde_json::from_slice(buf).ok()?;
+    if gm.t != "pack" || gm.pack.is_empty() { return None }
+    let variant = gm.variant();
+    let pack = decode_pack(&gm.pack, &gm.tag, variant.generic_key(), variant).ok()?;
+    let pack: BindResponsePack = serde_json::from_str(&pack).ok()?;
+    (pack.t == "bindok").then_some(pack.key)
+}
+
+/// Reads a recording, one [RecordedDatagram] per line; empty lines are skipped
+pub fn read_recording(r: impl BufRead) -> Result<Vec<RecordedDatagram>> {
+    let mut v = vec![];
+    for line in r.lines() {
+        let line = line?;
+        if line.trim().is_empty() { continue }
+        v.push(serde_json::from_str(&line)?);
+    }
+    Ok(v)
+}
+
+/// Format of the lines written by a [RawPacketLogger]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RawPacketFormat {
+    /// The timestamp (milliseconds since the Unix epoch), the direction, the address and the datagram in hex, 
+    /// separated by spaces
+    Hex,
+    /// [RecordedDatagram]s, which may be replayed by [Replayer]
+    #[default]
+    Json,
+}
+
+/// Writes the datagrams passed to [RawPacketLogger::log] to a file, a line each, e.g. from the hook of a client:
+///
+/// ```no_run
+/// # use gree::{*, sync_client::*, recording::*};
+/// # fn run() -> Result<()> {
+/// let c = GreeClient::new(GreeClientConfig::default())?;
+/// let logger = RawPacketLogger::create("traffic.txt", RawPacketFormat::Hex)?;
+/// c.on_raw_packet(move |dir, addr, buf| logger.log(dir, addr, buf));
+/// # Ok(())
+/// # }
+/// ```
+///
+/// The lines are flushed as written, so that the log survives a crash. Failures to write are logged and otherwise 
+/// ignored.
+pub struct RawPacketLogger {
+    format: RawPacketFormat,
+    out: Mutex<Box<dyn Write + Send>>,
+}
+
+impl RawPacketLogger {
+    pub fn new(out: impl Write + Send + 'static, format: RawPacketFormat) -> Self {
+        Self { format, out: Mutex::new(Box::new(out)) }
+    }
+
+    /// Writes to the file at `path`, which is truncated
+    pub fn create(path: impl AsRef<Path>, format: RawPacketFormat) -> Result<Self> {
+        Ok(Self::new(BufWriter::new(std::fs::File::create(path)?), format))
+    }
+
+    pub fn log(&self, dir: Direction, addr: SocketAddr, buf: &[u8]) {
+        let rd = RecordedDatagram::new(dir, addr, buf);
+        let mut out = lock(&self.out);
+        let r = match self.format {
+            RawPacketFormat::Hex => {
+                let hex: String = buf.iter().map(|b| format!("{b:02x}")).collect();
+                writeln!(out, "{} {} {addr} {hex}", rd.ts, dir.as_str())
+            }
+            RawPacketFormat::Json => serde_json::to_writer(&mut *out, &rd).map_err(std::io::Error::from)
+                .and_then(|()| out.write_all(b"\n")),
+        };
+        if let Err(e) = r.and_then(|()| out.flush()) { error!("raw packet log: {e}") }
+    }
+}
+
+/// Transport recording the datagrams passing through the inner transport, see module docs
+///
+/// Failures to write the recording are logged and do not affect the transport.
+pub struct Recorder<T> {
+    inner: T,
+    log: RawPacketLogger,
+}
+
+impl<T> Recorder<T> {
+    /// Records the datagrams of `inner` to `out`
+    pub fn new(inner: T, out: impl Write + Send + 'static) -> Self {
+        Self { inner, log: RawPacketLogger::new(out, RawPacketFormat::Json) }
+    }
+
+    /// Records the datagrams of `inner` to the file at `path`, which is truncated
+    pub fn create(inner: T, path: impl AsRef<Path>) -> Result<Self> {
+        Ok(Self { inner, log: RawPacketLogger::create(path, RawPacketFormat::Json)? })
+    }
+
+    /// The inner transport
+    pub fn inner(&self) -> &T {
+        &self.inner
+    }
+}
+
+impl<T: SyncTransport> SyncTransport for Recorder<T> {
+    fn send_to(&self, buf: &[u8], addr: SocketAddr) -> Result<usize> {
+        let n = self.inner.send_to(buf, addr)?;
+        self.log.log(Direction::Sent, addr, buf);
+        Ok(n)
+    }
+
+    fn recv_from(&self, buf: &mut [u8]) -> Result<(usize, SocketAddr)> {
+        let (n, addr) = self.inner.recv_from(buf)?;
+        self.log.log(Direction::Received, addr, &buf[..n]);
+        Ok((n, addr))
+    }
+
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> Result<()> {
+        self.inner.set_read_timeout(timeout)
+    }
+}
+
+#[cfg(any(feature = "tokio", feature = "async-std", feature = "smol"))]
+impl<T: AsyncTransport> AsyncTransport for Recorder<T> {
+    async fn send_to(&self, buf: &[u8], addr: SocketAddr) -> Result<usize> {
+        let n = self.inner.send_to(buf, addr).await?;
+        self.log.log(Direction::Sent, addr, buf);
+        Ok(n)
+    }
+
+    async fn recv_from(&self, buf: &mut [u8]) -> Result<(usize, SocketAddr)> {
+        let (n, addr) = self.inner.recv_from(buf).await?;
+        self.log.log(Direction::Received, addr, &buf[..n]);
+        Ok((n, addr))
+    }
+}
+
+/// A request sent during a replay that differs from the recording
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Mismatch {
+    /// The request recorded at this point, `None` if the recording has no more requests
+    pub expected: Option<RecordedDatagram>,
+    /// The request sent
+    pub actual: RecordedDatagram,
+}
+
+#[derive(Debug, Default)]
+struct ReplayState {
+    /// The rest of the recording, starting with a sent datagram
+    pending: VecDeque<RecordedDatagram>,
+    /// Received datagrams due for delivery
+    inbox: VecDeque<RecordedDatagram>,
+    mismatches: Vec<Mismatch>,
+    read_timeout: Option<Duration>,
+}
+
+impl ReplayState {
+    /// Moves the received datagrams at the head of `pending` to `inbox`
+    fn release(&mut self) {
+        while self.pending.front().is_some_and(|rd| rd.dir == Direction::Received) {
+            self.inbox.extend(self.pending.pop_front());
+        }
+    }
+
+    fn send(&mut self, buf: &[u8], addr: SocketAddr) {
+        let actual = RecordedDatagram::new(Direction::Sent, addr, buf);
+        let expected = self.pending.pop_front();
+        match &expected {
+            Some(rd) if rd.addr == actual.addr && rd.data == actual.data => (),
+            _ => {
+                debug!("replay: unexpected request to {addr}: {}", actual.data);
+                self.mismatches.push(Mismatch { expected, actual });
+            }
+        }
+        self.release();
+    }
+
+    fn recv(&mut self, buf: &mut [u8]) -> Option<(usize, SocketAddr)> {
+        let rd = self.inbox.pop_front()?;
+        // truncated like a datagram not fitting the buffer
+        let len = rd.data.len().min(buf.len());
+        buf[..len].copy_from_slice(&rd.data.as_bytes()[..len]);
+        Some((len, rd.addr))
+    }
+}
+
+/// Transport replaying a recording, see module docs
+///
+/// The responses are delivered as soon as the requests are sent, regardless of the recorded timing. Once the
+/// recording is exhausted, nothing more is received.
+#[derive(Debug)]
+pub struct Replayer {
+    s: Mutex<ReplayState>,
+    cond: Condvar,
+    #[cfg(any(feature = "tokio", feature = "async-std", feature = "smol"))]
+    notify: tokio::sync::Notify,
+}
+
+impl Replayer {
+    /// Replays the datagrams recorded
+    pub fn new(recording: impl IntoIterator<Item = RecordedDatagram>) -> Self {
+        let mut s = ReplayState { pending: recording.into_iter().collect(), ..Default::default() };
+        // announcements received before the first request
+        s.release();
+        Self {
+            s: Mutex::new(s),
+            cond: Condvar::new(),
+            #[cfg(any(feature = "tokio", feature = "async-std", feature = "smol"))]
+            notify: tokio::sync::Notify::new(),
+        }
+    }
+
+    /// Replays the recording in the file at `path`
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let f = std::io::BufReader::new(std::fs::File::open(path)?);
+        Ok(Self::new(read_recording(f)?))
+    }
+
+    /// The requests that differed from the recording so far
+    pub fn mismatches(&self) -> Vec<Mismatch> {
+        lock(&self.s).mismatches.clone()
+    }
+
+    /// True if all the recorded datagrams have been replayed
+    pub fn is_finished(&self) -> bool {
+        let s = lock(&self.s);
+        s.pending.is_empty() && s.inbox.is_empty()
+    }
+
+    fn send(&self, buf: &[u8], addr: SocketAddr) -> usize {
+        lock(&self.s).send(buf, addr);
+        self.cond.notify_all();
+        #[cfg(any(feature = "tokio", feature = "async-std", feature = "smol"))]
+        self.notify.notify_waiters();
+        buf.len()
+    }
+}
+
+impl SyncTransport for Replayer {
+    fn send_to(&self, buf: &[u8], addr: SocketAddr) -> Result<usize> {
+        Ok(self.send(buf, addr))
+    }
+
+    fn recv_from(&self, buf: &mut [u8]) -> Result<(usize, SocketAddr)> {
+        let mut s = lock(&self.s);
+        let deadline = s.read_timeout.map(|t| std::time::Instant::now() + t);
+        loop {
+            if let Some(r) = s.recv(buf) { return Ok(r) }
+            s = match deadline {
+                None => self.cond.wait(s).unwrap_or_else(std::sync::PoisonError::into_inner),
+                Some(deadline) => {
+                    let left = deadline.saturating_duration_since(std::time::Instant::now());
+                    if left.is_zero() { return Err(std::io::Error::from(std::io::ErrorKind::WouldBlock).into()) }
+                    self.cond.wait_timeout(s, left).unwrap_or_else(std::sync::PoisonError::into_inner).0
+                }
+            };
+        }
+    }
+
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> Result<()> {
+        lock(&self.s).read_timeout = timeout;
+        Ok(())
+    }
+}
+
+#[cfg(any(feature = "tokio", feature = "async-std", feature = "smol"))]
+impl AsyncTransport for Replayer {
+    async fn send_to(&self, buf: &[u8], addr: SocketAddr) -> Result<usize> {
+        Ok(self.send(buf, addr))
+    }
+
+    async fn recv_from(&self, buf: &mut [u8]) -> Result<(usize, SocketAddr)> {
+        loop {
+            // registered before checking, so that a send in between is not missed
+            let notified = self.notify.notified();
+            if let Some(r) = lock(&self.s).recv(buf) { return Ok(r) }
+            notified.await;
+        }
+    }
+}
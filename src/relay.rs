@@ -0,0 +1,281 @@
+//! Remote relay transport (requires `relay`)
+//!
+//! Devices on a remote LAN may be controlled by tunneling the datagrams through a relay agent running on that LAN.
+//! The agent ([run_agent]) accepts TCP connections; for each connection it binds a UDP socket, sends the datagrams
+//! received over the connection to the devices and sends back what the devices answer. On the controlling side,
+//! [RelayTransport] plugs into the async [GreeClient](crate::async_client::GreeClient) as its [Transport]; the scans
+//! are sent to the broadcast address of the remote LAN, which should be set as [GreeClientConfig::bcast_addr].
+//!
+//! The connections are authenticated with a secret shared by both sides ([AgentConfig::secret]): the agent sends a
+//! random challenge (12 bytes), the controlling side answers with its AES-GCM tag under the secret (16 bytes), and 
+//! the agent acknowledges with a single byte or closes the connection. The frames themselves are not encrypted (the 
+//! packs they carry are, with the device keys). The agent only sends the datagrams to the Gree port of the networks 
+//! of [AgentConfig::networks], dropping the others.
+//!
+//! Each datagram is sent as a frame: the length of the rest of the frame (`u32`, big endian), the length of the
+//! address (`u8`), the remote address as text (e.g. `192.168.1.20:7000`) and the datagram itself.
+//!
+//! Example usage:
+//!
+//! ```no_run
+//! # use gree::{*, async_client::*, relay::*};
+//! # async fn run() -> Result<()> {
+//! // on the remote LAN
+//! let agent = AgentConfig::new(([192, 168, 1, 2], 7007).into(), "Zq8#xW4!pL2@vN6$");
+//! tokio::spawn(run_agent(agent));
+//! // on the controlling side
+//! let mut cfg = GreeConfig::default();
+//! cfg.client_config.bcast_addr = [192, 168, 1, 255].into();
+//! let transport = RelayTransport::connect("relay.example.com:7007", "Zq8#xW4!pL2@vN6$").await?;
+//! let gree = Gree::with_client(cfg.clone(), GreeClient::with_transport(cfg.client_config, transport));
+//! # Ok(())
+//! # }
+//! ```
+
+#![cfg(feature = "relay")]
+
+use std::{net::{IpAddr, Ipv4Addr, SocketAddr}, time::Duration};
+use aes_gcm::{Aes128Gcm, KeyInit, Nonce, AeadInPlace, aead::{OsRng, rand_core::RngCore}};
+use tokio::{io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt}, net::{TcpListener, TcpStream, ToSocketAddrs, UdpSocket, tcp::{OwnedReadHalf, OwnedWriteHalf}}, sync::Mutex};
+use crate::{*, async_client::Transport};
+
+/// The largest frame accepted; datagrams are far smaller
+pub const MAX_FRAME: usize = 65536;
+
+/// How long the agent waits for the controlling side to authenticate
+pub const AUTH_TIMEOUT: Duration = Duration::from_secs(10);
+
+const AUTH_AAD: &[u8] = b"gree-relay";
+
+/// Relay agent configuration
+#[derive(Debug, Clone)]
+pub struct AgentConfig {
+    /// Socket addr to accept the connections at. Bind to the LAN address rather than to all the interfaces unless the 
+    /// agent is meant to be reachable from elsewhere.
+    pub addr: SocketAddr,
+    /// Secret the controlling side authenticates with, 16 bytes long (like the device keys)
+    pub secret: String,
+    /// Networks the datagrams may be sent to, as address and prefix length (e.g. `192.168.1.0/24` is 
+    /// `([192, 168, 1, 0].into(), 24)`). The limited broadcast address (`255.255.255.255`) is always allowed. If empty,
+    /// the networks of the local IPv4 interfaces, loopback excluded.
+    pub networks: Vec<(Ipv4Addr, u8)>,
+}
+
+impl AgentConfig {
+    /// Configuration relaying to the networks of the local interfaces
+    pub fn new(addr: SocketAddr, secret: &str) -> Self {
+        Self { addr, secret: secret.to_owned(), networks: vec![] }
+    }
+}
+
+/// The AES-GCM tag of the challenge under the secret, which proves the knowledge of the secret
+fn auth_tag(secret: &str, challenge: &[u8; 12]) -> Result<[u8; 16]> {
+    let cipher = Aes128Gcm::new_from_slice(secret.as_bytes())
+        .map_err(|_| Error::Config("relay: the secret must be 16 bytes long".to_owned()))?;
+    let tag = cipher.encrypt_in_place_detached(Nonce::from_slice(challenge), AUTH_AAD, &mut [])
+        .map_err(|_| Error::Crypto)?;
+    Ok(tag.into())
+}
+
+/// Networks of the local IPv4 interfaces, loopback excluded
+fn local_networks() -> Result<Vec<(Ipv4Addr, u8)>> {
+    let mut networks = vec![];
+    for i in if_addrs::get_if_addrs()? {
+        let if_addrs::IfAddr::V4(a) = i.addr else { continue };
+        if a.is_loopback() { continue }
+        networks.push((a.ip, u32::from(a.netmask).leading_ones() as u8));
+    }
+    Ok(networks)
+}
+
+/// True if the agent may send a datagram to `addr`: the Gree port on one of the networks, or the limited broadcast
+fn is_allowed(networks: &[(Ipv4Addr, u8)], addr: SocketAddr) -> bool {
+    let IpAddr::V4(ip) = addr.ip() else { return false };
+    if addr.port() != PORT { return false }
+    ip.is_broadcast() || networks.iter().any(|(net, prefix)| {
+        let mask = u32::MAX.checked_shl(32 - u32::from((*prefix).min(32))).unwrap_or(0);
+        u32::from(ip) & mask == u32::from(*net) & mask
+    })
+}
+
+/// Writes a frame carrying the datagram `buf` and the remote address `addr`
+pub async fn write_frame<W: AsyncWrite + Unpin>(w: &mut W, addr: SocketAddr, buf: &[u8]) -> Result<()> {
+    let addr = addr.to_string();
+    let mut frame = Vec::with_capacity(5 + addr.len() + buf.len());
+    frame.extend_from_slice(&((1 + addr.len() + buf.len()) as u32).to_be_bytes());
+    frame.push(addr.len() as u8);
+    frame.extend_from_slice(addr.as_bytes());
+    frame.extend_from_slice(buf);
+    w.write_all(&frame).await?;
+    Ok(())
+}
+
+/// Reads a frame, returning the remote address and the datagram
+pub async fn read_frame<R: AsyncRead + Unpin>(r: &mut R) -> Result<(SocketAddr, Vec<u8>)> {
+    let len = r.read_u32().await? as usize;
+    if len == 0 || len > MAX_FRAME { return Err(Error::Relay(format!("invalid frame length {len}"))) }
+    let mut frame = vec![0u8; len];
+    r.read_exact(&mut frame).await?;
+    let addr_len = frame[0] as usize;
+    let addr = frame.get(1..1 + addr_len)
+        .and_then(|a| std::str::from_utf8(a).ok())
+        .and_then(|a| a.parse().ok())
+        .ok_or_else(|| Error::Relay("invalid frame address".to_owned()))?;
+    Ok((addr, frame.split_off(1 + addr_len)))
+}
+
+/// [Transport] tunneling the datagrams to a relay agent over TCP, see module docs
+#[derive(Debug)]
+pub struct RelayTransport {
+    r: Mutex<OwnedReadHalf>,
+    w: Mutex<OwnedWriteHalf>,
+}
+
+impl RelayTransport {
+    /// Connects to the relay agent and authenticates with the shared secret (see [AgentConfig::secret])
+    pub async fn connect(agent: impl ToSocketAddrs, secret: &str) -> Result<Self> {
+        let mut s = TcpStream::connect(agent).await?;
+        s.set_nodelay(true)?;
+        let mut challenge = [0u8; 12];
+        s.read_exact(&mut challenge).await?;
+        s.write_all(&auth_tag(secret, &challenge)?).await?;
+        match s.read_u8().await {
+            Ok(1) => (),
+            _ => return Err(Error::Relay("authentication rejected by the agent".to_owned())),
+        }
+        debug!("relay: connected to {}", s.peer_addr()?);
+        let (r, w) = s.into_split();
+        Ok(Self { r: Mutex::new(r), w: Mutex::new(w) })
+    }
+}
+
+impl Transport for RelayTransport {
+    async fn send_to(&self, buf: &[u8], addr: SocketAddr) -> Result<usize> {
+        write_frame(&mut *self.w.lock().await, addr, buf).await?;
+        Ok(buf.len())
+    }
+
+    async fn recv_from(&self, buf: &mut [u8]) -> Result<(usize, SocketAddr)> {
+        // the connection failing is not transient (unlike the errors of a UDP socket), so the client stops receiving
+        let (addr, datagram) = read_frame(&mut *self.r.lock().await).await.map_err(|e| {
+            error!("relay: {e}");
+            Error::receiver_disconnected()
+        })?;
+        // truncated like a datagram not fitting the buffer
+        let len = datagram.len().min(buf.len());
+        buf[..len].copy_from_slice(&datagram[..len]);
+        Ok((len, addr))
+    }
+}
+
+/// Runs the relay agent, accepting connections until failing to accept
+pub async fn run_agent(cfg: AgentConfig) -> Result<()> {
+    auth_tag(&cfg.secret, &[0; 12])?;
+    let networks: std::sync::Arc<[_]> = match cfg.networks.is_empty() {
+        true => local_networks()?.into(),
+        false => cfg.networks.into(),
+    };
+    let secret: std::sync::Arc<str> = cfg.secret.into();
+    let listener = TcpListener::bind(cfg.addr).await?;
+    debug!("relay agent: listening at {:?}, relaying to {networks:?}", listener.local_addr());
+    loop {
+        let (s, peer) = listener.accept().await?;
+        let (networks, secret) = (networks.clone(), secret.clone());
+        tokio::spawn(async move {
+            match serve_connection(s, &secret, &networks).await {
+                Ok(()) => debug!("relay agent: [{peer}] closed"),
+                Err(e) => debug!("relay agent: [{peer}] {e}"),
+            }
+        });
+    }
+}
+
+/// Checks the answer of the controlling side to a random challenge, see module docs
+async fn authenticate(s: &mut TcpStream, secret: &str) -> Result<()> {
+    let mut challenge = [0u8; 12];
+    OsRng.fill_bytes(&mut challenge);
+    s.write_all(&challenge).await?;
+    let mut tag = [0u8; 16];
+    tokio::time::timeout(AUTH_TIMEOUT, s.read_exact(&mut tag)).await
+        .map_err(|_| Error::Relay("authentication timed out".to_owned()))??;
+    // compared in constant time
+    let diff = auth_tag(secret, &challenge)?.iter().zip(tag).fold(0, |d, (a, b)| d | (a ^ b));
+    if diff != 0 { return Err(Error::Relay("authentication failed".to_owned())) }
+    s.write_u8(1).await?;
+    Ok(())
+}
+
+/// Relays the datagrams of one connection through a UDP socket of its own. The directions are served by separate 
+/// futures, as reading a frame is not cancel safe.
+async fn serve_connection(mut s: TcpStream, secret: &str, networks: &[(Ipv4Addr, u8)]) -> Result<()> {
+    s.set_nodelay(true)?;
+    authenticate(&mut s, secret).await?;
+    let udp = UdpSocket::bind(("0.0.0.0", 0)).await?;
+    udp.set_broadcast(true)?;
+    let (mut r, mut w) = s.into_split();
+    let to_devices = async {
+        loop {
+            let (addr, datagram) = match read_frame(&mut r).await {
+                Err(Error::Io(e)) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(()),
+                frame => frame?,
+            };
+            if !is_allowed(networks, addr) {
+                error!("relay agent: dropping datagram to {addr}, which is not the Gree port of the networks relayed to");
+                continue
+            }
+            trace!("relay agent: => {addr} {}", String::from_utf8_lossy(&datagram));
+            udp.send_to(&datagram, addr).await?;
+        }
+    };
+    let from_devices = async {
+        let mut b = vec![0u8; MAX_FRAME];
+        loop {
+            let (len, addr) = udp.recv_from(&mut b).await?;
+            trace!("relay agent: <= {addr} {}", String::from_utf8_lossy(&b[..len]));
+            write_frame(&mut w, addr, &b[..len]).await?;
+        }
+    };
+    tokio::select! {
+        r = to_devices => r,
+        r = from_devices => r,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECRET: &str = "0123456789abcdef";
+
+    #[test]
+    fn destinations_are_limited_to_the_networks() {
+        let networks = [([192, 168, 1, 0].into(), 24), ([10, 0, 0, 7].into(), 8)];
+        let allowed = |addr: &str| is_allowed(&networks, addr.parse().unwrap());
+        assert!(allowed("192.168.1.20:7000"));
+        assert!(allowed("192.168.1.255:7000"));
+        assert!(allowed("10.20.30.40:7000"));
+        assert!(allowed("255.255.255.255:7000"));
+        assert!(!allowed("192.168.1.20:7001"));
+        assert!(!allowed("192.168.2.20:7000"));
+        assert!(!allowed("127.0.0.1:7000"));
+        assert!(!allowed("[::1]:7000"));
+        assert!(is_allowed(&[([0, 0, 0, 0].into(), 0)], "8.8.8.8:7000".parse().unwrap()));
+    }
+
+    #[tokio::test]
+    async fn connections_are_authenticated() {
+        let listener = TcpListener::bind(("127.0.0.1", 0)).await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let agent = tokio::spawn(async move {
+            let mut results = vec![];
+            for _ in 0..2 {
+                let (mut s, _) = listener.accept().await.unwrap();
+                results.push(authenticate(&mut s, SECRET).await.map_err(|e| e.to_string()));
+            }
+            results
+        });
+        assert!(matches!(RelayTransport::connect(addr, "fedcba9876543210").await, Err(Error::Relay(_))));
+        RelayTransport::connect(addr, SECRET).await.unwrap();
+        assert_eq!(agent.await.unwrap(), [Err("Relay: authentication failed".to_owned()), Ok(())]);
+    }
+}
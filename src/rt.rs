@@ -0,0 +1,131 @@
+//! Async runtime abstraction of the async client
+//!
+//! The runtime is selected by the `tokio`, `async-std` or `smol` feature; if several are enabled, they are preferred
+//! in this order. The synchronization primitives (`tokio::sync`) do not depend on the runtime, so only the sockets,
+//! timers and tasks are abstracted here.
+
+#![cfg(any(feature = "tokio", feature = "async-std", feature = "smol"))]
+
+use std::{future::Future, time::{Duration, Instant}};
+
+#[cfg(feature = "tokio")]
+mod imp {
+    use super::*;
+
+    pub use tokio::{net::UdpSocket, task::JoinHandle};
+
+    pub fn spawn<F: Future<Output = ()> + Send + 'static>(f: F) -> JoinHandle<()> {
+        tokio::spawn(f)
+    }
+
+    pub async fn sleep(d: Duration) {
+        tokio::time::sleep(d).await
+    }
+
+    pub async fn timeout<F: Future>(d: Duration, f: F) -> Option<F::Output> {
+        tokio::time::timeout(d, f).await.ok()
+    }
+
+    pub fn udp_socket_from_std(s: std::net::UdpSocket) -> std::io::Result<UdpSocket> {
+        s.set_nonblocking(true)?;
+        UdpSocket::from_std(s)
+    }
+}
+
+#[cfg(all(feature = "async-std", not(feature = "tokio")))]
+mod imp {
+    use super::*;
+
+    pub use async_std::net::UdpSocket;
+
+    /// Handle of a spawned task. Dropping the handle detaches the task.
+    #[derive(Debug)]
+    pub struct JoinHandle<T>(std::sync::Mutex<Option<async_std::task::JoinHandle<T>>>);
+
+    impl<T: Send + 'static> JoinHandle<T> {
+        /// Cancels the task
+        pub fn abort(&self) {
+            if let Some(t) = crate::lock(&self.0).take() {
+                async_std::task::spawn(async move { t.cancel().await; });
+            }
+        }
+    }
+
+    pub fn spawn<F: Future<Output = ()> + Send + 'static>(f: F) -> JoinHandle<()> {
+        JoinHandle(std::sync::Mutex::new(Some(async_std::task::spawn(f))))
+    }
+
+    pub async fn sleep(d: Duration) {
+        async_std::task::sleep(d).await
+    }
+
+    pub async fn timeout<F: Future>(d: Duration, f: F) -> Option<F::Output> {
+        async_std::future::timeout(d, f).await.ok()
+    }
+
+    pub fn udp_socket_from_std(s: std::net::UdpSocket) -> std::io::Result<UdpSocket> {
+        s.set_nonblocking(true)?;
+        Ok(UdpSocket::from(s))
+    }
+}
+
+#[cfg(all(feature = "smol", not(any(feature = "tokio", feature = "async-std"))))]
+mod imp {
+    use super::*;
+
+    pub use smol::net::UdpSocket;
+
+    /// Handle of a spawned task. Dropping the handle detaches the task.
+    #[derive(Debug)]
+    pub struct JoinHandle<T>(std::sync::Mutex<Option<smol::Task<T>>>);
+
+    impl<T> JoinHandle<T> {
+        /// Cancels the task
+        pub fn abort(&self) {
+            crate::lock(&self.0).take();
+        }
+    }
+
+    impl<T> Drop for JoinHandle<T> {
+        fn drop(&mut self) {
+            if let Some(t) = crate::lock(&self.0).take() { t.detach() }
+        }
+    }
+
+    pub fn spawn<F: Future<Output = ()> + Send + 'static>(f: F) -> JoinHandle<()> {
+        JoinHandle(std::sync::Mutex::new(Some(smol::spawn(f))))
+    }
+
+    pub async fn sleep(d: Duration) {
+        smol::Timer::after(d).await;
+    }
+
+    pub async fn timeout<F: Future>(d: Duration, f: F) -> Option<F::Output> {
+        smol::future::or(async { Some(f.await) }, async { smol::Timer::after(d).await; None }).await
+    }
+
+    pub fn udp_socket_from_std(s: std::net::UdpSocket) -> std::io::Result<UdpSocket> {
+        UdpSocket::try_from(s)
+    }
+}
+
+pub use imp::*;
+
+/// Ticks every `period`, the first tick completing immediately. A missed tick delays the following ones, rather than
+/// firing them in a burst.
+pub struct Interval {
+    next: Instant,
+    period: Duration,
+}
+
+impl Interval {
+    pub fn new(period: Duration) -> Self {
+        Self { next: Instant::now(), period }
+    }
+
+    pub async fn tick(&mut self) {
+        let now = Instant::now();
+        if self.next > now { sleep(self.next - now).await }
+        self.next = self.next.max(now) + self.period;
+    }
+}
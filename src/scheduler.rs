@@ -0,0 +1,249 @@
+//! Scheduler for timed commands (requires `scheduler`)
+//!
+//! A [Schedule] is a weekly time table of named [ScheduleEntry]s: each fires at a local time of day on some days of
+//! the week, applying a scene (see [GreeConfig::scenes]) or writing variables to a target, which may be a device, an
+//! alias or a group. The [Scheduler] keeps the schedule, persisting it to a JSON file if one is given, and executes it
+//! in a background task against a shared async [Gree].
+//!
+//! Example usage:
+//!
+//! ```no_run
+//! # use std::sync::Arc;
+//! # use gree::{*, async_client::*, scheduler::*};
+//! # async fn run() -> Result<()> {
+//! let gree = Arc::new(Gree::new(GreeConfig::default()).await?);
+//! let scheduler = Arc::new(Scheduler::load("/var/lib/gree/schedule.json")?);
+//! let at = NaiveTime::from_hms_opt(22, 30, 0).unwrap();
+//! scheduler.insert("bedtime", ScheduleEntry::new(at, "bedrooms", Action::Scene("night".to_owned())))?;
+//! Scheduler::spawn(scheduler, gree);
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! The schedule file looks like this:
+//!
+//! ```json
+//! {
+//!   "bedtime": { "at": "22:30", "days": ["Mon", "Tue", "Wed", "Thu", "Fri"], "target": "bedrooms", "action": { "Scene": "night" } },
+//!   "wakeup": { "at": "07:00", "target": "living", "action": { "Set": { "Pow": "1", "Mod": "heat" } } }
+//! }
+//! ```
+
+#![cfg(feature = "scheduler")]
+
+use std::{collections::{BTreeMap, HashMap}, path::PathBuf, sync::{Arc, Mutex}, time::Duration};
+use chrono::{DateTime, Datelike, Local, TimeZone};
+use serde_derive::{Serialize, Deserialize};
+use tokio::{sync::Notify, task::JoinHandle, time};
+use crate::{*, async_client::*};
+
+pub use chrono::{NaiveTime, Weekday};
+
+/// What a [ScheduleEntry] does when it fires
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Action {
+    /// Applies the scene named, see [Gree::apply_scene]
+    Scene(String),
+    /// Writes the variables (name => value, as accepted by [vars::parse_value])
+    Set(HashMap<String, String>),
+}
+
+impl Action {
+    /// Checks that the variables of `Set` are valid
+    pub fn validate(&self) -> Result<()> {
+        match self {
+            Self::Scene(_) => Ok(()),
+            Self::Set(values) => Scene::parse(values.iter()).map(|_| ()),
+        }
+    }
+}
+
+/// An entry of the [Schedule]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ScheduleEntry {
+    /// Local time of day the entry fires at
+    pub at: NaiveTime,
+    /// Days of week the entry fires on; every day if empty
+    #[serde(default)]
+    pub days: Vec<Weekday>,
+    /// Device (MAC address or alias) or group
+    pub target: String,
+    pub action: Action,
+}
+
+impl ScheduleEntry {
+    /// Creates an entry firing every day
+    pub fn new(at: NaiveTime, target: &str, action: Action) -> Self {
+        Self { at, days: vec![], target: target.to_owned(), action }
+    }
+
+    /// The first time the entry fires after `t`
+    pub fn next_after(&self, t: DateTime<Local>) -> Option<DateTime<Local>> {
+        (0..=7).filter_map(|d| t.date_naive().checked_add_days(chrono::Days::new(d)))
+            .filter(|date| self.days.is_empty() || self.days.contains(&date.weekday()))
+            // a time skipped by a DST change yields nothing for that day
+            .filter_map(|date| Local.from_local_datetime(&date.and_time(self.at)).earliest())
+            .find(|fire| *fire > t)
+    }
+}
+
+/// Named schedule entries
+pub type Schedule = BTreeMap<String, ScheduleEntry>;
+
+/// Keeps and executes the [Schedule], see module docs
+#[derive(Debug, Default)]
+pub struct Scheduler {
+    path: Option<PathBuf>,
+    entries: Mutex<Schedule>,
+    changed: Notify,
+}
+
+impl Scheduler {
+    /// The longest the scheduler sleeps before re-checking the clock, so that clock changes are noticed
+    pub const MAX_SLEEP: Duration = Duration::from_secs(60);
+
+    /// Creates a scheduler with the schedule given, which is not persisted
+    pub fn new(schedule: Schedule) -> Self {
+        Self { path: None, entries: Mutex::new(schedule), changed: Notify::new() }
+    }
+
+    /// Creates a scheduler persisting its schedule to the JSON file at `path`. The schedule is loaded from the file,
+    /// if it exists.
+    pub fn load(path: impl Into<PathBuf>) -> Result<Self> {
+        let path = path.into();
+        let schedule: Schedule = match std::fs::read(&path) {
+            Ok(b) => serde_json::from_slice(&b)?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Schedule::new(),
+            Err(e) => Err(e)?,
+        };
+        for (name, entry) in &schedule {
+            entry.action.validate().map_err(|e| Error::Config(format!("schedule entry `{name}`: {e}")))?;
+        }
+        Ok(Self { path: Some(path), ..Self::new(schedule) })
+    }
+
+    /// The schedule
+    pub fn entries(&self) -> Schedule {
+        lock(&self.entries).clone()
+    }
+
+    /// Adds the entry, replacing the one of the same name
+    pub fn insert(&self, name: &str, entry: ScheduleEntry) -> Result<()> {
+        entry.action.validate()?;
+        let mut entries = lock(&self.entries);
+        entries.insert(name.to_owned(), entry);
+        self.save(&entries)?;
+        self.changed.notify_one();
+        Ok(())
+    }
+
+    /// Removes the entry named, returning it
+    pub fn remove(&self, name: &str) -> Result<Option<ScheduleEntry>> {
+        let mut entries = lock(&self.entries);
+        let entry = entries.remove(name);
+        if entry.is_some() {
+            self.save(&entries)?;
+            self.changed.notify_one();
+        }
+        Ok(entry)
+    }
+
+    fn save(&self, entries: &Schedule) -> Result<()> {
+        let Some(path) = &self.path else { return Ok(()) };
+        trace!("saving {} schedule entries to {:?}", entries.len(), path);
+        let tmp = path.with_extension("tmp");
+        std::fs::write(&tmp, serde_json::to_vec_pretty(entries)?)?;
+        std::fs::rename(tmp, path)?;
+        Ok(())
+    }
+
+    /// Spawns the task executing the schedule against `gree`. Entries due while the task was not running are not
+    /// executed. Abort the task returned to stop.
+    pub fn spawn<C: GreeClientApi + 'static>(scheduler: Arc<Self>, gree: Arc<Gree<C>>) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            let mut last = Local::now();
+            loop {
+                let now = Local::now();
+                // the clock was set back
+                if now < last { last = now }
+                let due: Vec<(String, ScheduleEntry)> = lock(&scheduler.entries).iter()
+                    .filter(|(_, e)| e.next_after(last).is_some_and(|t| t <= now))
+                    .map(|(n, e)| (n.clone(), e.clone()))
+                    .collect();
+                last = now;
+                for (name, entry) in due {
+                    let gree = gree.clone();
+                    tokio::spawn(async move { Self::execute(&gree, &name, &entry).await });
+                }
+                let next = lock(&scheduler.entries).values().filter_map(|e| e.next_after(now)).min();
+                let sleep = next.and_then(|t| (t - now).to_std().ok()).unwrap_or(Self::MAX_SLEEP).min(Self::MAX_SLEEP);
+                tokio::select! {
+                    _ = time::sleep(sleep) => (),
+                    _ = scheduler.changed.notified() => (),
+                }
+            }
+        })
+    }
+
+    async fn execute<C: GreeClientApi>(gree: &Gree<C>, name: &str, entry: &ScheduleEntry) {
+        debug!("schedule: `{name}` fired for {}", entry.target);
+        let r = match &entry.action {
+            Action::Scene(scene) => gree.apply_scene(&entry.target, scene).await,
+            Action::Set(values) => match Scene::parse(values.iter()) {
+                Ok(scene) => gree.net_write(&entry.target, &mut scene.to_net_var_bag()).await,
+                Err(e) => Err(e),
+            }
+        };
+        if let Err(e) = r { error!("schedule: `{name}`: {e}") }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+
+    fn hm(h: u32, m: u32) -> NaiveTime { NaiveTime::from_hms_opt(h, m, 0).unwrap() }
+
+    /// 2024-01-10, a Wednesday, at `h:m` local time
+    fn wed(h: u32, m: u32) -> DateTime<Local> {
+        Local.from_local_datetime(&NaiveDate::from_ymd_opt(2024, 1, 10).unwrap().and_time(hm(h, m))).unwrap()
+    }
+
+    fn entry(h: u32, m: u32, days: &[Weekday]) -> ScheduleEntry {
+        ScheduleEntry { days: days.to_vec(), ..ScheduleEntry::new(hm(h, m), "living", Action::Scene("night".to_owned())) }
+    }
+
+    #[test]
+    fn later_the_same_day() {
+        assert_eq!(entry(22, 30, &[]).next_after(wed(12, 0)), Some(wed(22, 30)));
+    }
+
+    #[test]
+    fn passed_today_rolls_to_tomorrow() {
+        let next = entry(7, 0, &[]).next_after(wed(12, 0)).unwrap();
+        assert_eq!((next.weekday(), next.time()), (Weekday::Thu, hm(7, 0)));
+        // firing at `t` exactly is not after `t`
+        let next = entry(12, 0, &[]).next_after(wed(12, 0)).unwrap();
+        assert_eq!((next.weekday(), next.time()), (Weekday::Thu, hm(12, 0)));
+    }
+
+    #[test]
+    fn skips_to_the_next_matching_day() {
+        let next = entry(7, 0, &[Weekday::Mon, Weekday::Fri]).next_after(wed(12, 0)).unwrap();
+        assert_eq!((next.date_naive(), next.time()), (NaiveDate::from_ymd_opt(2024, 1, 12).unwrap(), hm(7, 0)));
+        // the same weekday a week later, once passed today
+        let next = entry(7, 0, &[Weekday::Wed]).next_after(wed(12, 0)).unwrap();
+        assert_eq!(next.date_naive(), NaiveDate::from_ymd_opt(2024, 1, 17).unwrap());
+    }
+
+    #[test]
+    fn empty_days_fire_daily() {
+        let e = entry(7, 0, &[]);
+        let mut t = wed(12, 0);
+        for day in [Weekday::Thu, Weekday::Fri, Weekday::Sat, Weekday::Sun, Weekday::Mon] {
+            t = e.next_after(t).unwrap();
+            assert_eq!(t.weekday(), day);
+        }
+    }
+}
@@ -0,0 +1,309 @@
+//! Embeddable HTTP REST service (requires `server`)
+//!
+//! Exposes an async [Gree] over HTTP with the following routes:
+//!
+//! * `GET /scan` - performs a scan, returns the MACs of the devices known afterwards (found by this or earlier scans)
+//! * `GET /dev` - returns the MACs of the devices known
+//! * `GET /dev/<device>` - returns information about the device
+//! * `GET /dev/<device>/get?<name>&...` - reads the variables, returns their values
+//! * `GET /dev/<device>/set?<name>=<value>&...` - writes the variables, returns the values reported by the device
+//! * `GET /ws` - WebSocket pushing [GreeEvent]s as JSON text frames, e.g. 
+//!   `{"event":"VarChanged","mac":"000cc0000001","name":"Pow","old":0,"new":1}`
+//! * `GET /openapi.json` - returns the OpenAPI document describing the routes, see [openapi]
+//!
+//! Events are only produced while the `Gree` instance is in use, so a dashboard relying on `/ws` would normally be served with 
+//! [serve_shared] alongside the poller (see [Gree::spawn_poller]).
+//!
+//! `<device>` is a MAC address or an alias. Errors are returned as `{"code": ..., "message": ...}` with the status code
+//! derived from the error, see [status_code].
+//!
+//! Example usage:
+//!
+//! ```no_run
+//! # use gree::{*, async_client::*};
+//! # #[tokio::main]
+//! # async fn main() -> Result<()> {
+//! let gree = Gree::new(GreeConfig::default()).await?;
+//! gree::server::serve(gree, ([127, 0, 0, 1], 7777)).await;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! ```bash
+//! curl http://localhost:7777/scan
+//! curl http://localhost:7777/dev/000cc0000000/get?SetTem&Pow
+//! curl http://localhost:7777/dev/000cc0000000/set?SetTem=23&Pow=1
+//! ```
+
+#![cfg(feature = "server")]
+
+use std::{net::SocketAddr, convert::Infallible, collections::HashMap, sync::Arc};
+use serde_derive::Serialize;
+use futures_util::{SinkExt, StreamExt};
+use tokio::{select, sync::mpsc::UnboundedReceiver};
+use warp::{Filter, Reply, Rejection, hyper::StatusCode, ws::{Ws, WebSocket, Message}};
+use utoipa::{OpenApi, ToSchema};
+use crate::{*, async_client::*};
+
+/// Shared `Gree` instance, as used by the routes
+pub type SharedGree<C> = Arc<Gree<C>>;
+
+type Query = HashMap<String, String>;
+
+/// Device information, as returned from `GET /dev/<device>`
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct DeviceInfo {
+    #[schema(value_type = String)]
+    pub mac: MacAddr,
+    pub ip: String,
+    /// Device name, as reported in the scan response
+    pub name: String,
+    /// `V1` or `V2`
+    #[schema(value_type = String)]
+    pub variant: ProtocolVariant,
+    pub bound: bool,
+    pub provisioned: bool,
+}
+
+impl From<&Device> for DeviceInfo {
+    fn from(dev: &Device) -> Self {
+        Self {
+            mac: MacAddr::from(&dev.scan_result.mac),
+            ip: dev.ip.to_string(),
+            name: dev.scan_result.name.clone(),
+            variant: dev.variant,
+            bound: dev.key.is_some(),
+            provisioned: dev.provisioned,
+        }
+    }
+}
+
+/// An API error serializable to JSON
+#[derive(Serialize, ToSchema)]
+struct ErrorMessage {
+    code: u16,
+    message: String,
+}
+
+#[derive(Debug)]
+struct Rejected(Error);
+
+impl warp::reject::Reject for Rejected { }
+
+fn reject(e: Error) -> Rejection {
+    warp::reject::custom(Rejected(e))
+}
+
+/// HTTP status code corresponding to the error: 400 for the errors in the request (unknown variables, bad values), 
+/// 404 for unknown devices, 503 for devices not answering, 502 for failures of the devices and of the protocol 
+/// exchanges with them, and 500 for the rest
+pub fn status_code(e: &Error) -> StatusCode {
+    match e.root() {
+        Error::NotFound(_) => StatusCode::NOT_FOUND,
+        Error::InvalidVar(_) | Error::InvalidValue(..) | Error::InvalidMacAddr(_) | Error::Config(_) => StatusCode::BAD_REQUEST,
+        Error::Offline(_) => StatusCode::SERVICE_UNAVAILABLE,
+        e if e.is_timeout() => StatusCode::SERVICE_UNAVAILABLE,
+        Error::DeviceError { .. } | Error::Group(_) | Error::MacNotBound(_) | Error::Crypto | Error::InvalidPack(_) 
+            | Error::Base64Decode(_) | Error::SerDe(_) | Error::Relay(_) => StatusCode::BAD_GATEWAY,
+        _ => StatusCode::INTERNAL_SERVER_ERROR
+    }
+}
+
+async fn handle_rejection(err: Rejection) -> std::result::Result<impl Reply, Infallible> {
+    let (code, message) = if let Some(Rejected(e)) = err.find::<Rejected>() {
+        (status_code(e), e.to_string())
+    } else if err.is_not_found() {
+        (StatusCode::NOT_FOUND, "not found".to_owned())
+    } else {
+        (StatusCode::BAD_REQUEST, format!("{err:?}"))
+    };
+    let json = warp::reply::json(&ErrorMessage { code: code.into(), message });
+    Ok(warp::reply::with_status(json, code))
+}
+
+fn with_gree<C: GreeClientApi + 'static>(gree: &SharedGree<C>) -> impl Filter<Extract = (SharedGree<C>,), Error = Infallible> + Clone {
+    let gree = gree.clone();
+    warp::any().map(move || gree.clone())
+}
+
+async fn device_list<C: GreeClientApi>(gree: &Gree<C>) -> Result<Vec<MacAddr>> {
+    gree.with_state(|state| state.devices.keys().cloned().collect()).await
+}
+
+/// Pushes the events to the socket until either side closes
+async fn push_events(socket: WebSocket, mut events: UnboundedReceiver<GreeEvent>) {
+    let (mut tx, mut rx) = socket.split();
+    loop {
+        select! {
+            e = events.recv() => {
+                let Some(e) = e else { break };
+                let text = match serde_json::to_string(&e) {
+                    Ok(text) => text,
+                    Err(e) => { error!("ws: {e}"); continue }
+                };
+                if let Err(e) = tx.send(Message::text(text)).await {
+                    debug!("ws send: {e}");
+                    break
+                }
+            }
+            m = rx.next() => match m {
+                Some(Ok(m)) if !m.is_close() => (),
+                _ => break
+            }
+        }
+    }
+    let _ = tx.close().await;
+}
+
+#[utoipa::path(get, path = "/scan", responses(
+    (status = 200, description = "MACs of the devices known after the scan", body = Vec<String>),
+    (status = 500, body = ErrorMessage),
+))]
+async fn scan<C: GreeClientApi>(gree: SharedGree<C>) -> std::result::Result<impl Reply, Rejection> {
+    gree.scan().await.into_result().map_err(reject)?;
+    device_list(&gree).await.map(|devs| warp::reply::json(&devs)).map_err(reject)
+}
+
+#[utoipa::path(get, path = "/dev", responses(
+    (status = 200, description = "MACs of the devices known", body = Vec<String>),
+))]
+async fn dev<C: GreeClientApi>(gree: SharedGree<C>) -> std::result::Result<impl Reply, Rejection> {
+    device_list(&gree).await.map(|devs| warp::reply::json(&devs)).map_err(reject)
+}
+
+#[utoipa::path(get, path = "/dev/{device}", params(
+    ("device" = String, Path, description = "MAC address or alias"),
+), responses(
+    (status = 200, body = DeviceInfo),
+    (status = 404, body = ErrorMessage),
+))]
+async fn dev_info<C: GreeClientApi>(dev: String, gree: SharedGree<C>) -> std::result::Result<impl Reply, Rejection> {
+    gree.with_device(&dev, |dev| DeviceInfo::from(dev)).await
+        .map(|d| warp::reply::json(&d))
+        .map_err(reject)
+}
+
+#[utoipa::path(get, path = "/dev/{device}/get", params(
+    ("device" = String, Path, description = "MAC address or alias"),
+    ("vars" = HashMap<String, String>, Query, style = Form, explode, description = "Names of the variables, e.g. `SetTem&Pow`"),
+), responses(
+    (status = 200, description = "Values of the variables", body = HashMap<String, Value>),
+    (status = 400, body = ErrorMessage),
+    (status = 404, body = ErrorMessage),
+    (status = 500, body = ErrorMessage),
+    (status = 502, body = ErrorMessage),
+    (status = 503, body = ErrorMessage),
+))]
+async fn dev_get<C: GreeClientApi>(dev: String, vars: Query, gree: SharedGree<C>) -> std::result::Result<impl Reply, Rejection> {
+    let mut bag = net_var_bag_from_names(vars.keys()).map_err(reject)?;
+    gree.net_read(&dev, &mut bag).await
+        .map(|_| warp::reply::json(&bag.to_json()))
+        .map_err(reject)
+}
+
+#[utoipa::path(get, path = "/dev/{device}/set", params(
+    ("device" = String, Path, description = "MAC address or alias"),
+    ("vars" = HashMap<String, String>, Query, style = Form, explode, description = "Variables to write, e.g. `SetTem=23&Pow=1`"),
+), responses(
+    (status = 200, description = "Values reported by the device", body = HashMap<String, Value>),
+    (status = 400, body = ErrorMessage),
+    (status = 404, body = ErrorMessage),
+    (status = 500, body = ErrorMessage),
+    (status = 502, body = ErrorMessage),
+    (status = 503, body = ErrorMessage),
+))]
+async fn dev_set<C: GreeClientApi>(dev: String, vars: Query, gree: SharedGree<C>) -> std::result::Result<impl Reply, Rejection> {
+    let mut bag = net_var_bag_from_nvs(vars.iter()).map_err(reject)?;
+    gree.net_write(&dev, &mut bag).await
+        .map(|_| warp::reply::json(&bag.to_json()))
+        .map_err(reject)
+}
+
+#[utoipa::path(get, path = "/ws", responses(
+    (status = 101, description = "WebSocket pushing the events as JSON text frames"),
+))]
+async fn ws<C: GreeClientApi>(ws: Ws, gree: SharedGree<C>) -> std::result::Result<impl Reply, Rejection> {
+    let events = gree.subscribe();
+    Ok(ws.on_upgrade(move |socket| push_events(socket, events)))
+}
+
+#[derive(OpenApi)]
+#[openapi(
+    info(title = "gree", description = "Controlling Gree Smart air conditioning units", license(name = "MIT")),
+    paths(scan, dev, dev_info, dev_get, dev_set, ws),
+    components(schemas(DeviceInfo, ErrorMessage)),
+)]
+struct ApiDoc;
+
+/// The OpenAPI document describing the routes, as served at `GET /openapi.json`
+pub fn openapi() -> utoipa::openapi::OpenApi {
+    ApiDoc::openapi()
+}
+
+/// Builds the routes (see module docs) serving the shared `Gree` instance, for embedding into a larger warp service
+pub fn routes<C: GreeClientApi + 'static>(gree: SharedGree<C>) -> impl Filter<Extract = (impl Reply,), Error = Infallible> + Clone {
+    let scan = warp::path!("scan")
+        .and(with_gree(&gree))
+        .and_then(scan::<C>);
+    let population = warp::path!("dev")
+        .and(with_gree(&gree))
+        .and_then(dev::<C>);
+    let devinfo = warp::path!("dev" / String)
+        .and(with_gree(&gree))
+        .and_then(dev_info::<C>);
+    let get = warp::path!("dev" / String / "get")
+        .and(warp::query::<Query>())
+        .and(with_gree(&gree))
+        .and_then(dev_get::<C>);
+    let set = warp::path!("dev" / String / "set")
+        .and(warp::query::<Query>())
+        .and(with_gree(&gree))
+        .and_then(dev_set::<C>);
+    let ws = warp::path!("ws")
+        .and(warp::ws())
+        .and(with_gree(&gree))
+        .and_then(ws::<C>);
+    let api_doc = warp::path!("openapi.json")
+        .map(|| warp::reply::json(&openapi()));
+    warp::get()
+        .and(scan.or(population).or(devinfo).or(get).or(set).or(ws).or(api_doc))
+        .recover(handle_rejection)
+}
+
+/// Serves `gree` at `addr` until the task is cancelled
+pub async fn serve<C: GreeClientApi + 'static>(gree: Gree<C>, addr: impl Into<SocketAddr>) {
+    serve_shared(Arc::new(gree), addr).await
+}
+
+/// Serves the shared `Gree` instance at `addr` until the task is cancelled. Other tasks (e.g. the poller, see
+/// [Gree::spawn_poller]) may use the instance concurrently.
+pub async fn serve_shared<C: GreeClientApi + 'static>(gree: SharedGree<C>, addr: impl Into<SocketAddr>) {
+    let addr = addr.into();
+    debug!("Serving at {addr}");
+    warp::serve(routes(gree)).run(addr).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn documented_paths_are_routed() {
+        let mut cfg = GreeConfig::default();
+        cfg.client_config.bcast_addr = [127, 0, 0, 31].into();
+        cfg.client_config.scan_timeout = Duration::from_millis(100);
+        let routes = routes(Arc::new(Gree::new(cfg).await.unwrap()));
+
+        let doc = openapi();
+        assert_eq!(doc.paths.paths.len(), 6);
+        for path in doc.paths.paths.keys() {
+            let r = warp::test::request().path(&path.replace("{device}", "000cc0000001")).reply(&routes).await;
+            // an unrouted request is rejected as "not found" rather than by a handler
+            let e: Option<serde_json::Value> = serde_json::from_slice(r.body()).ok();
+            assert!(!matches!(&e, Some(e) if e["message"] == "not found"), "{path} is not routed");
+        }
+        let r = warp::test::request().path("/nonexistent").reply(&routes).await;
+        assert_eq!(serde_json::from_slice::<serde_json::Value>(r.body()).unwrap()["message"], "not found");
+    }
+}
@@ -0,0 +1,144 @@
+//! Actor-style `Gree` service (requires `tokio`)
+//!
+//! [GreeService::spawn] moves an async [Gree] into a background task and returns a [GreeHandle], which is cheap to
+//! clone and may be passed to any task. The handle talks to the service over a channel; each request is executed in
+//! a task of its own, so requests to different devices run concurrently. The service stops when all the handles
+//! are dropped.
+//!
+//! Example usage:
+//!
+//! ```no_run
+//! # use gree::{*, service::*};
+//! # async fn run() -> Result<()> {
+//! let gree = GreeService::spawn(GreeConfig::default()).await?;
+//! let g = gree.clone();
+//! tokio::spawn(async move { g.set("living", [(vars::POW, 1.into())].into()).await });
+//! let values = gree.get("bedroom", vec![vars::POW, vars::SET_TEM]).await?;
+//! println!("{values:?}");
+//! # Ok(())
+//! # }
+//! ```
+
+#![cfg(feature = "tokio")]
+
+use std::sync::Arc;
+use tokio::sync::{mpsc, oneshot};
+use crate::{*, async_client::*, vars::VarName};
+
+type Reply<T> = oneshot::Sender<Result<T>>;
+
+enum Command {
+    Scan(Reply<ScanOutcome>),
+    Devices(Reply<Vec<MacAddr>>),
+    Device(String, Reply<Device>),
+    Bind(String, Reply<()>),
+    Read(String, Vec<VarName>, Reply<VarValues>),
+    Write(String, VarValues, Reply<VarValues>),
+    LastKnownValues(String, Reply<VarValues>),
+    Subscribe(Reply<mpsc::UnboundedReceiver<GreeEvent>>),
+}
+
+/// Spawns `Gree` instances as background services
+pub struct GreeService;
+
+impl GreeService {
+    /// Creates a `Gree` from the configuration and spawns it as a service
+    pub async fn spawn(cfg: GreeConfig) -> Result<GreeHandle> {
+        Ok(Self::spawn_gree(Gree::new(cfg).await?))
+    }
+
+    /// Spawns an existing `Gree` (e.g. one over a custom client) as a service
+    pub fn spawn_gree<C: GreeClientApi + 'static>(gree: Gree<C>) -> GreeHandle {
+        let (tx, rx) = mpsc::unbounded_channel();
+        tokio::spawn(Self::run(Arc::new(gree), rx));
+        GreeHandle { tx }
+    }
+
+    async fn run<C: GreeClientApi + 'static>(gree: Arc<Gree<C>>, mut rx: mpsc::UnboundedReceiver<Command>) {
+        while let Some(cmd) = rx.recv().await {
+            let gree = gree.clone();
+            tokio::spawn(async move { Self::execute(&gree, cmd).await });
+        }
+        debug!("service: all handles dropped, stopping");
+    }
+
+    async fn execute<C: GreeClientApi>(gree: &Gree<C>, cmd: Command) {
+        match cmd {
+            Command::Scan(r) => reply(r, Ok(gree.scan().await)),
+            Command::Devices(r) => reply(r, gree.with_state(|s| s.devices.keys().cloned().collect()).await),
+            Command::Device(target, r) => reply(r, gree.with_device(&target, |dev| dev.clone()).await),
+            Command::Bind(target, r) => reply(r, gree.bind(&target).await),
+            Command::Read(target, names, r) => {
+                let mut bag: NetVarBag<SimpleNetVar> = names.into_iter().map(|n| (n, SimpleNetVar::new())).collect();
+                reply(r, gree.net_read(&target, &mut bag).await.map(|_| bag.to_json()))
+            }
+            Command::Write(target, values, r) => {
+                let mut bag: NetVarBag<SimpleNetVar> = values.into_iter().map(|(n, v)| (n, SimpleNetVar::from_value(v))).collect();
+                reply(r, gree.net_write(&target, &mut bag).await.map(|_| bag.to_json()))
+            }
+            Command::LastKnownValues(target, r) => reply(r, Ok(gree.last_known_values(&target))),
+            Command::Subscribe(r) => reply(r, Ok(gree.subscribe())),
+        }
+    }
+}
+
+fn reply<T>(r: Reply<T>, result: Result<T>) {
+    // a send error only means that the caller has gone
+    let _ = r.send(result);
+}
+
+/// Handle to a `Gree` service, see [GreeService]
+///
+/// Requests fail with `Error::Send` if the service has stopped. `target` is a MAC address or an alias.
+#[derive(Clone)]
+pub struct GreeHandle {
+    tx: mpsc::UnboundedSender<Command>,
+}
+
+impl GreeHandle {
+    async fn call<T>(&self, cmd: impl FnOnce(Reply<T>) -> Command) -> Result<T> {
+        let (tx, rx) = oneshot::channel();
+        self.tx.send(cmd(tx)).map_err(|_| Error::Send)?;
+        rx.await.map_err(|_| Error::receiver_disconnected())?
+    }
+
+    /// Performs explicit scan, see [Gree::scan]. A failure to reach the service is reported as [ScanOutcome::Failed].
+    pub async fn scan(&self) -> ScanOutcome {
+        self.call(Command::Scan).await.unwrap_or_else(ScanOutcome::Failed)
+    }
+
+    /// MAC addresses of the devices known
+    pub async fn devices(&self) -> Result<Vec<MacAddr>> {
+        self.call(Command::Devices).await
+    }
+
+    /// Snapshot of the device's state, see [Gree::with_device]
+    pub async fn device(&self, target: &str) -> Result<Device> {
+        self.call(|r| Command::Device(target.to_owned(), r)).await
+    }
+
+    /// Performs explicit bind, see [Gree::bind]
+    pub async fn bind(&self, target: &str) -> Result<()> {
+        self.call(|r| Command::Bind(target.to_owned(), r)).await
+    }
+
+    /// Reads the variables, returning their values
+    pub async fn get(&self, target: &str, names: Vec<VarName>) -> Result<VarValues> {
+        self.call(|r| Command::Read(target.to_owned(), names, r)).await
+    }
+
+    /// Writes the variables, returning the values reported by the device
+    pub async fn set(&self, target: &str, values: VarValues) -> Result<VarValues> {
+        self.call(|r| Command::Write(target.to_owned(), values, r)).await
+    }
+
+    /// Last known values of the target's variables, see [Gree::last_known_values]
+    pub async fn last_known_values(&self, target: &str) -> Result<VarValues> {
+        self.call(|r| Command::LastKnownValues(target.to_owned(), r)).await
+    }
+
+    /// Subscribes to state change events, see [Gree::subscribe]
+    pub async fn subscribe(&self) -> Result<mpsc::UnboundedReceiver<GreeEvent>> {
+        self.call(Command::Subscribe).await
+    }
+}
@@ -1,31 +1,262 @@
-use std::{time::Duration, collections::HashMap, net::{IpAddr, SocketAddr, Ipv4Addr}};
+use std::{time::{Duration, Instant, SystemTime}, collections::{HashMap, HashSet}, net::{IpAddr, SocketAddr, Ipv4Addr}, sync::Arc};
 
 use serde_json::Value;
+use serde_derive::{Serialize, Deserialize};
 
 use crate::{*, apdu::{ScanResponsePack, GenericMessage, BindResponsePack}, vars::VarName};
 
-pub type MacAddr = String;
+/// MAC address of a device, in the form the devices report it: 12 lowercase hex digits without separators 
+/// (`000cc0000001`). Parsing (`"00:0C:C0:00:00:01".parse()`) accepts the common notations, normalizing them, and fails 
+/// with `Error::InvalidMacAddr` on anything else. [From] normalizes the valid addresses likewise, but keeps other strings 
+/// as they are, for the identifiers reported by devices not following the format.
+#[derive(Clone, Default, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(from = "String", into = "String")]
+pub struct MacAddr(String);
+
+impl MacAddr {
+    /// Separators accepted between the digit groups: `00:0c:...`, `00-0c-...`, `000c.c000.0001`
+    const SEPARATORS: [char; 3] = [':', '-', '.'];
+
+    /// The normalized form of `s`, if it is a MAC address in one of the common notations
+    fn normalize(s: &str) -> Option<String> {
+        let s = s.trim();
+        let sep = s.chars().find(|c| Self::SEPARATORS.contains(c));
+        let groups: Vec<&str> = match sep { Some(sep) => s.split(sep).collect(), None => vec![s] };
+        let group_len = 12 / groups.len();
+        let valid = [1, 3, 6].contains(&groups.len()) 
+            && groups.iter().all(|g| g.len() == group_len && g.chars().all(|c| c.is_ascii_hexdigit()));
+        valid.then(|| groups.concat().to_ascii_lowercase())
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl std::str::FromStr for MacAddr {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self> {
+        Self::normalize(s).map(Self).ok_or_else(|| Error::InvalidMacAddr(s.to_owned()))
+    }
+}
+
+impl From<&str> for MacAddr {
+    fn from(s: &str) -> Self {
+        Self(Self::normalize(s).unwrap_or_else(|| s.to_owned()))
+    }
+}
+
+impl From<String> for MacAddr {
+    fn from(s: String) -> Self {
+        Self(Self::normalize(&s).unwrap_or(s))
+    }
+}
+
+impl From<&String> for MacAddr {
+    fn from(s: &String) -> Self {
+        s.as_str().into()
+    }
+}
+
+impl From<MacAddr> for String {
+    fn from(mac: MacAddr) -> Self {
+        mac.0
+    }
+}
+
+impl std::ops::Deref for MacAddr {
+    type Target = str;
+    fn deref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl AsRef<str> for MacAddr {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl std::borrow::Borrow<str> for MacAddr {
+    fn borrow(&self) -> &str {
+        &self.0
+    }
+}
+
+/// Compares the normalized forms, so that `MacAddr::from("00:0C:C0:00:00:01") == "000c.c000.0001"`. Strings which are 
+/// not MAC addresses are compared as they are.
+impl PartialEq<str> for MacAddr {
+    fn eq(&self, other: &str) -> bool {
+        match Self::normalize(other) {
+            Some(other) => self.0 == other,
+            None => self.0 == other,
+        }
+    }
+}
+
+impl PartialEq<&str> for MacAddr {
+    fn eq(&self, other: &&str) -> bool {
+        *self == **other
+    }
+}
+
+impl std::fmt::Display for MacAddr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::fmt::Debug for MacAddr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        std::fmt::Debug::fmt(&self.0, f)
+    }
+}
 
 /// Low-level Gree client configuration
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone)]
 pub struct GreeClientConfig {
     /// Recv datagram buffer size
     pub buffer_size: usize,
-    /// Socket recv timeout
-    pub recv_timeout: Duration,
+    /// How long a scan waits for the responses, see `scan_mode`
+    pub scan_timeout: Duration,
+    /// How long status and command requests wait for the response (per attempt, see `retry`). Together with 
+    /// `scan_timeout` and `bind_timeout`, this replaces the former `recv_timeout` (see [GreeClientConfig::set_recv_timeout]), 
+    /// whose 3 s default all the three keep.
+    pub exchange_timeout: Duration,
+    /// How long bind requests wait for the response (per attempt, see `retry`)
+    pub bind_timeout: Duration,
     /// Socket addr to bind to
     pub bind_addr: SocketAddr,
-    /// Maximum devices to be discovered diring a scan. The scan is stopped early when this number of devices is reached.
+    /// Maximum devices to be discovered diring a scan. The scan is stopped early when this number of devices is reached
+    /// (with [ScanMode::FirstN] only).
     pub max_count: usize,
+    /// When a scan ends
+    pub scan_mode: ScanMode,
     /// Broadcast address for the network.
     pub bcast_addr: IpAddr,
+    /// Additional broadcast addresses, for scanning several subnets in one pass. The scan pack is sent to each of them 
+    /// along with `bcast_addr`.
+    pub extra_bcast_addrs: Vec<IpAddr>,
+    /// Retry policy for the request/response exchanges (bind, status, cmd)
+    pub retry: RetryPolicy,
+    /// How often the receive thread of the sync client checks whether it should stop, see `sync_client::GreeClient::close`
+    pub poll_interval: Duration,
+    /// Called with the datagrams which could not be parsed. These are logged and skipped either way.
+    pub malformed_hook: Option<PacketHook>,
+    /// Parse the datagrams and the packs leniently, for firmwares of rebranded units which send integers as strings 
+    /// (`"r": "200"`), strings as numbers or `null`s. Fields unknown to this crate are kept in the `extra` field of 
+    /// the packs either way.
+    pub lenient: bool,
+    /// Value of the `uid` field of the requests (and of the bind pack). The official app sends 0; some firmwares 
+    /// behave differently for other values.
+    pub uid: i32,
+    /// Value of the `i` field of the requests
+    pub seq: SeqMode,
+    /// Generic key of the firmware, used instead of [ProtocolVariant::generic_key] for binding and for decoding the 
+    /// scan responses, for regional models shipping with a different default key
+    pub generic_key: Option<String>,
+}
+
+/// Value of the `i` field of the requests, see [GreeClientConfig::seq]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SeqMode {
+    /// 1 in the bind requests, 0 in the others, as the official app sends
+    #[default]
+    Default,
+    /// The same value in all the requests
+    Fixed(i32),
+    /// Incremented with each exchange (not with each attempt), starting from 1
+    Increment,
+}
+
+impl SeqMode {
+    /// The `i` of a request for which the official app sends `default`; `counter` holds the last value incremented
+    pub fn value(self, default: i32, counter: &std::sync::atomic::AtomicI32) -> i32 {
+        match self {
+            Self::Default => default,
+            Self::Fixed(i) => i,
+            Self::Increment => counter.fetch_add(1, std::sync::atomic::Ordering::Relaxed).wrapping_add(1),
+        }
+    }
 }
 
 impl GreeClientConfig {
     pub const DEFAULT_BUFFER_SIZE: usize = 2048;
     pub const DEFAULT_MAX_COUNT: usize = 10;
     pub const DEFAULT_BROADCAST_ADDR: [u8; 4] =  [10, 0, 0, 255];
-    pub const DEFAULT_RECV_TIMEOUT: Duration = Duration::from_secs(3);
+    pub const DEFAULT_SCAN_TIMEOUT: Duration = Duration::from_secs(3);
+    pub const DEFAULT_EXCHANGE_TIMEOUT: Duration = Duration::from_secs(3);
+    pub const DEFAULT_BIND_TIMEOUT: Duration = Duration::from_secs(3);
+    pub const DEFAULT_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+    /// Sets the scan, exchange and bind timeouts at once, as the single `recv_timeout` field did before they were split
+    #[deprecated(note = "set `scan_timeout`, `exchange_timeout` and `bind_timeout` instead")]
+    pub fn set_recv_timeout(&mut self, timeout: Duration) {
+        (self.scan_timeout, self.exchange_timeout, self.bind_timeout) = (timeout, timeout, timeout)
+    }
+
+    /// Default configuration broadcasting to all the local IPv4 networks (requires `auto-bcast` feature). 
+    /// See [local_bcast_addrs].
+    pub fn auto_bcast() -> Result<Self> {
+        let addrs = local_bcast_addrs()?;
+        let (bcast_addr, extra) = addrs.split_first().ok_or_else(|| Error::Config("no broadcast addresses found".to_owned()))?;
+        Ok(Self { bcast_addr: *bcast_addr, extra_bcast_addrs: extra.to_vec(), ..Default::default() })
+    }
+}
+
+/// Broadcast addresses of the local IPv4 interfaces, loopback excluded (requires `auto-bcast` feature)
+#[cfg(feature = "auto-bcast")]
+pub fn local_bcast_addrs() -> Result<Vec<IpAddr>> {
+    let mut addrs = vec![];
+    for i in if_addrs::get_if_addrs()? {
+        let if_addrs::IfAddr::V4(a) = i.addr else { continue };
+        if a.is_loopback() { continue }
+        let bcast = a.broadcast.unwrap_or_else(|| (u32::from(a.ip) | !u32::from(a.netmask)).into());
+        let bcast = IpAddr::V4(bcast);
+        if !addrs.contains(&bcast) { addrs.push(bcast) }
+    }
+    Ok(addrs)
+}
+
+/// Broadcast addresses of the local IPv4 interfaces, loopback excluded (requires `auto-bcast` feature)
+#[cfg(not(feature = "auto-bcast"))]
+pub fn local_bcast_addrs() -> Result<Vec<IpAddr>> {
+    Err(Error::Config("broadcast address detection requires `auto-bcast` feature".to_owned()))
+}
+
+/// When a scan ends, see [GreeClientConfig::scan_mode]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ScanMode {
+    /// Ends when `max_count` devices have responded, or when no device responds within `scan_timeout`. Quick, but may 
+    /// miss the slower devices on congested networks.
+    #[default]
+    FirstN,
+    /// Collects the responses for the whole `scan_timeout`, regardless of their number
+    FullWindow,
+    /// Waits up to `scan_timeout` for the first response, then ends when no device responds within `grace`, 
+    /// regardless of the number of responses
+    UntilQuiet { grace: Duration },
+}
+
+impl ScanMode {
+    pub const DEFAULT_GRACE: Duration = Duration::from_millis(500);
+}
+
+impl GreeClientConfig {
+    /// The generic key for `variant`, see [GreeClientConfig::generic_key]
+    pub fn generic_key(&self, variant: ProtocolVariant) -> &str {
+        self.generic_key.as_deref().unwrap_or(variant.generic_key())
+    }
+
+    /// How long a scan started at `started`, having received `received` responses, waits for the next one; `None` if 
+    /// the scan is over
+    pub fn scan_wait(&self, started: Instant, received: usize) -> Option<Duration> {
+        match self.scan_mode {
+            ScanMode::FirstN => (received < self.max_count).then_some(self.scan_timeout),
+            ScanMode::FullWindow => self.scan_timeout.checked_sub(started.elapsed()).filter(|d| !d.is_zero()),
+            ScanMode::UntilQuiet { grace } => Some(if received == 0 { self.scan_timeout } else { grace }),
+        }
+    }
 }
 
 impl Default for GreeClientConfig {
@@ -33,62 +264,848 @@ impl Default for GreeClientConfig {
     fn default() -> Self {
         Self {
             buffer_size: Self::DEFAULT_BUFFER_SIZE,
-            recv_timeout: Self::DEFAULT_RECV_TIMEOUT,
+            scan_timeout: Self::DEFAULT_SCAN_TIMEOUT,
+            exchange_timeout: Self::DEFAULT_EXCHANGE_TIMEOUT,
+            bind_timeout: Self::DEFAULT_BIND_TIMEOUT,
             bind_addr: (Ipv4Addr::UNSPECIFIED, 0).into(),
             max_count: Self::DEFAULT_MAX_COUNT, 
+            scan_mode: ScanMode::default(),
             bcast_addr: Self::DEFAULT_BROADCAST_ADDR.into(), 
+            extra_bcast_addrs: vec![],
+            retry: RetryPolicy::default(),
+            poll_interval: Self::DEFAULT_POLL_INTERVAL,
+            malformed_hook: None,
+            lenient: false,
+            uid: 0,
+            seq: SeqMode::default(),
+            generic_key: None,
+        }
+    }
+}
+
+/// Callback receiving raw datagrams along with the reason they were rejected, e.g. for diagnostics or packet capture.
+/// See [GreeClientConfig::malformed_hook].
+#[derive(Clone)]
+pub struct PacketHook(Arc<PacketFn>);
+
+type PacketFn = dyn Fn(SocketAddr, &[u8], &Error) + Send + Sync;
+
+impl PacketHook {
+    pub fn new(f: impl Fn(SocketAddr, &[u8], &Error) + Send + Sync + 'static) -> Self {
+        Self(Arc::new(f))
+    }
+
+    pub fn call(&self, addr: SocketAddr, packet: &[u8], e: &Error) {
+        (self.0)(addr, packet, e)
+    }
+}
+
+impl std::fmt::Debug for PacketHook {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("PacketHook")
+    }
+}
+
+/// Parses a datagram received, logging and skipping (after passing it to [GreeClientConfig::malformed_hook]) the 
+/// malformed ones, so that a stray packet does not break the receive loop
+pub(crate) fn parse_datagram(addr: SocketAddr, b: &[u8], cfg: &GreeClientConfig) -> Option<GenericMessage> {
+    match with_lenient(cfg.lenient, || serde_json::from_slice::<GenericMessage>(b)) {
+        Ok(gm) => Some(GenericMessage { lenient: cfg.lenient, ..gm }),
+        Err(e) => {
+            let e = Error::from(e);
+            error!("[{addr}] malformed datagram skipped: {e}");
+            if let Some(hook) = &cfg.malformed_hook {
+                hook.call(addr, b, &e);
+            }
+            None
         }
     }
 }
 
+/// Retry policy for the request/response exchanges, applied when the device does not answer within 
+/// [GreeClientConfig::exchange_timeout] (or [GreeClientConfig::bind_timeout]).
+/// 
+/// The n-th retry is delayed by `base_delay * 2^(n-1)`, plus a random delay of up to `jitter`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// Total number of attempts, including the first one. `1` disables retries.
+    pub attempts: u32,
+    /// Delay before the first retry
+    pub base_delay: Duration,
+    /// Maximum random delay added to each retry delay
+    pub jitter: Duration,
+}
+
+impl RetryPolicy {
+    pub const DEFAULT_ATTEMPTS: u32 = 2;
+    pub const DEFAULT_BASE_DELAY: Duration = Duration::from_millis(100);
+    pub const DEFAULT_JITTER: Duration = Duration::from_millis(50);
+
+    /// Policy with retries disabled
+    pub fn none() -> Self {
+        Self { attempts: 1, ..Default::default() }
+    }
+
+    /// Delay before the `retry`-th retry (1-based)
+    pub fn delay(&self, retry: u32) -> Duration {
+        let backoff = self.base_delay.saturating_mul(1 << retry.saturating_sub(1).min(16));
+        let jitter = match self.jitter.as_nanos() as u64 {
+            0 => 0,
+            max => random_u64() % max,
+        };
+        backoff + Duration::from_nanos(jitter)
+    }
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self { attempts: Self::DEFAULT_ATTEMPTS, base_delay: Self::DEFAULT_BASE_DELAY, jitter: Self::DEFAULT_JITTER }
+    }
+}
+
+/// Cheap random number, good enough for jitter
+fn random_u64() -> u64 {
+    use std::hash::{BuildHasher, Hasher};
+    // each RandomState is seeded differently
+    std::collections::hash_map::RandomState::new().build_hasher().finish()
+}
+
 /// Gree network configuration
 #[derive(Debug, Clone)]
 pub struct GreeConfig {
     /// lower level client configuration
     pub client_config: GreeClientConfig,
+    /// How devices are discovered during scans
+    pub discovery: Discovery,
     /// Minimum scan age. Scan is always bypassed if the last successful scan is younger than this value. 
     pub min_scan_age: Duration,
     /// Maximum scan age. Scan is forced if the last (successful) scan is older than this value.
     pub max_scan_age: Duration,
+    /// If set, scans are only performed when requested explicitly (`Gree::scan`), never implicitly by the other 
+    /// operations, e.g. where the devices are discovered once at startup and no broadcasts are wanted afterwards
+    pub manual_discovery: bool,
     /// Aliases for the network devices
     pub aliases: HashMap<String, MacAddr>,
+    /// If set, the names the devices advertise in their scan responses are registered as aliases, see 
+    /// [GreeState::aliases]. The aliases configured take precedence.
+    pub name_aliases: bool,
+    /// Named groups of devices (MAC addresses or aliases). Writes to a group are fanned out to every member. A group 
+    /// may also be named through an alias, as aliases are resolved first.
+    pub groups: HashMap<String, Vec<String>>,
+    /// Named scenes, see `Gree::apply_scene`
+    pub scenes: HashMap<String, Scene>,
+    /// Storage for device bindings. If set, bindings are restored on startup and saved after binds and scans.
+    pub store: Option<Arc<dyn StateStore>>,
+    /// Sink for the history of variable values. If set, the background poller (`async_client::Gree::spawn_poller`) 
+    /// records the values of `history_vars` it reads.
+    pub history: Option<Arc<dyn HistorySink>>,
+    /// Variables recorded to `history`. The poller reads them along with `poll_vars`.
+    pub history_vars: Vec<VarName>,
+    /// Policies evaluated by the background poller (`async_client::Gree::spawn_poller`) for every device after each 
+    /// poll, see [Policy]
+    pub policies: Vec<Arc<dyn Policy>>,
+    /// If set, `Gree::new` starts the passive discovery listener on this address, see `Gree::listen`
+    pub listen_addr: Option<SocketAddr>,
+    /// Variables read by the background poller (`async_client::Gree::spawn_poller`)
+    pub poll_vars: Vec<VarName>,
+    /// Devices known in advance (provisioned devices), added to the state on startup. These are reachable even if 
+    /// the discovery is not possible (e.g. the broadcasts are blocked).
+    pub devices: HashMap<MacAddr, DeviceConfig>,
+    /// Number of consecutive scans a device may miss before it is evicted. Partial scans (e.g. timing out before all 
+    /// the devices answer) do not evict devices unless this is 1.
+    pub max_missed_scans: u32,
+    /// If set, scans also evict the devices which have not answered for this long (see [Device::last_seen]), however 
+    /// many scans they missed. Provisioned devices and devices not seen since the startup are not evicted.
+    pub max_device_age: Option<Duration>,
+    /// Number of consecutive timeouts after which the device is considered offline
+    pub offline_threshold: u32,
+    /// Initial delay before an offline device is retried. The delay doubles with each subsequent timeout.
+    pub offline_backoff: Duration,
+    /// Maximum delay before an offline device is retried
+    pub max_offline_backoff: Duration,
+    /// Minimum time between subsequent operations on the same device. Operations issued earlier are delayed, as the
+    /// devices tend to misbehave when flooded with packs.
+    pub min_command_gap: Duration,
+    /// If set, the device clock is set to the host's local time after each successful bind. Failures to set the 
+    /// clock are logged and otherwise ignored.
+    pub sync_time_on_bind: bool,
+    /// If set, the devices a scan discovers are bound right after it (concurrently, in the async client), so that the
+    /// first operation on them does not wait for the bind. Failures are reported as [GreeEvent::BindFailed] and 
+    /// otherwise ignored.
+    pub eager_bind: bool,
+    /// `SetTem` range of the devices not listed in `set_tem_ranges`
+    pub set_tem_range: SetTemRange,
+    /// `SetTem` ranges of particular devices (by MAC address)
+    pub set_tem_ranges: HashMap<MacAddr, SetTemRange>,
+    /// Number of consecutive reads a device may report nothing for a variable before the variable is no longer 
+    /// requested from it (its reads yield an empty string without accessing the network). 0 (the default) disables 
+    /// this, as a unit may leave variables empty temporarily, e.g. while powered off.
+    pub empty_read_threshold: u32,
+    /// Maximum number of variables read in one status request. Reads of more variables are split into several 
+    /// requests, the values being merged back into the bag, as some firmwares truncate the responses to larger 
+    /// requests. 0 (the default) reads all the variables at once.
+    pub max_read_cols: usize,
+}
+
+/// Device discovery strategy
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum Discovery {
+    /// Broadcast the scan pack to [GreeClientConfig::bcast_addr]
+    #[default]
+    Broadcast,
+    /// Send the scan pack to each of the addresses, for networks where broadcasts are filtered. See [ip_range].
+    Unicast(Vec<IpAddr>),
+}
+
+/// Parses an IPv4 address range in CIDR notation (e.g. `192.168.1.0/24`) into the host addresses it contains. A plain 
+/// address yields just itself. Ranges larger than `/16` are rejected.
+pub fn ip_range(s: &str) -> Result<Vec<IpAddr>> {
+    let invalid = || Error::Config(format!("invalid address range `{s}`"));
+    let (addr, prefix) = match s.split_once('/') {
+        Some((addr, prefix)) => (addr, prefix.parse::<u32>().map_err(|_| invalid())?),
+        None => return Ok(vec![s.parse().map_err(|_| invalid())?]),
+    };
+    let addr: Ipv4Addr = addr.parse().map_err(|_| invalid())?;
+    if !(16..=32).contains(&prefix) { return Err(invalid()) }
+    let mask = u32::MAX.checked_shl(32 - prefix).unwrap_or(0);
+    let network = u32::from(addr) & mask;
+    let broadcast = network | !mask;
+    let hosts = if prefix >= 31 { network..=broadcast } else { network + 1..=broadcast - 1 };
+    Ok(hosts.map(|a| IpAddr::V4(a.into())).collect())
+}
+
+/// A device known in advance
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DeviceConfig {
+    /// IP address of the device
+    pub ip: IpAddr,
+    /// Encryption key. If not set, the device is bound when needed.
+    #[serde(default)]
+    pub key: Option<String>,
+    /// Protocol variant
+    #[serde(default)]
+    pub variant: ProtocolVariant,
+}
+
+/// Range of `SetTem` values (in degrees Celsius) accepted by a device. Devices silently clamp or ignore values 
+/// outside their range, so the high-level client rejects them before sending.
+/// 
+/// The default, 16..=30 °C, corresponds to 61..=86 °F.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SetTemRange {
+    pub min: u8,
+    pub max: u8,
+}
+
+impl SetTemRange {
+    pub const DEFAULT_MIN: u8 = 16;
+    pub const DEFAULT_MAX: u8 = 30;
+
+    /// Checks the pending `SetTem` write (if any), failing with `InvalidValue` if it is out of the range
+    pub fn check<T: NetVar>(&self, vars: &NetVarBag<T>) -> Result<()> {
+        let Some(nv) = vars.get(vars::SET_TEM) else { return Ok(()) };
+        if !nv.is_net_write_pending() { return Ok(()) }
+        match nv.net_get().as_i64() {
+            Some(t) if (self.min as i64..=self.max as i64).contains(&t) => Ok(()),
+            _ => Err(Error::invalid_value(vars::SET_TEM, &format!("{} outside {}..={}", nv.net_get(), self.min, self.max)))
+        }
+    }
+}
+
+impl Default for SetTemRange {
+    fn default() -> Self {
+        Self { min: Self::DEFAULT_MIN, max: Self::DEFAULT_MAX }
+    }
 }
 
 impl GreeConfig {
 
     pub const DEFAULT_MIN_SCAN_AGE: Duration = Duration::from_secs(60);
     pub const DEFAULT_MAX_SCAN_AGE: Duration = Duration::from_secs(3600 * 24);
+    pub const DEFAULT_POLL_VARS: [VarName; 5] = [vars::POW, vars::MOD, vars::SET_TEM, vars::WD_SPD, vars::TEM_SEN];
+    pub const DEFAULT_MAX_MISSED_SCANS: u32 = 3;
+    pub const DEFAULT_OFFLINE_THRESHOLD: u32 = 3;
+    pub const DEFAULT_OFFLINE_BACKOFF: Duration = Duration::from_secs(10);
+    pub const DEFAULT_MAX_OFFLINE_BACKOFF: Duration = Duration::from_secs(600);
+    pub const DEFAULT_MIN_COMMAND_GAP: Duration = Duration::from_millis(100);
+    pub const DEFAULT_EMPTY_READ_THRESHOLD: u32 = 0;
+    pub const DEFAULT_HISTORY_VARS: [VarName; 1] = [vars::TEM_SEN];
+
+    /// The `SetTem` range of the device
+    pub fn set_tem_range(&self, mac: &str) -> SetTemRange {
+        self.set_tem_ranges.get(mac).copied().unwrap_or(self.set_tem_range)
+    }
+
+    /// Resolves the target (an alias or a MAC address) into a MAC address
+    pub fn resolve<'t>(&'t self, target: &'t str) -> &'t str {
+        self.aliases.get(target).map(|s| s.as_str()).unwrap_or(target)
+    }
+
+    /// MAC addresses of the members if the target (after alias resolution) is a group, `None` otherwise
+    pub fn group_members<'t>(&'t self, target: &'t str) -> Option<Vec<&'t str>> {
+        let members = self.groups.get(self.resolve(target))?;
+        Some(members.iter().map(|m| self.resolve(m)).collect())
+    }
 }
 
 impl Default for GreeConfig {
     fn default() -> Self {
         Self {
             client_config: Default::default(),
+            discovery: Discovery::Broadcast,
             min_scan_age: Self::DEFAULT_MIN_SCAN_AGE, 
             max_scan_age: Self::DEFAULT_MAX_SCAN_AGE,
+            manual_discovery: false,
             aliases: HashMap::new(),
+            name_aliases: false,
+            groups: HashMap::new(),
+            scenes: HashMap::new(),
+            store: None,
+            history: None,
+            history_vars: Self::DEFAULT_HISTORY_VARS.to_vec(),
+            policies: vec![],
+            listen_addr: None,
+            poll_vars: Self::DEFAULT_POLL_VARS.to_vec(),
+            devices: HashMap::new(),
+            max_missed_scans: Self::DEFAULT_MAX_MISSED_SCANS,
+            max_device_age: None,
+            offline_threshold: Self::DEFAULT_OFFLINE_THRESHOLD,
+            offline_backoff: Self::DEFAULT_OFFLINE_BACKOFF,
+            max_offline_backoff: Self::DEFAULT_MAX_OFFLINE_BACKOFF,
+            min_command_gap: Self::DEFAULT_MIN_COMMAND_GAP,
+            sync_time_on_bind: false,
+            eager_bind: false,
+            set_tem_range: SetTemRange::default(),
+            set_tem_ranges: HashMap::new(),
+            empty_read_threshold: Self::DEFAULT_EMPTY_READ_THRESHOLD,
+            max_read_cols: 0,
         }
     }
 }
 
-/// State of Gree network
+/// State of Gree network. Serializes with the device keys redacted, see [WithKeys].
+#[derive(Serialize)]
 pub struct GreeState {
     pub devices: HashMap<MacAddr, Device>,
+    /// Eco profiles active on the devices. Unlike `devices`, these are retained across scans.
+    pub eco_profiles: HashMap<MacAddr, EcoProfile>,
+    /// Last known values of the devices' variables, as learned from reads and writes, with the time they were 
+    /// learned. Retained across scans.
+    pub values: HashMap<MacAddr, HashMap<VarName, VarValue>>,
+    /// Writes queued for the devices but not sent yet. Writes to the same variable are coalesced, the last value wins.
+    pub pending: HashMap<MacAddr, VarValues>,
+    /// Number of consecutive times the devices reported nothing for their variables, see 
+    /// [GreeConfig::empty_read_threshold]. Retained across scans.
+    pub empty_reads: HashMap<MacAddr, HashMap<VarName, u32>>,
+    /// When writes to the devices were last requested (not counting the writes made by [Policy]s). Retained across 
+    /// scans.
+    pub last_write: HashMap<MacAddr, SystemTime>,
+    /// Aliases derived from the names the devices advertise (see [GreeConfig::name_aliases]), by normalized name (see 
+    /// [normalize_alias]). Devices sharing a name are told apart by suffixes: `bedroom`, `bedroom-2`, ... in the order 
+    /// of their MAC addresses.
+    pub aliases: HashMap<String, MacAddr>,
+}
+
+/// Image of a [GreeState], for warm-starting a service after a restart, see [GreeState::to_snapshot]. Unlike the 
+/// state, it serializes with the keys, and deserializes.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GreeStateSnapshot {
+    /// When the snapshot was taken
+    pub taken_at: SystemTime,
+    pub devices: std::collections::BTreeMap<MacAddr, DeviceImage>,
+}
+
+/// A device in a [GreeStateSnapshot]: its binding, scan response, capabilities and last known values. The 
+/// reachability (online state, timeouts, ...) is not kept, but found anew.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DeviceImage {
+    pub ip: IpAddr,
+    pub scan_result: ScanResponsePack,
+    pub key: Option<String>,
+    #[serde(default)]
+    pub variant: ProtocolVariant,
+    #[serde(default)]
+    pub provisioned: bool,
+    #[serde(default)]
+    pub capabilities: Option<Capabilities>,
+    #[serde(default)]
+    pub sub_devices: Vec<SubDeviceInfo>,
+    /// See [GreeState::values]
+    #[serde(default, deserialize_with = "deserialize_var_map")]
+    pub values: HashMap<VarName, VarValue>,
+    /// See [GreeState::eco_profiles]
+    #[serde(default)]
+    pub eco_profile: Option<EcoProfile>,
 }
 
 impl GreeState {
-    pub fn new() -> Self { Self { devices: HashMap::new() } }
-    pub fn scan_ind(&mut self, scan_result: Vec<(IpAddr, GenericMessage, ScanResponsePack)>) {
-        self.devices = scan_result.into_iter().map(|(ip, _, scan_result)| (
-            scan_result.mac.clone(),
-            Device { ip, scan_result, key: None }
-        )).collect();
+    /// Image of the state for restoring it with [GreeState::from_snapshot], e.g. after a restart
+    pub fn to_snapshot(&self) -> GreeStateSnapshot {
+        let devices = self.devices.iter().map(|(mac, dev)| (mac.clone(), DeviceImage {
+            ip: dev.ip,
+            scan_result: dev.scan_result.clone(),
+            key: dev.key.clone(),
+            variant: dev.variant,
+            provisioned: dev.provisioned,
+            capabilities: dev.capabilities.clone(),
+            sub_devices: dev.sub_devices.clone(),
+            values: self.values.get(mac).cloned().unwrap_or_default(),
+            eco_profile: self.eco_profiles.get(mac).cloned(),
+        })).collect();
+        GreeStateSnapshot { taken_at: SystemTime::now(), devices }
+    }
+
+    /// State restored from a snapshot. The devices are considered online until they fail to answer; the values keep 
+    /// the times they were learned, so that they go stale as they would have.
+    pub fn from_snapshot(snapshot: GreeStateSnapshot) -> Self {
+        let mut s = Self::new();
+        for (mac, d) in snapshot.devices {
+            let mut dev = Device::new(d.ip, d.scan_result, d.key, d.variant, d.provisioned);
+            dev.capabilities = d.capabilities;
+            dev.sub_devices = d.sub_devices;
+            if !d.values.is_empty() { s.values.insert(mac.clone(), d.values); }
+            if let Some(p) = d.eco_profile { s.eco_profiles.insert(mac.clone(), p); }
+            s.devices.insert(mac, dev);
+        }
+        s
+    }
+}
+
+/// Normalizes a device name for use as an alias: lowercased, with the runs of whitespace replaced by dashes, so that 
+/// `Living Room` becomes `living-room`
+pub fn normalize_alias(name: &str) -> String {
+    name.split_whitespace().map(str::to_lowercase).collect::<Vec<_>>().join("-")
+}
+
+/// Values of variables by internalized name
+pub type VarValues = HashMap<VarName, Value>;
+
+/// Last known value of a variable, see [GreeState::values]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct VarValue {
+    pub value: Value,
+    /// When the value was last received from the network
+    pub updated: SystemTime,
+}
+
+impl VarValue {
+    /// True if the value has not been received from the network within `ttl`
+    pub fn is_stale(&self, ttl: Duration) -> bool {
+        self.updated.elapsed().unwrap_or_default() > ttl
+    }
+}
+
+/// Difference between the device states before and after a scan, see [GreeState::scan_ind]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ScanDiff {
+    /// Devices not known before, with their IP addresses
+    pub added: Vec<(MacAddr, IpAddr)>,
+    /// Devices evicted for not answering the scans (see [GreeConfig::max_missed_scans] and 
+    /// [GreeConfig::max_device_age])
+    pub removed: Vec<MacAddr>,
+    /// Devices answering from a different IP address: `(mac, old, new)`
+    pub ip_changed: Vec<(MacAddr, IpAddr, IpAddr)>,
+    /// Offline devices which answered the scan
+    pub online: Vec<MacAddr>,
+}
+
+impl ScanDiff {
+    /// True if the scan has not changed anything
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.ip_changed.is_empty() && self.online.is_empty()
+    }
+
+    /// Events corresponding to the difference
+    pub fn events(&self) -> Vec<GreeEvent> {
+        let added = self.added.iter().map(|(mac, ip)| GreeEvent::DeviceDiscovered { mac: mac.clone(), ip: *ip });
+        let removed = self.removed.iter().map(|mac| GreeEvent::DeviceLost { mac: mac.clone() });
+        let ip_changed = self.ip_changed.iter().map(|(mac, old, new)| GreeEvent::IpChanged { mac: mac.clone(), old: *old, new: *new });
+        let online = self.online.iter().map(|mac| GreeEvent::DeviceOnline { mac: mac.clone() });
+        added.chain(removed).chain(ip_changed).chain(online).collect()
+    }
+}
+
+/// Outcome of a scan, explicit (`Gree::scan`) or implicit
+#[derive(Debug)]
+pub enum ScanOutcome {
+    /// The scan was performed
+    Performed { 
+        /// Number of devices which answered
+        found: usize,
+        /// Time the scan took
+        duration: Duration,
+        /// Changes the scan has made to the devices known
+        diff: ScanDiff,
+    },
+    /// The scan was bypassed, as the last scan is younger than [GreeConfig::min_scan_age] (or, for an implicit scan 
+    /// not forced by a failure, [GreeConfig::max_scan_age])
+    SkippedTooRecent,
+    /// The implicit scan was bypassed, as [GreeConfig::manual_discovery] is set
+    SkippedManual,
+    /// The scan failed
+    Failed(Error),
+}
+
+impl ScanOutcome {
+    /// Changes the scan has made to the devices known (none, if the scan was bypassed), or the error it failed with
+    pub fn into_result(self) -> Result<ScanDiff> {
+        match self {
+            Self::Performed { diff, .. } => Ok(diff),
+            Self::SkippedTooRecent | Self::SkippedManual => Ok(ScanDiff::default()),
+            Self::Failed(e) => Err(e),
+        }
+    }
+}
+
+/// State change event, see `Gree::subscribe`
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "event")]
+pub enum GreeEvent {
+    /// A device not known before answered a scan
+    DeviceDiscovered { mac: MacAddr, ip: IpAddr },
+    /// A device was evicted for not answering the scans (see [GreeConfig::max_missed_scans] and 
+    /// [GreeConfig::max_device_age])
+    DeviceLost { mac: MacAddr },
+    /// A device was removed on request, see `Gree::remove_device`
+    DeviceRemoved { mac: MacAddr },
+    /// A device answered a scan from a different IP address
+    IpChanged { mac: MacAddr, old: IpAddr, new: IpAddr },
+    /// An offline device answered again
+    DeviceOnline { mac: MacAddr },
+    /// A device stopped answering (see [GreeConfig::offline_threshold])
+    DeviceOffline { mac: MacAddr },
+    /// A new value of a variable was learned from the network. `old` is `None` if the value was not known before.
+    VarChanged { mac: MacAddr, name: VarName, old: Option<Value>, new: Value },
+    /// Binding the device failed
+    BindFailed { mac: MacAddr, error: String },
+}
+
+impl Default for GreeState {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl GreeState {
+    pub fn new() -> Self { 
+        Self { devices: HashMap::new(), eco_profiles: HashMap::new(), values: HashMap::new(), pending: HashMap::new(),
+            empty_reads: HashMap::new(), last_write: HashMap::new(), aliases: HashMap::new() } 
+    }
+
+    /// Resolves the target into a MAC address: the aliases configured come first, then the MAC addresses of the 
+    /// devices known (in any of the notations [MacAddr] accepts) and then the aliases derived from the device names 
+    /// (see [GreeState::aliases])
+    pub fn resolve(&self, cfg: &GreeConfig, target: &str) -> MacAddr {
+        let unit = cfg.resolve(target);
+        if unit != target { return unit.into() }
+        let mac = MacAddr::from(unit);
+        if self.aliases.is_empty() || self.devices.contains_key(&mac) { return mac }
+        self.aliases.get(&normalize_alias(target)).cloned().unwrap_or(mac)
+    }
+
+    /// Registers the names the devices advertise as aliases, replacing the ones registered before, see 
+    /// [GreeState::aliases]
+    pub fn update_aliases(&mut self) {
+        let mut aliases = HashMap::new();
+        for (mac, dev) in self.iter_sorted() {
+            let name = normalize_alias(&dev.scan_result.name);
+            if name.is_empty() { continue }
+            let alias = (1..).map(|n| if n == 1 { name.clone() } else { format!("{name}-{n}") })
+                .find(|a| !aliases.contains_key(a))
+                .unwrap_or_default();
+            aliases.insert(alias, mac.clone());
+        }
+        self.aliases = aliases;
+    }
+
+    /// Queues a write for the device, replacing the value queued for the same variable, if any
+    pub fn queue_write(&mut self, mac: &str, name: VarName, value: Value) {
+        self.pending.entry(mac.into()).or_default().insert(name, value);
+    }
+
+    /// Re-queues writes which could not be sent. Values queued in the meantime take precedence.
+    pub fn requeue_writes(&mut self, mac: &str, writes: VarValues) {
+        let pending = self.pending.entry(mac.into()).or_default();
+        for (name, value) in writes {
+            pending.entry(name).or_insert(value);
+        }
+    }
+
+    /// Merges the scan result into the devices. Devices already known are updated in place, retaining their keys, protocol 
+    /// variants and other state. Devices which have been missing from `max_missed_scans` consecutive scan results 
+    /// are evicted, unless provisioned.
+    /// 
+    /// Returns the difference from the previous state.
+    pub fn scan_ind(&mut self, scan_result: Vec<(IpAddr, GenericMessage, ScanResponsePack)>, max_missed_scans: u32) -> ScanDiff {
+        let mut diff = ScanDiff::default();
+        let mut seen = HashSet::new();
+        let now = Instant::now();
+        for (ip, gm, scan_result) in scan_result {
+            seen.insert(MacAddr::from(&scan_result.mac));
+            self.merge(ip, &gm, scan_result, now, &mut diff);
+        }
+        self.devices.retain(|mac, dev| {
+            if dev.provisioned || seen.contains(mac) { return true }
+            dev.missed_scans += 1;
+            if dev.missed_scans < max_missed_scans { return true }
+            diff.removed.push(mac.clone());
+            false
+        });
+        diff
+    }
+
+    /// Evicts the devices which have not answered for longer than `max_age` (see [GreeConfig::max_device_age]), 
+    /// returning their MAC addresses
+    pub fn evict_stale(&mut self, max_age: Duration, now: Instant) -> Vec<MacAddr> {
+        let mut removed = vec![];
+        self.devices.retain(|mac, dev| {
+            let stale = !dev.provisioned && dev.last_seen.is_some_and(|t| now.saturating_duration_since(t) > max_age);
+            if stale { removed.push(mac.clone()) }
+            !stale
+        });
+        removed
+    }
+
+    /// Removes the device along with the state retained across scans (its last known values, pending writes etc.), 
+    /// returning it if it was known
+    pub fn remove(&mut self, mac: &str) -> Option<Device> {
+        let dev = self.devices.remove(mac)?;
+        self.eco_profiles.remove(mac);
+        self.values.remove(mac);
+        self.pending.remove(mac);
+        self.empty_reads.remove(mac);
+        self.last_write.remove(mac);
+        self.aliases.retain(|_, m| m != mac);
+        Some(dev)
+    }
+
+    /// Merges a presence announcement (a scan response received outside of a scan) into the devices. Unlike 
+    /// [GreeState::scan_ind], no devices are evicted.
+    pub fn announce_ind(&mut self, ip: IpAddr, gm: &GenericMessage, scan_result: ScanResponsePack) -> ScanDiff {
+        let mut diff = ScanDiff::default();
+        self.merge(ip, gm, scan_result, Instant::now(), &mut diff);
+        diff
+    }
+
+    fn merge(&mut self, ip: IpAddr, gm: &GenericMessage, scan_result: ScanResponsePack, now: Instant, diff: &mut ScanDiff) {
+        let mac = MacAddr::from(&scan_result.mac);
+        match self.devices.get_mut(&mac) {
+            Some(dev) => {
+                if dev.ip != ip {
+                    diff.ip_changed.push((mac.clone(), dev.ip, ip));
+                    dev.ip = ip;
+                }
+                if dev.key.is_none() { dev.variant = gm.variant() }
+                dev.scan_result = scan_result;
+                dev.missed_scans = 0;
+                if dev.seen_ind(&mac, now).is_some() { diff.online.push(mac) }
+            }
+            None => {
+                let mut dev = Device::new(ip, scan_result, None, gm.variant(), false);
+                dev.last_seen = Some(now);
+                self.devices.insert(mac.clone(), dev);
+                diff.added.push((mac, ip));
+            }
+        }
+    }
+
+    /// Records values learned from the network for the device. Returns `VarChanged` events for the values that differ
+    /// from the last known ones.
+    pub fn learn(&mut self, mac: &str, learned: VarValues) -> Vec<GreeEvent> {
+        let values = self.values.entry(mac.into()).or_default();
+        let updated = SystemTime::now();
+        learned.into_iter().filter_map(|(name, new)| {
+            let old = values.insert(name, VarValue { value: new.clone(), updated }).map(|v| v.value);
+            if old.as_ref() == Some(&new) { return None }
+            Some(GreeEvent::VarChanged { mac: mac.into(), name, old, new })
+        }).collect()
+    }
+
+    /// Last known values of the device's variables
+    pub fn last_known_values(&self, mac: &str) -> VarValues {
+        self.values.get(mac).map(|values| values.iter().map(|(n, v)| (*n, v.value.clone())).collect()).unwrap_or_default()
+    }
+
+    /// Satisfies the pending reads of the variables whose last known values are younger than `max_age`
+    pub fn read_cached<T: NetVar>(&self, mac: &str, vars: &mut NetVarBag<T>, max_age: Duration) {
+        let Some(values) = self.values.get(mac) else { return };
+        for (name, nv) in vars.iter_mut() {
+            if !nv.is_net_read_pending() { continue }
+            if let Some(v) = values.get(name).filter(|v| !v.is_stale(max_age)) { nv.net_set(v.value.clone()) }
+        }
+    }
+
+    /// Counts the variables the device reported nothing for, resetting the count of those reported with a value
+    pub fn count_empty_reads(&mut self, mac: &str, learned: &VarValues, threshold: u32) {
+        let counts = self.empty_reads.entry(mac.into()).or_default();
+        for (name, value) in learned {
+            if !is_unreported(value) {
+                counts.remove(name);
+                continue
+            }
+            let count = counts.entry(*name).or_default();
+            *count += 1;
+            if *count == threshold { debug!("[{mac}] {name} reported empty {threshold} times, no longer requested") }
+        }
+    }
+
+    /// Marks the pending reads of the variables the device has consistently reported nothing for as done, with an 
+    /// empty string (as the device would report), so that they are not requested. A `threshold` of 0 disables this.
+    pub fn skip_empty_reads<T: NetVar>(&self, mac: &str, threshold: u32, vars: &mut NetVarBag<T>) {
+        let Some(counts) = self.empty_reads.get(mac) else { return };
+        if threshold == 0 { return }
+        for (name, nv) in vars.iter_mut() {
+            if nv.is_net_read_pending() && counts.get(name).is_some_and(|c| *c >= threshold) {
+                nv.net_set(Value::String(String::new()));
+            }
+        }
+    }
+
+    /// MAC address of the multi-split bridge the unit `mac` belongs to, if it is a listed sub-unit
+    pub fn parent_of(&self, mac: &str) -> Option<&MacAddr> {
+        self.devices.iter().find(|(_, dev)| dev.sub_devices.iter().any(|sub| sub.mac == mac)).map(|(parent, _)| parent)
+    }
+
+    /// Multi-split bridges reporting sub-units which have not been listed yet
+    pub fn unlisted_bridges(&self) -> Vec<MacAddr> {
+        self.devices.iter().filter(|(_, dev)| dev.scan_result.sub_cnt > 0 && dev.sub_devices.is_empty()).map(|(mac, _)| mac.clone()).collect()
+    }
+
+    /// True if the device is provisioned (see [Device::provisioned])
+    pub fn is_provisioned(&self, mac: &str) -> bool {
+        self.devices.get(mac).is_some_and(|dev| dev.provisioned)
+    }
+
+    /// The devices sorted by MAC address
+    pub fn iter_sorted(&self) -> impl Iterator<Item = (&MacAddr, &Device)> {
+        let mut devices: Vec<_> = self.devices.iter().collect();
+        devices.sort_unstable_by_key(|(mac, _)| *mac);
+        devices.into_iter()
+    }
+
+    /// The device at `ip`. If several devices share the address, the one with the lowest MAC address.
+    pub fn by_ip(&self, ip: IpAddr) -> Option<&Device> {
+        self.iter_sorted().map(|(_, dev)| dev).find(|dev| dev.ip == ip)
+    }
+
+    /// The device named `name` in its scan response. If several devices share the name, the one with the lowest MAC 
+    /// address.
+    pub fn by_name(&self, name: &str) -> Option<&Device> {
+        self.iter_sorted().map(|(_, dev)| dev).find(|dev| dev.scan_result.name == name)
+    }
+
+    /// Bindings of the bound devices, for persisting
+    pub fn bindings(&self) -> HashMap<MacAddr, DeviceBinding> {
+        self.devices.iter().filter_map(|(mac, dev)| dev.key.as_ref().map(|key| (
+            mac.clone(),
+            DeviceBinding { mac: mac.clone(), ip: dev.ip, key: key.clone(), variant: dev.variant }
+        ))).collect()
+    }
+
+    /// Adds provisioned devices (see [Device::provisioned]). Devices already known are not overwritten.
+    pub fn preset(&mut self, devices: &HashMap<MacAddr, DeviceConfig>) {
+        for (mac, d) in devices {
+            self.devices.entry(mac.clone()).or_insert_with(|| Device::new(
+                d.ip,
+                ScanResponsePack { mac: mac.to_string(), ..Default::default() },
+                d.key.clone(),
+                d.variant,
+                true,
+            ));
+        }
+    }
+
+    /// Imports bindings obtained elsewhere, see `Gree::import_bindings`. Unlike [GreeState::restore], the keys of the 
+    /// devices already known are replaced; their IP addresses are kept, as found by the scans. Fails, importing none, 
+    /// if a binding lacks the MAC address.
+    pub fn import(&mut self, bindings: Vec<DeviceBinding>) -> Result<()> {
+        if let Some(b) = bindings.iter().find(|b| b.mac.is_empty()) {
+            return Err(Error::Config(format!("binding of {} lacks the MAC address", b.ip)))
+        }
+        for b in bindings {
+            match self.devices.get_mut(&b.mac) {
+                Some(dev) => {
+                    dev.key = Some(b.key);
+                    dev.variant = b.variant;
+                }
+                None => self.restore([(b.mac.clone(), b)].into()),
+            }
+        }
+        Ok(())
+    }
+
+    /// Restores devices from bindings. Devices already known are only assigned the key, if they have none.
+    pub fn restore(&mut self, bindings: HashMap<MacAddr, DeviceBinding>) {
+        for (mac, b) in bindings {
+            match self.devices.get_mut(&mac) {
+                Some(dev) => if dev.key.is_none() {
+                    dev.key = Some(b.key);
+                    dev.variant = b.variant;
+                }
+                None => {
+                    self.devices.insert(mac.clone(), Device::new(
+                        b.ip,
+                        ScanResponsePack { mac: mac.into(), ..Default::default() },
+                        Some(b.key),
+                        b.variant,
+                        false,
+                    ));
+                }
+            }
+        }
+    }
+}
+
+/// True if the value does not carry information: units lacking a feature report an empty string for its variable
+pub(crate) fn is_unreported(v: &Value) -> bool {
+    v.is_null() || v.as_str() == Some("")
+}
+
+/// Variables a device supports, as found by probing: reading all the variables (see [DeviceSnapshot::vars]) and 
+/// recording which of them come back meaningful. Units lacking a feature report an empty string for its variable.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Capabilities {
+    /// Variables reported with a value
+    #[serde(deserialize_with = "deserialize_var_names")]
+    pub supported: HashSet<VarName>,
+    /// Variables probed but not reported with a value
+    #[serde(deserialize_with = "deserialize_var_names")]
+    pub unsupported: HashSet<VarName>,
+}
+
+impl Capabilities {
+    /// Sorts the variables of a NetVarBag previously used in a `net_read` into supported and unsupported
+    pub fn from_net_var_bag<T: NetVar>(b: &NetVarBag<T>) -> Self {
+        let (supported, unsupported) = b.iter()
+            .map(|(n, nv)| (*n, nv.net_get()))
+            .partition::<Vec<_>, _>(|(_, v)| !is_unreported(v));
+        Self {
+            supported: supported.into_iter().map(|(n, _)| n).collect(),
+            unsupported: unsupported.into_iter().map(|(n, _)| n).collect(),
+        }
+    }
+
+    /// True unless the variable was found unsupported. Variables not probed (e.g. `time`) are assumed supported.
+    pub fn supports(&self, name: VarName) -> bool {
+        !self.unsupported.contains(name)
+    }
+
+    /// Checks the pending writes, failing with `InvalidVar` on the first unsupported variable
+    pub fn check<T: NetVar>(&self, mac: &str, vars: &NetVarBag<T>) -> Result<()> {
+        match vars.write_pending().find(|(n, _)| !self.supports(n)) {
+            Some((n, _)) => Err(Error::InvalidVar(format!("{n} (not supported by {mac})"))),
+            None => Ok(())
+        }
     }
 }
 
 /// Information about a gree device on the network.
 /// 
 /// Devices are discovered during scans. The `key` field is set as a result of successful binding.
+/// 
+/// Serializes with the key redacted (see [WithKeys]) and `last_seen` as `last_seen_ago`, the seconds elapsed since.
+/// The other instants are skipped.
+#[derive(Debug, Clone, Serialize)]
 pub struct Device {
     /// IP address of the device
     pub ip: IpAddr,
@@ -97,12 +1114,474 @@ pub struct Device {
     pub scan_result: ScanResponsePack,
 
     /// Encryption key (if bound)
+    #[serde(serialize_with = "serialize_key")]
     pub key: Option<String>,
+
+    /// Protocol variant used by the device. Negotiated during bind.
+    pub variant: ProtocolVariant,
+
+    /// True if the device is statically configured (see [GreeConfig::devices]). Provisioned devices are never evicted
+    /// by scans, and operations on them do not trigger implicit scans.
+    pub provisioned: bool,
+
+    /// False if the device has not answered [GreeConfig::offline_threshold] consecutive requests. Operations on offline
+    /// devices fail with `Error::Offline` without accessing the network until `retry_at`.
+    pub online: bool,
+
+    /// When the device last answered a scan or a request
+    #[serde(rename = "last_seen_ago", serialize_with = "serialize_age")]
+    pub last_seen: Option<Instant>,
+
+    /// Number of consecutive requests the device has not answered
+    pub timeouts: u32,
+
+    /// When an offline device may be retried
+    #[serde(skip)]
+    pub retry_at: Option<Instant>,
+
+    /// Number of consecutive scans the device has not answered
+    pub missed_scans: u32,
+
+    /// When the last operation on the device completed, see [GreeConfig::min_command_gap]
+    #[serde(skip)]
+    pub last_command: Option<Instant>,
+
+    /// Variables the device supports, if probed (see `Gree::probe_capabilities`). Writes to unsupported variables 
+    /// are refused without accessing the network.
+    pub capabilities: Option<Capabilities>,
+
+    /// Sub-units of a multi-split bridge, if listed (see [ScanResponsePack::sub_cnt]). The units are addressed by 
+    /// their MAC addresses; the requests are sent to the bridge.
+    pub sub_devices: Vec<SubDeviceInfo>,
+
+    /// Round-trip times measured by `Gree::ping`
+    pub ping: PingStats,
+}
+
+/// Round-trip times of the pings of a device (see `Gree::ping`). Serializes the times in milliseconds.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct PingStats {
+    /// Number of pings answered
+    pub answered: u32,
+    /// Number of pings failed, e.g. timed out
+    pub failed: u32,
+    /// Round-trip time of the last ping answered
+    #[serde(serialize_with = "serialize_ms")]
+    pub last: Option<Duration>,
+    #[serde(serialize_with = "serialize_ms")]
+    pub min: Option<Duration>,
+    #[serde(serialize_with = "serialize_ms")]
+    pub max: Option<Duration>,
+    /// Mean round-trip time of the pings answered
+    #[serde(serialize_with = "serialize_ms")]
+    pub mean: Option<Duration>,
+}
+
+impl PingStats {
+    /// Records a ping, answered after `rtt` or failed (`None`)
+    pub fn record(&mut self, rtt: Option<Duration>) {
+        let Some(rtt) = rtt else {
+            self.failed = self.failed.saturating_add(1);
+            return
+        };
+        self.answered = self.answered.saturating_add(1);
+        self.last = Some(rtt);
+        self.min = Some(self.min.map_or(rtt, |t| t.min(rtt)));
+        self.max = Some(self.max.map_or(rtt, |t| t.max(rtt)));
+        let mean = self.mean.unwrap_or_default().as_secs_f64();
+        self.mean = Some(Duration::from_secs_f64(mean + (rtt.as_secs_f64() - mean) / self.answered as f64));
+    }
+}
+
+/// Network statistics of a device, kept by the low-level client (see `GreeClient::stats`). Serializes the times in
+/// milliseconds.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct NetStats {
+    /// Request/response exchanges, each counted once regardless of the attempts it took
+    pub requests: u64,
+    /// Attempts repeated for want of a response, see [GreeClientConfig::retry]
+    pub retries: u64,
+    /// Exchanges failed for want of a response, after all the attempts
+    pub timeouts: u64,
+    /// Responses which could not be decrypted or parsed
+    pub decode_errors: u64,
+    /// Round-trip time of the last attempt answered
+    #[serde(serialize_with = "serialize_ms")]
+    pub last_rtt: Option<Duration>,
+}
+
+/// Statistics of a device, as reported by `Gree::stats`
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct DeviceStats {
+    pub ip: IpAddr,
+    /// See [Device::online]
+    pub online: bool,
+    /// Seconds since the device last answered
+    #[serde(rename = "last_seen_ago", serialize_with = "serialize_age")]
+    pub last_seen: Option<Instant>,
+    /// Consecutive requests not answered, see [Device::timeouts]
+    pub consecutive_timeouts: u32,
+    /// See [Device::missed_scans]
+    pub missed_scans: u32,
+    /// Counters of the exchanges with the device; the sub-units of a bridge are counted with the bridge
+    pub net: NetStats,
+    /// See [Device::ping]
+    pub ping: PingStats,
+}
+
+impl GreeState {
+    /// Statistics of the devices known, combining their state with the network statistics `net` of the low-level 
+    /// client
+    pub fn stats(&self, net: &HashMap<MacAddr, NetStats>) -> std::collections::BTreeMap<MacAddr, DeviceStats> {
+        self.devices.iter().map(|(mac, dev)| (mac.clone(), DeviceStats {
+            ip: dev.ip,
+            online: dev.online,
+            last_seen: dev.last_seen,
+            consecutive_timeouts: dev.timeouts,
+            missed_scans: dev.missed_scans,
+            net: net.get(mac).cloned().unwrap_or_default(),
+            ping: dev.ping.clone(),
+        })).collect()
+    }
+}
+
+fn serialize_ms<S: serde::Serializer>(t: &Option<Duration>, s: S) -> std::result::Result<S::Ok, S::Error> {
+    serde::Serialize::serialize(&t.map(|t| t.as_secs_f64() * 1000.0), s)
+}
+
+/// What the device keys serialize as, unless serialized [WithKeys]
+pub const REDACTED: &str = "<redacted>";
+
+thread_local! {
+    static WITH_KEYS: std::cell::Cell<bool> = const { std::cell::Cell::new(false) };
+}
+
+/// Serializes the value with the device keys included. [Device] and [GreeState] serialize with the keys redacted 
+/// otherwise, so that they may be exposed (e.g. by a server) without leaking the keys.
+/// 
+/// ```
+/// # use gree::*;
+/// # fn f(state: &GreeState) -> Result<()> {
+/// let redacted = serde_json::to_string(state)?;
+/// let complete = serde_json::to_string(&WithKeys(state))?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone, Copy)]
+pub struct WithKeys<'a, T>(pub &'a T);
+
+impl<T: serde::Serialize> serde::Serialize for WithKeys<'_, T> {
+    fn serialize<S: serde::Serializer>(&self, s: S) -> std::result::Result<S::Ok, S::Error> {
+        let was = WITH_KEYS.replace(true);
+        let r = self.0.serialize(s);
+        WITH_KEYS.set(was);
+        r
+    }
+}
+
+/// Internalizes the variable names deserialized, skipping the ones not known (e.g. no longer registered, see 
+/// [vars::register])
+fn deserialize_var_names<'de, D: serde::Deserializer<'de>>(d: D) -> std::result::Result<HashSet<VarName>, D::Error> {
+    let names: Vec<String> = serde::Deserialize::deserialize(d)?;
+    Ok(names.iter().filter_map(|n| vars::name_of(n)).collect())
+}
+
+/// Like [deserialize_var_names], for maps by variable name
+fn deserialize_var_map<'de, D: serde::Deserializer<'de>, T: serde::Deserialize<'de>>(d: D) -> std::result::Result<HashMap<VarName, T>, D::Error> {
+    let map: HashMap<String, T> = serde::Deserialize::deserialize(d)?;
+    Ok(map.into_iter().filter_map(|(n, v)| Some((vars::name_of(&n)?, v))).collect())
+}
+
+fn serialize_key<S: serde::Serializer>(key: &Option<String>, s: S) -> std::result::Result<S::Ok, S::Error> {
+    if WITH_KEYS.get() {
+        serde::Serialize::serialize(key, s)
+    } else {
+        serde::Serialize::serialize(&key.as_ref().map(|_| REDACTED), s)
+    }
+}
+
+fn serialize_age<S: serde::Serializer>(t: &Option<Instant>, s: S) -> std::result::Result<S::Ok, S::Error> {
+    serde::Serialize::serialize(&t.map(|t| t.elapsed().as_secs_f64()), s)
 }
 
 impl Device {
-    pub fn bind_ind(&mut self, pack: BindResponsePack) {
-        self.key = Some(pack.key)
+    pub fn new(ip: IpAddr, scan_result: ScanResponsePack, key: Option<String>, variant: ProtocolVariant, provisioned: bool) -> Self {
+        Self { ip, scan_result, key, variant, provisioned, online: true, last_seen: None, timeouts: 0, retry_at: None, missed_scans: 0, 
+            last_command: None, capabilities: None, sub_devices: vec![], ping: PingStats::default() }
+    }
+
+    /// True unless the device is offline and its retry time has not come yet
+    pub fn may_retry(&self, now: Instant) -> bool {
+        self.retry_at.is_none_or(|t| now >= t)
+    }
+
+    /// How long the next operation on the device has to wait to keep `gap` after the last one
+    pub fn command_wait(&self, now: Instant, gap: Duration) -> Duration {
+        self.last_command.map_or(Duration::ZERO, |t| (t + gap).saturating_duration_since(now))
+    }
+
+    /// Records an answer from the device. Returns `DeviceOnline` event if the device was offline.
+    pub fn seen_ind(&mut self, mac: &str, now: Instant) -> Option<GreeEvent> {
+        self.last_seen = Some(now);
+        self.timeouts = 0;
+        self.retry_at = None;
+        if self.online { return None }
+        self.online = true;
+        Some(GreeEvent::DeviceOnline { mac: mac.into() })
+    }
+
+    /// Records a request the device has not answered, and schedules the retry with exponential backoff once the device
+    /// is offline. Returns `DeviceOffline` event if the device has just gone offline.
+    pub fn timeout_ind(&mut self, mac: &str, cfg: &GreeConfig, now: Instant) -> Option<GreeEvent> {
+        self.timeouts = self.timeouts.saturating_add(1);
+        if self.timeouts < cfg.offline_threshold { return None }
+        let exp = (self.timeouts - cfg.offline_threshold).min(31);
+        let backoff = cfg.offline_backoff.saturating_mul(1 << exp).min(cfg.max_offline_backoff);
+        self.retry_at = Some(now + backoff);
+        if !self.online { return None }
+        self.online = false;
+        Some(GreeEvent::DeviceOffline { mac: mac.into() })
+    }
+
+    pub fn bind_ind(&mut self, pack: BindResponsePack, variant: ProtocolVariant) {
+        self.key = Some(pack.key);
+        self.variant = variant;
+    }
+}
+
+
+/// Energy saving profile: `SvSt` on, reduced fan and `SetTem` clamped into a range. 
+/// 
+/// While the profile is active on a device, the high-level client clamps every `SetTem` written to it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EcoProfile {
+    /// Lowest `SetTem` allowed
+    pub min_set_tem: i64,
+    /// Highest `SetTem` allowed
+    pub max_set_tem: i64,
+    /// Fan level set when the profile is applied
+    pub fan_level: vars::FanLevel,
+}
+
+impl EcoProfile {
+    pub const DEFAULT_MIN_SET_TEM: i64 = 20;
+    pub const DEFAULT_MAX_SET_TEM: i64 = 26;
+    pub const DEFAULT_FAN_LEVEL: vars::FanLevel = vars::FanLevel::Low;
+
+    /// Clamps pending `SetTem` write (if any) into the profile's range
+    pub fn enforce<T: NetVar>(&self, vars: &mut NetVarBag<T>) {
+        if let Some(nv) = vars.get_mut(vars::SET_TEM) {
+            if !nv.is_net_write_pending() { return }
+            if let Some(t) = nv.net_get().as_i64() {
+                let clamped = t.clamp(self.min_set_tem, self.max_set_tem);
+                if clamped != t {
+                    debug!("eco: SetTem {t} clamped to {clamped}");
+                    nv.user_set(clamped.into());
+                }
+            }
+        }
+    }
+
+    /// Constructs NetVarBag applying the profile. `set_tem` is the device's current `SetTem`, which is clamped into the range.
+    pub fn to_net_var_bag(&self, set_tem: &Value) -> NetVarBag<SimpleNetVar> {
+        let mut bag = net_var_bag_from_fan_level(self.fan_level);
+        bag.insert(vars::SV_ST, SimpleNetVar::from_value(vars::SvSt::On.into()));
+        if !set_tem.is_null() { 
+            bag.insert(vars::SET_TEM, SimpleNetVar::from_value(set_tem.clone()));
+        }
+        self.enforce(&mut bag);
+        bag
+    }
+}
+
+impl Default for EcoProfile {
+    fn default() -> Self {
+        Self {
+            min_set_tem: Self::DEFAULT_MIN_SET_TEM,
+            max_set_tem: Self::DEFAULT_MAX_SET_TEM,
+            fan_level: Self::DEFAULT_FAN_LEVEL,
+        }
+    }
+}
+
+
+/// Typed state of an air conditioner, read in a single status request by `Gree::read_state`
+/// 
+/// Features not available on all units are `None` if the unit does not report them.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AcState {
+    /// `Pow`
+    pub power: vars::OnOff,
+    /// `Mod`
+    pub mode: vars::Mode,
+    /// `SetTem`, in whole degrees Celsius; see [AcState::temperature] for the exact temperature
+    pub target_temp: u8,
+    /// `TemRec`
+    pub temp_rec: Option<vars::OnOff>,
+    /// `TemUn`
+    pub temp_unit: vars::TemUn,
+    /// `WdSpd`, `Tur` and `Quiet`
+    pub fan: vars::FanLevel,
+    /// `SwUpDn`
+    pub swing: vars::SwUpDn,
+    /// `SwingLfRig`
+    pub swing_horizontal: Option<vars::SwingLfRig>,
+    /// `Lig`
+    pub light: vars::OnOff,
+    /// `Health`
+    pub health: Option<vars::OnOff>,
+    /// `SwhSlp`
+    pub sleep: vars::OnOff,
+    /// `Blo`
+    pub x_fan: vars::OnOff,
+    /// `Air`
+    pub fresh_air: Option<vars::OnOff>,
+    /// `SvSt`
+    pub energy_saving: vars::OnOff,
+}
+
+impl AcState {
+    /// Variables making up the state
+    pub const VARS: [VarName; 16] = [
+        vars::POW, vars::MOD, vars::SET_TEM, vars::TEM_REC, vars::TEM_UN, vars::WD_SPD, vars::TUR, vars::QUIET, vars::SW_UP_DN,
+        vars::SWING_LF_RIG, vars::LIG, vars::HEALTH, vars::SWH_SLP, vars::BLO, vars::AIR, vars::SV_ST,
+    ];
+
+    /// Decodes the state from a NetVarBag previously used in a `net_read` (see [net_var_bag_for_ac_state])
+    pub fn from_net_var_bag<T: NetVar>(b: &NetVarBag<T>) -> Result<Self> {
+        let get = |n| b.get(n).map(|nv| nv.net_get()).unwrap_or(&Value::Null);
+        fn required<V: for<'v> TryFrom<&'v Value, Error = Error>>(v: &Value) -> Result<V> {
+            V::try_from(v)
+        }
+        // units lacking the feature report an empty string
+        fn optional<V: for<'v> TryFrom<&'v Value, Error = Error>>(v: &Value) -> Option<V> {
+            V::try_from(v).ok()
+        }
+        let set_tem = get(vars::SET_TEM);
+        Ok(Self {
+            power: required(get(vars::POW))?,
+            mode: required(get(vars::MOD))?,
+            target_temp: set_tem.as_u64().and_then(|t| u8::try_from(t).ok())
+                .ok_or_else(|| Error::invalid_value(vars::SET_TEM, &set_tem.to_string()))?,
+            temp_rec: optional(get(vars::TEM_REC)),
+            temp_unit: required(get(vars::TEM_UN))?,
+            fan: net_var_bag_to_fan_level(b)?,
+            swing: required(get(vars::SW_UP_DN))?,
+            swing_horizontal: optional(get(vars::SWING_LF_RIG)),
+            light: required(get(vars::LIG))?,
+            health: optional(get(vars::HEALTH)),
+            sleep: required(get(vars::SWH_SLP))?,
+            x_fan: required(get(vars::BLO))?,
+            fresh_air: optional(get(vars::AIR)),
+            energy_saving: required(get(vars::SV_ST))?,
+        })
+    }
+
+    /// The set temperature in `temp_unit`, see [vars::Temperature]
+    pub fn temperature(&self) -> vars::Temperature {
+        let tem_rec = self.temp_rec.map(Value::from).unwrap_or_default();
+        vars::Temperature::from_values(&self.target_temp.into(), &tem_rec, &self.temp_unit.into())
+            .unwrap_or(vars::Temperature::Celsius(self.target_temp.into()))
+    }
+}
+
+/// Changes to the state of an air conditioner, written in a single command by `Gree::apply`. Fields left `None` are 
+/// not written.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AcStateDelta {
+    pub power: Option<vars::OnOff>,
+    pub mode: Option<vars::Mode>,
+    pub target_temp: Option<u8>,
+    pub temp_rec: Option<vars::OnOff>,
+    pub temp_unit: Option<vars::TemUn>,
+    pub fan: Option<vars::FanLevel>,
+    pub swing: Option<vars::SwUpDn>,
+    pub swing_horizontal: Option<vars::SwingLfRig>,
+    pub light: Option<vars::OnOff>,
+    pub health: Option<vars::OnOff>,
+    pub sleep: Option<vars::OnOff>,
+    pub x_fan: Option<vars::OnOff>,
+    pub fresh_air: Option<vars::OnOff>,
+    pub energy_saving: Option<vars::OnOff>,
+}
+
+impl AcStateDelta {
+    /// True if there is nothing to write
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Sets `target_temp`, `temp_rec` and `temp_unit` consistently, see [vars::Temperature]
+    pub fn set_temperature(&mut self, t: vars::Temperature) {
+        let [set_tem, tem_rec, tem_un] = t.to_values();
+        self.target_temp = set_tem.as_u64().and_then(|t| u8::try_from(t).ok());
+        self.temp_rec = vars::OnOff::try_from(tem_rec).ok();
+        self.temp_unit = vars::TemUn::try_from(tem_un).ok();
+    }
+
+    /// Constructs NetVarBag writing the changes
+    pub fn to_net_var_bag(&self) -> NetVarBag<SimpleNetVar> {
+        let mut bag = self.fan.map(net_var_bag_from_fan_level).unwrap_or_default();
+        let values = [
+            (vars::POW, self.power.map(Value::from)),
+            (vars::MOD, self.mode.map(Value::from)),
+            (vars::SET_TEM, self.target_temp.map(Value::from)),
+            (vars::TEM_REC, self.temp_rec.map(Value::from)),
+            (vars::TEM_UN, self.temp_unit.map(Value::from)),
+            (vars::SW_UP_DN, self.swing.map(Value::from)),
+            (vars::SWING_LF_RIG, self.swing_horizontal.map(Value::from)),
+            (vars::LIG, self.light.map(Value::from)),
+            (vars::HEALTH, self.health.map(Value::from)),
+            (vars::SWH_SLP, self.sleep.map(Value::from)),
+            (vars::BLO, self.x_fan.map(Value::from)),
+            (vars::AIR, self.fresh_air.map(Value::from)),
+            (vars::SV_ST, self.energy_saving.map(Value::from)),
+        ];
+        bag.extend(values.into_iter().filter_map(|(n, v)| Some((n, SimpleNetVar::from_value(v?)))));
+        bag
+    }
+}
+
+
+/// A preset of variable values (e.g. "night": `Pow`=1, `Mod`=Cool, `SetTem`=26, `Quiet`=1), written in a single 
+/// command by `Gree::apply_scene`
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Scene {
+    pub values: VarValues,
+}
+
+impl Scene {
+    /// Parses the scene from (name, value) pairs, validating the values (see [vars::parse_value])
+    pub fn parse<'t, S: AsRef<str> + 't>(nvs: impl IntoIterator<Item = (&'t S, &'t S)>) -> Result<Self> {
+        let mut values = VarValues::new();
+        for (name, value) in nvs {
+            let name = vars::name_of(name.as_ref()).ok_or_else(|| Error::InvalidVar(name.as_ref().to_owned()))?;
+            values.insert(name, vars::parse_value(name, value)?);
+        }
+        Ok(Self { values })
+    }
+
+    /// Constructs NetVarBag writing the scene
+    pub fn to_net_var_bag(&self) -> NetVarBag<SimpleNetVar> {
+        self.values.iter().map(|(n, v)| (*n, SimpleNetVar::from_value(v.clone()))).collect()
+    }
+}
+
+/// All the readable variables of a device at a point in time, see `Gree::snapshot`
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct DeviceSnapshot {
+    pub mac: MacAddr,
+    pub ip: IpAddr,
+    /// When the values were received
+    pub taken_at: SystemTime,
+    pub values: VarValues,
+}
+
+impl DeviceSnapshot {
+    /// Variables read: [vars::ALL] except `time`, which can not be read along with the others, and the registered 
+    /// variables (see [vars::register])
+    pub fn vars() -> impl Iterator<Item = VarName> {
+        vars::ALL.into_iter().filter(|n| *n != vars::TIME).chain(vars::registered())
     }
 }
 
@@ -115,6 +1594,8 @@ pub trait NetVar {
     fn net_set(&mut self, value: Value);
     /// Returns the value to be written to the network
     fn net_get(&self) -> &Value;
+    /// Sets the value to be written to the network, leaving net_read_pending and the time of the last read as they are
+    fn user_set(&mut self, value: Value);
     /// True if the value of this NetVar is supposed to be read and set from the network
     fn is_net_read_pending(&self) -> bool;
     /// True if the value of this NetVar is supposed to be written to the network
@@ -129,11 +1610,18 @@ pub struct SimpleNetVar {
     value: Value,
     net_read_pending: bool,
     net_write_pending: bool,
+    updated: Option<SystemTime>,
+}
+
+impl Default for SimpleNetVar {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl SimpleNetVar {
     pub fn new() -> Self {
-        Self { value: Value::Null, net_read_pending: true, net_write_pending: false }
+        Self { value: Value::Null, net_read_pending: true, net_write_pending: false, updated: None }
     }
 
     /// Parses variable setting and adds it to a `NetVarBag`. The `NetVarBag` might then be used for a `net_write`.
@@ -155,19 +1643,24 @@ impl SimpleNetVar {
 
     /// Creates a `SimpleNetVar` from a value. The `SimpleNetVar` might then be used for a `net_write`. 
     pub fn from_value(value: Value) -> Self {
-        Self { value, net_read_pending: false, net_write_pending: true }
-    }
-
-    /// Sets a value of the `SimpleNetVar` from the user side. The `SimpleNetVar` might then be used for a `net_write`. 
-    pub fn user_set(&mut self, value: Value) {
-        self.value = value;
-        self.net_write_pending = true;
+        Self { value, net_read_pending: false, net_write_pending: true, updated: None }
     }
 
     /// Gets a value of the `SimpleNetVar` from the user side, typically after a `net_read`.
     pub fn user_get(&self) -> &Value {
         &self.value
     }
+
+    /// When the value was last received from the network (by a read, or echoed by a write); `None` if it never was
+    pub fn updated(&self) -> Option<SystemTime> {
+        self.updated
+    }
+
+    /// True if the value has not been received from the network within `ttl`, or at all
+    pub fn is_stale(&self, ttl: Duration) -> bool {
+        // a clock set back makes the value look fresh rather than failing
+        self.updated.is_none_or(|t| t.elapsed().unwrap_or_default() > ttl)
+    }
 }
 
 impl NetVar for SimpleNetVar {
@@ -175,39 +1668,250 @@ impl NetVar for SimpleNetVar {
     fn net_set(&mut self, value: Value) { 
         self.value = value;
         self.net_read_pending = false;
+        self.updated = Some(SystemTime::now());
     }
     fn net_get(&self) -> &Value { &self.value }
+    /// Sets a value of the `SimpleNetVar` from the user side. The `SimpleNetVar` might then be used for a `net_write`. 
+    fn user_set(&mut self, value: Value) {
+        self.value = value;
+        self.net_write_pending = true;
+    }
     fn is_net_read_pending(&self) -> bool { self.net_read_pending }
     fn is_net_write_pending(&self) -> bool { self.net_write_pending }
     fn clear_net_write_pending(&mut self) { self.net_write_pending = false }
 }
 
 /// A collection of network variables by internalized name
-pub type NetVarBag<T> = HashMap<VarName, T>;
+/// 
+/// The variables are kept in insertion order, which is the order of the cols in the requests. Inserting a variable 
+/// already present replaces it in place. The interface mirrors that of `HashMap`; bags are small, so lookups are 
+/// linear.
+#[derive(Debug, Clone, PartialEq)]
+pub struct NetVarBag<T> {
+    vars: Vec<(VarName, T)>,
+}
+
+/// The former, unordered `NetVarBag`. Convert with `NetVarBag::from`.
+#[deprecated(note = "use NetVarBag, which keeps the insertion order")]
+pub type NetVarMap<T> = HashMap<VarName, T>;
+
+impl<T> Default for NetVarBag<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> NetVarBag<T> {
+    pub fn new() -> Self {
+        Self { vars: vec![] }
+    }
+
+    pub fn len(&self) -> usize {
+        self.vars.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.vars.is_empty()
+    }
+
+    pub fn contains_key(&self, name: &str) -> bool {
+        self.get(name).is_some()
+    }
+
+    pub fn get(&self, name: &str) -> Option<&T> {
+        self.vars.iter().find(|(n, _)| *n == name).map(|(_, v)| v)
+    }
+
+    pub fn get_mut(&mut self, name: &str) -> Option<&mut T> {
+        self.vars.iter_mut().find(|(n, _)| *n == name).map(|(_, v)| v)
+    }
+
+    /// Inserts the variable, returning the one it replaced, if any. A replaced variable keeps its position.
+    pub fn insert(&mut self, name: VarName, value: T) -> Option<T> {
+        match self.get_mut(name) {
+            Some(v) => Some(std::mem::replace(v, value)),
+            None => { self.vars.push((name, value)); None }
+        }
+    }
+
+    pub fn remove(&mut self, name: &str) -> Option<T> {
+        let i = self.vars.iter().position(|(n, _)| *n == name)?;
+        Some(self.vars.remove(i).1)
+    }
+
+    /// Variables in insertion order
+    pub fn iter(&self) -> impl Iterator<Item = (&VarName, &T)> {
+        self.vars.iter().map(|(n, v)| (n, v))
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = (&VarName, &mut T)> {
+        self.vars.iter_mut().map(|(n, v)| (&*n, v))
+    }
+
+    pub fn keys(&self) -> impl Iterator<Item = &VarName> {
+        self.vars.iter().map(|(n, _)| n)
+    }
+
+    pub fn values(&self) -> impl Iterator<Item = &T> {
+        self.vars.iter().map(|(_, v)| v)
+    }
+
+    pub fn values_mut(&mut self) -> impl Iterator<Item = &mut T> {
+        self.vars.iter_mut().map(|(_, v)| v)
+    }
+}
+
+impl<T: NetVar> NetVarBag<T> {
+    /// Names of the variables pending read, in order
+    pub fn read_pending(&self) -> impl Iterator<Item = VarName> + '_ {
+        self.vars.iter().filter(|(_, v)| v.is_net_read_pending()).map(|(n, _)| *n)
+    }
+
+    /// Names and values of the variables pending write, in order
+    pub fn write_pending(&self) -> impl Iterator<Item = (VarName, &Value)> {
+        self.vars.iter().filter(|(_, v)| v.is_net_write_pending()).map(|(n, v)| (*n, v.net_get()))
+    }
+
+    /// Values of the variables. Convenient for value reporting.
+    pub fn to_json(&self) -> VarValues {
+        self.iter().map(|(k, v)| (*k, v.net_get().clone())).collect()
+    }
+}
+
+impl<T> std::ops::Index<&str> for NetVarBag<T> {
+    type Output = T;
+    fn index(&self, name: &str) -> &T {
+        self.get(name).unwrap_or_else(|| panic!("{name} not in the bag"))
+    }
+}
+
+impl<T> FromIterator<(VarName, T)> for NetVarBag<T> {
+    fn from_iter<I: IntoIterator<Item = (VarName, T)>>(iter: I) -> Self {
+        let mut bag = Self::new();
+        bag.extend(iter);
+        bag
+    }
+}
+
+impl<T> Extend<(VarName, T)> for NetVarBag<T> {
+    fn extend<I: IntoIterator<Item = (VarName, T)>>(&mut self, iter: I) {
+        for (n, v) in iter { self.insert(n, v); }
+    }
+}
+
+impl<T> IntoIterator for NetVarBag<T> {
+    type Item = (VarName, T);
+    type IntoIter = std::vec::IntoIter<(VarName, T)>;
+    fn into_iter(self) -> Self::IntoIter {
+        self.vars.into_iter()
+    }
+}
+
+impl<T> From<HashMap<VarName, T>> for NetVarBag<T> {
+    fn from(m: HashMap<VarName, T>) -> Self {
+        m.into_iter().collect()
+    }
+}
 
 /// Constructs NetVarBag from an iterator of names. The bag returned is ready to be used in a network read call.
 pub fn net_var_bag_from_names<'t, S: AsRef<str> + 't>(mut ns: impl Iterator<Item = &'t S>) -> Result<NetVarBag<SimpleNetVar>> {
-    ns.try_fold(std::collections::HashMap::new(), SimpleNetVar::add_n_to)
+    ns.try_fold(NetVarBag::new(), SimpleNetVar::add_n_to)
 }
 
 /// Constructs NetVarBag from an iterator of (name, value) pairs. The bag returned is ready to be used in a network write call.
 pub fn net_var_bag_from_nvs<'t, S: AsRef<str> + 't>(mut nvs: impl Iterator<Item = (&'t S, &'t S)>) -> Result<NetVarBag<SimpleNetVar>> {
-    nvs.try_fold(std::collections::HashMap::new(), SimpleNetVar::add_nv_to)
+    nvs.try_fold(NetVarBag::new(), SimpleNetVar::add_nv_to)
 }
 
 /// Converts NetVarBag into a json. Convenient for value reporting.
+#[deprecated(note = "use NetVarBag::to_json")]
 pub fn net_var_bag_to_json<T: NetVar>(b: &NetVarBag<T>) -> HashMap<VarName, Value> {
-    b.into_iter().map(|(k, v)| (*k, v.net_get().clone())).collect()
+    b.to_json()
+}
+
+/// Constructs NetVarBag for reading the fan level. See [vars::FanLevel].
+pub fn net_var_bag_for_fan_level() -> NetVarBag<SimpleNetVar> {
+    vars::FanLevel::VARS.into_iter().map(|n| (n, SimpleNetVar::new())).collect()
+}
+
+/// Constructs NetVarBag for writing the fan level. See [vars::FanLevel].
+pub fn net_var_bag_from_fan_level(level: vars::FanLevel) -> NetVarBag<SimpleNetVar> {
+    vars::FanLevel::VARS.into_iter().zip(level.to_values()).map(|(n, v)| (n, SimpleNetVar::from_value(v))).collect()
+}
+
+/// Decodes the fan level from a NetVarBag previously used in a `net_read`. See [vars::FanLevel].
+pub fn net_var_bag_to_fan_level<T: NetVar>(b: &NetVarBag<T>) -> Result<vars::FanLevel> {
+    let get = |n| b.get(n).map(|nv| nv.net_get()).unwrap_or(&Value::Null);
+    let [wd_spd, tur, quiet] = vars::FanLevel::VARS.map(get);
+    vars::FanLevel::from_values(wd_spd, tur, quiet)
+        .ok_or_else(|| Error::invalid_value(vars::WD_SPD, &wd_spd.to_string()))
+}
+
+/// Constructs NetVarBag for reading the temperature. See [vars::Temperature].
+pub fn net_var_bag_for_temperature() -> NetVarBag<SimpleNetVar> {
+    vars::Temperature::VARS.into_iter().map(|n| (n, SimpleNetVar::new())).collect()
+}
+
+/// Constructs NetVarBag for writing the temperature. See [vars::Temperature].
+pub fn net_var_bag_from_temperature(t: vars::Temperature) -> NetVarBag<SimpleNetVar> {
+    vars::Temperature::VARS.into_iter().zip(t.to_values()).map(|(n, v)| (n, SimpleNetVar::from_value(v))).collect()
+}
+
+/// Decodes the temperature from a NetVarBag previously used in a `net_read`. See [vars::Temperature].
+pub fn net_var_bag_to_temperature<T: NetVar>(b: &NetVarBag<T>) -> Result<vars::Temperature> {
+    let get = |n| b.get(n).map(|nv| nv.net_get()).unwrap_or(&Value::Null);
+    let [set_tem, tem_rec, tem_un] = vars::Temperature::VARS.map(get);
+    vars::Temperature::from_values(set_tem, tem_rec, tem_un)
+        .ok_or_else(|| Error::invalid_value(vars::SET_TEM, &set_tem.to_string()))
+}
+
+/// Samples of the variables read into the NetVarBag, for recording them to a [HistorySink]. Variables the device 
+/// reported nothing for are skipped.
+pub fn history_samples<T: NetVar>(mac: &str, names: &[VarName], b: &NetVarBag<T>) -> Vec<HistorySample> {
+    let at = SystemTime::now();
+    names.iter()
+        .filter_map(|n| Some((*n, b.get(n)?.net_get())))
+        .filter(|(_, v)| !is_unreported(v))
+        .map(|(name, value)| HistorySample { mac: mac.into(), name, value: value.clone(), at })
+        .collect()
+}
+
+/// Constructs NetVarBag of the variables pending write, for writing them to a member of a group
+pub fn net_var_bag_of_writes<T: NetVar>(b: &NetVarBag<T>) -> NetVarBag<SimpleNetVar> {
+    b.write_pending().map(|(k, v)| (k, SimpleNetVar::from_value(v.clone()))).collect()
+}
+
+/// Combines the results of an operation on the members of a group: `Error::Group` lists the members failed
+pub fn group_result<'t>(results: impl IntoIterator<Item = (&'t str, Result<()>)>) -> Result<()> {
+    let errors: Vec<(MacAddr, Error)> = results.into_iter().filter_map(|(mac, r)| Some((mac.into(), r.err()?))).collect();
+    if errors.is_empty() { Ok(()) } else { Err(Error::Group(errors)) }
+}
+
+/// Constructs NetVarBag for reading the state of an air conditioner. See [AcState].
+pub fn net_var_bag_for_ac_state() -> NetVarBag<SimpleNetVar> {
+    AcState::VARS.into_iter().map(|n| (n, SimpleNetVar::new())).collect()
 }
 
 /// Constructs NetVarBag of [SimpleNetVar]s, for reading (from keys) or writing (from key => value pairs)
+///
+/// ```
+/// # use gree::{*, vars::*};
+/// # fn main() -> Result<()> {
+/// let read = gree::net_var_bag!(POW, "SetTem")?;
+/// assert_eq!(read.len(), 2);
+/// let write = gree::net_var_bag!(POW => "on", SET_TEM => "23")?;
+/// assert_eq!(write[SET_TEM].net_get(), &Value::from(23));
+/// assert!(gree::net_var_bag!("NoSuchVar").is_err());
+/// # Ok(())
+/// # }
+/// ```
 #[macro_export]
 macro_rules! net_var_bag {
     ($($var:expr => $val:expr),+) => {
-        [$(($var, $val)),+].into_iter().try_fold(std::collections::HashMap::new(), gree::SimpleNetVar::add_nv_to)
+        [$(($var, $val)),+].into_iter().try_fold($crate::NetVarBag::new(), $crate::SimpleNetVar::add_nv_to)
     };
     ($($var:expr),+) => {
-        [$($var),+].into_iter().try_fold(std::collections::HashMap::new(), gree::SimpleNetVar::add_n_to)
+        [$($var),+].into_iter().try_fold($crate::NetVarBag::new(), $crate::SimpleNetVar::add_n_to)
     };
 }
 
@@ -217,4 +1921,140 @@ pub enum Op<'t, T: NetVar> {
     Bind,
     NetRead(&'t mut NetVarBag<T>),
     NetWrite(&'t mut NetVarBag<T>),
+    /// Sends the pack of the type `pack_type` with the fields of `body` (a JSON object) and the unit's `mac`, and 
+    /// replaces `body` with the pack received in response. Meant for experimenting with the pack types not supported 
+    /// otherwise; the response is neither interpreted nor checked for a result code.
+    Raw { pack_type: &'t str, body: &'t mut Value },
+}
+
+/// Pack of the type `pack_type` with the fields of `body` and `mac`, see [Op::Raw]
+pub fn raw_pack(pack_type: &str, mac: &str, body: &Value) -> Result<Value> {
+    let Value::Object(fields) = body else { return Err(Error::Config(format!("body of `{pack_type}` pack is not a JSON object"))) };
+    let mut pack = fields.clone();
+    pack.insert("t".to_owned(), pack_type.into());
+    pack.insert("mac".to_owned(), mac.into());
+    Ok(Value::Object(pack))
+}
+
+impl<T: NetVar> Op<'_, T> {
+    /// Operation name, for diagnostics
+    pub fn name(&self) -> &'static str {
+        match self {
+            Op::Bind => "bind",
+            Op::NetRead(_) => "net_read",
+            Op::NetWrite(_) => "net_write",
+            Op::Raw { .. } => "raw",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mac_addr_parses_common_notations() {
+        for s in ["000cc0000001", "000CC0000001", "00:0c:c0:00:00:01", "00:0C:C0:00:00:01", "00-0c-c0-00-00-01", "000c.c000.0001", " 000cc0000001 "] {
+            assert_eq!(s.parse::<MacAddr>().unwrap().as_str(), "000cc0000001", "{s}");
+        }
+    }
+
+    #[test]
+    fn mac_addr_rejects_invalid() {
+        for s in ["", "000cc000000", "000cc00000011", "000cc000000g", "00:0c:c0:00:00", "00:0c:c0-00:00:01", "0:0c:c0:00:00:001"] {
+            assert!(matches!(s.parse::<MacAddr>(), Err(Error::InvalidMacAddr(e)) if e == s), "{s}");
+        }
+    }
+
+    #[test]
+    fn mac_addr_from_keeps_invalid_strings() {
+        assert_eq!(MacAddr::from("00-0C-C0-00-00-01").as_str(), "000cc0000001");
+        assert_eq!(MacAddr::from("Living Room").as_str(), "Living Room");
+        assert_eq!(MacAddr::from(String::from("sub:1")).as_str(), "sub:1");
+    }
+
+    #[test]
+    fn mac_addr_compares_normalized() {
+        let mac = MacAddr::from("00:0C:C0:00:00:01");
+        assert_eq!(mac, "000cc0000001");
+        assert_eq!(mac, "000c.c000.0001");
+        assert_eq!(mac, *"00-0c-c0-00-00-01");
+        assert_ne!(mac, "000cc0000002");
+        assert_eq!(MacAddr::from("Living Room"), "Living Room");
+        assert_ne!(MacAddr::from("Living Room"), "living room");
+    }
+
+    const IP1: IpAddr = IpAddr::V4(std::net::Ipv4Addr::new(192, 168, 1, 10));
+    const IP2: IpAddr = IpAddr::V4(std::net::Ipv4Addr::new(192, 168, 1, 11));
+
+    fn scanned(ip: IpAddr, mac: &str) -> (IpAddr, GenericMessage, ScanResponsePack) {
+        (ip, GenericMessage::default(), ScanResponsePack { mac: mac.to_owned(), cid: mac.to_owned(), ..Default::default() })
+    }
+
+    #[test]
+    fn rescan_keeps_keys_and_reports_ip_changes() {
+        let mut state = GreeState::new();
+        let diff = state.scan_ind(vec![scanned(IP1, "000cc0000001")], 3);
+        assert_eq!(diff.added, [(MacAddr::from("000cc0000001"), IP1)]);
+        state.devices.get_mut("000cc0000001").unwrap().key = Some("key".to_owned());
+
+        let diff = state.scan_ind(vec![scanned(IP1, "000cc0000001")], 3);
+        assert!(diff.is_empty(), "{diff:?}");
+        let diff = state.scan_ind(vec![scanned(IP2, "000cc0000001")], 3);
+        assert_eq!(diff.ip_changed, [(MacAddr::from("000cc0000001"), IP1, IP2)]);
+        assert!(diff.added.is_empty());
+        let dev = &state.devices["000cc0000001"];
+        assert_eq!((dev.ip, dev.key.as_deref()), (IP2, Some("key")));
+    }
+
+    #[test]
+    fn devices_missing_from_scans_are_evicted() {
+        let mut state = GreeState::new();
+        state.scan_ind(vec![scanned(IP1, "000cc0000001"), scanned(IP2, "000cc0000002")], 2);
+
+        let diff = state.scan_ind(vec![scanned(IP2, "000cc0000002")], 2);
+        assert!(diff.removed.is_empty());
+        assert_eq!(state.devices["000cc0000001"].missed_scans, 1);
+        // answering resets the count
+        state.scan_ind(vec![scanned(IP1, "000cc0000001"), scanned(IP2, "000cc0000002")], 2);
+        assert_eq!(state.devices["000cc0000001"].missed_scans, 0);
+
+        state.scan_ind(vec![scanned(IP2, "000cc0000002")], 2);
+        let diff = state.scan_ind(vec![scanned(IP2, "000cc0000002")], 2);
+        assert_eq!(diff.removed, [MacAddr::from("000cc0000001")]);
+        assert_eq!(state.devices.keys().collect::<Vec<_>>(), [&MacAddr::from("000cc0000002")]);
+    }
+
+    #[test]
+    fn provisioned_devices_are_never_evicted() {
+        let mut state = GreeState::new();
+        let (ip, _, pack) = scanned(IP1, "000cc0000001");
+        let mut dev = Device::new(ip, pack, Some("key".to_owned()), ProtocolVariant::V1, true);
+        let now = Instant::now();
+        dev.last_seen = Some(now);
+        state.devices.insert("000cc0000001".into(), dev);
+
+        for _ in 0..3 {
+            assert!(state.scan_ind(vec![], 1).removed.is_empty());
+        }
+        assert!(state.devices.contains_key("000cc0000001"));
+
+        state.scan_ind(vec![scanned(IP2, "000cc0000002")], 1);
+        let later = now + Duration::from_secs(3600);
+        assert_eq!(state.evict_stale(Duration::from_secs(60), later), [MacAddr::from("000cc0000002")]);
+        assert!(state.devices.contains_key("000cc0000001"));
+    }
+
+    #[test]
+    fn eco_enforce_clamps_only_the_write() {
+        let eco = EcoProfile::default();
+        let mut bag = NetVarBag::new();
+        bag.insert(vars::SET_TEM, SimpleNetVar::from_value(Value::from(EcoProfile::DEFAULT_MAX_SET_TEM + 3)));
+        eco.enforce(&mut bag);
+        let nv = &bag[vars::SET_TEM];
+        assert_eq!(*nv.user_get(), EcoProfile::DEFAULT_MAX_SET_TEM);
+        assert!(nv.is_net_write_pending());
+        assert!(!nv.is_net_read_pending());
+        assert_eq!(nv.updated(), None);
+    }
 }
@@ -0,0 +1,166 @@
+//! Persistent storage of device bindings
+//! 
+//! Keys obtained by binds are stored, so that devices need not be rebound after a restart. 
+//! See [GreeConfig::store](crate::GreeConfig::store). [EncryptedStateStore] (requires `encrypted-store`) encrypts the 
+//! keys stored with a passphrase.
+
+use std::{collections::HashMap, fmt::Debug, net::IpAddr, path::PathBuf};
+use serde_derive::{Serialize, Deserialize};
+use crate::{*, state::MacAddr};
+
+/// Persistent part of a bound device's state. Also the unit of `Gree::export_bindings`/`Gree::import_bindings`, 
+/// which accept the field names of Home Assistant's Gree integration (`host`, `encryption_key`) as well.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DeviceBinding {
+    /// MAC address of the device. May be empty in the stores, which are keyed by the MAC address.
+    #[serde(default)]
+    pub mac: MacAddr,
+    /// IP address of the device
+    #[serde(alias = "host")]
+    pub ip: IpAddr,
+    /// Encryption key
+    #[serde(alias = "encryption_key")]
+    pub key: String,
+    /// Protocol variant
+    #[serde(default)]
+    pub variant: ProtocolVariant,
+}
+
+/// Storage backend for device bindings
+pub trait StateStore: Debug + Send + Sync {
+    /// Loads the bindings. An empty map should be returned if nothing was stored yet.
+    fn load(&self) -> Result<HashMap<MacAddr, DeviceBinding>>;
+    /// Stores the bindings, replacing the ones stored before
+    fn save(&self, bindings: &HashMap<MacAddr, DeviceBinding>) -> Result<()>;
+}
+
+/// [StateStore] keeping the bindings in a JSON file
+#[derive(Debug, Clone)]
+pub struct FileStateStore {
+    path: PathBuf,
+}
+
+impl FileStateStore {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+}
+
+impl StateStore for FileStateStore {
+    fn load(&self) -> Result<HashMap<MacAddr, DeviceBinding>> {
+        match std::fs::read(&self.path) {
+            Ok(b) => Ok(serde_json::from_slice(&b)?),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(HashMap::new()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn save(&self, bindings: &HashMap<MacAddr, DeviceBinding>) -> Result<()> {
+        trace!("saving {} binding(s) to {:?}", bindings.len(), self.path);
+        let tmp = self.path.with_extension("tmp");
+        std::fs::write(&tmp, serde_json::to_vec_pretty(bindings)?)?;
+        std::fs::rename(tmp, &self.path)?;
+        Ok(())
+    }
+}
+
+/// Prefix of the keys encrypted by [EncryptedStateStore], followed by the base64 of the salt, the nonce and the 
+/// ciphertext
+#[cfg(feature = "encrypted-store")]
+const ENCRYPTED_KEY_PREFIX: &str = "enc1:";
+#[cfg(feature = "encrypted-store")]
+const SALT_LEN: usize = 16;
+#[cfg(feature = "encrypted-store")]
+const NONCE_LEN: usize = 12;
+
+/// [StateStore] wrapper encrypting the device keys with a passphrase (requires `encrypted-store`), as the keys grant 
+/// full control of the devices. The keys are encrypted with AES-256-GCM under a key derived from the passphrase with 
+/// Argon2id; the IP addresses and the protocol variants are stored in the clear.
+/// 
+/// Keys stored unencrypted before are loaded as they are, and encrypted by the next save. Loading fails with 
+/// `Error::Crypto` if the passphrase is wrong.
+/// 
+/// ```no_run
+/// # use std::sync::Arc;
+/// # use gree::*;
+/// let mut cfg = GreeConfig::default();
+/// let store = EncryptedStateStore::new(FileStateStore::new("/var/lib/gree/bindings.json"), "correct horse battery staple");
+/// cfg.store = Some(Arc::new(store));
+/// ```
+#[cfg(feature = "encrypted-store")]
+pub struct EncryptedStateStore<S> {
+    inner: S,
+    passphrase: Vec<u8>,
+    /// Salt of the keys saved, generated per instance
+    salt: [u8; SALT_LEN],
+    /// Ciphers by salt, as the derivation is deliberately slow
+    ciphers: std::sync::Mutex<HashMap<[u8; SALT_LEN], aes_gcm::Aes256Gcm>>,
+}
+
+#[cfg(feature = "encrypted-store")]
+impl<S: StateStore> EncryptedStateStore<S> {
+    pub fn new(inner: S, passphrase: impl AsRef<[u8]>) -> Self {
+        use aes_gcm::aead::{OsRng, rand_core::RngCore};
+        let mut salt = [0; SALT_LEN];
+        OsRng.fill_bytes(&mut salt);
+        Self { inner, passphrase: passphrase.as_ref().to_vec(), salt, ciphers: Default::default() }
+    }
+
+    fn with_cipher<R>(&self, salt: [u8; SALT_LEN], f: impl FnOnce(&aes_gcm::Aes256Gcm) -> R) -> Result<R> {
+        use aes_gcm::KeyInit;
+        let mut ciphers = lock(&self.ciphers);
+        let cipher = match ciphers.entry(salt) {
+            std::collections::hash_map::Entry::Occupied(e) => e.into_mut(),
+            std::collections::hash_map::Entry::Vacant(e) => {
+                let mut key = [0; 32];
+                argon2::Argon2::default().hash_password_into(&self.passphrase, &salt, &mut key).map_err(|_| Error::Crypto)?;
+                e.insert(aes_gcm::Aes256Gcm::new(&key.into()))
+            }
+        };
+        Ok(f(cipher))
+    }
+
+    fn encrypt(&self, key: &str) -> Result<String> {
+        use aes_gcm::{AeadCore, aead::{Aead, OsRng}};
+        use base64::{Engine as _, engine::general_purpose};
+        let nonce = aes_gcm::Aes256Gcm::generate_nonce(&mut OsRng);
+        let ct = self.with_cipher(self.salt, |c| c.encrypt(&nonce, key.as_bytes()))?.map_err(|_| Error::Crypto)?;
+        Ok(format!("{ENCRYPTED_KEY_PREFIX}{}", general_purpose::STANDARD.encode([&self.salt[..], &nonce, &ct].concat())))
+    }
+
+    fn decrypt(&self, stored: &str) -> Result<String> {
+        use aes_gcm::{Nonce, aead::Aead};
+        use base64::{Engine as _, engine::general_purpose};
+        let Some(b) = stored.strip_prefix(ENCRYPTED_KEY_PREFIX) else { return Ok(stored.to_owned()) };
+        let b = general_purpose::STANDARD.decode(b)?;
+        if b.len() < SALT_LEN + NONCE_LEN { return Err(Error::Crypto) }
+        let (salt, rest) = b.split_at(SALT_LEN);
+        let (nonce, ct) = rest.split_at(NONCE_LEN);
+        let salt = salt.try_into().map_err(|_| Error::Crypto)?;
+        let key = self.with_cipher(salt, |c| c.decrypt(Nonce::from_slice(nonce), ct))?.map_err(|_| Error::Crypto)?;
+        String::from_utf8(key).map_err(|_| Error::Crypto)
+    }
+}
+
+#[cfg(feature = "encrypted-store")]
+impl<S: Debug> Debug for EncryptedStateStore<S> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("EncryptedStateStore").field("inner", &self.inner).finish_non_exhaustive()
+    }
+}
+
+#[cfg(feature = "encrypted-store")]
+impl<S: StateStore> StateStore for EncryptedStateStore<S> {
+    fn load(&self) -> Result<HashMap<MacAddr, DeviceBinding>> {
+        self.inner.load()?.into_iter()
+            .map(|(mac, b)| Ok((mac, DeviceBinding { key: self.decrypt(&b.key)?, ..b })))
+            .collect()
+    }
+
+    fn save(&self, bindings: &HashMap<MacAddr, DeviceBinding>) -> Result<()> {
+        let bindings = bindings.iter()
+            .map(|(mac, b)| Ok((mac.clone(), DeviceBinding { key: self.encrypt(&b.key)?, ..b.clone() })))
+            .collect::<Result<_>>()?;
+        self.inner.save(&bindings)
+    }
+}
@@ -0,0 +1,146 @@
+//! Thermostat control loop using an external temperature sensor (requires `tokio`)
+//!
+//! The sensor of the unit itself (`TemSen`) is often off by several degrees, or it measures the wrong spot of the
+//! room. A [Thermostat] holds a setpoint against a temperature source supplied by the user instead: it switches the
+//! unit on (in the heating or cooling mode, with `SetTem` driven past the setpoint so that the unit keeps working)
+//! once the measured temperature leaves the band `setpoint ± hysteresis`, and switches it off once the temperature
+//! crosses the band to the other side.
+//!
+//! Example usage:
+//!
+//! ```no_run
+//! # use std::sync::Arc;
+//! # use gree::{*, async_client::*, thermostat::*};
+//! # async fn read_sensor() -> Result<f32> { Ok(21.0) }
+//! # async fn run() -> Result<()> {
+//! let gree = Arc::new(Gree::new(GreeConfig::default()).await?);
+//! let thermostat = Thermostat::new("living", ThermostatMode::Heat, 21.5);
+//! thermostat.spawn(gree, read_sensor);
+//! # Ok(())
+//! # }
+//! ```
+
+#![cfg(feature = "tokio")]
+
+use std::{future::Future, sync::Arc, time::Duration};
+use tokio::{task::JoinHandle, time};
+use crate::{*, async_client::*};
+
+/// What the [Thermostat] drives the unit to do
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ThermostatMode {
+    Heat,
+    Cool,
+}
+
+impl From<ThermostatMode> for vars::Mode {
+    fn from(m: ThermostatMode) -> Self {
+        match m {
+            ThermostatMode::Heat => Self::Heat,
+            ThermostatMode::Cool => Self::Cool,
+        }
+    }
+}
+
+/// Holds a target temperature measured by an external sensor, see module docs
+#[derive(Debug, Clone, PartialEq)]
+pub struct Thermostat {
+    /// Device (MAC address or alias) or group
+    pub target: String,
+    pub mode: ThermostatMode,
+    /// Temperature to hold, in degrees Celsius, as reported by the source
+    pub setpoint: f32,
+    /// Half the width of the band around `setpoint` within which the unit is left as it is
+    pub hysteresis: f32,
+    /// How far past `setpoint` `SetTem` is written when the unit is switched on, in degrees Celsius
+    pub drive_offset: u8,
+    /// The range `SetTem` is clamped to
+    pub range: SetTemRange,
+    /// How often the source is read
+    pub interval: Duration,
+}
+
+impl Thermostat {
+    pub const DEFAULT_HYSTERESIS: f32 = 0.5;
+    pub const DEFAULT_DRIVE_OFFSET: u8 = 2;
+    pub const DEFAULT_INTERVAL: Duration = Duration::from_secs(60);
+
+    pub fn new(target: &str, mode: ThermostatMode, setpoint: f32) -> Self {
+        Self {
+            target: target.to_owned(),
+            mode,
+            setpoint,
+            hysteresis: Self::DEFAULT_HYSTERESIS,
+            drive_offset: Self::DEFAULT_DRIVE_OFFSET,
+            range: SetTemRange::default(),
+            interval: Self::DEFAULT_INTERVAL,
+        }
+    }
+
+    /// Whether the unit should be running at the `measured` temperature. `running` is the current state as last set
+    /// by the thermostat; if it is not known, the unit runs while the temperature is on the wrong side of `setpoint`.
+    pub fn should_run(&self, measured: f32, running: Option<bool>) -> bool {
+        // distance from the setpoint towards the side the unit has to work against
+        let deficit = match self.mode {
+            ThermostatMode::Heat => self.setpoint - measured,
+            ThermostatMode::Cool => measured - self.setpoint,
+        };
+        if deficit > self.hysteresis {
+            true
+        } else if deficit < -self.hysteresis {
+            false
+        } else {
+            running.unwrap_or(deficit > 0.0)
+        }
+    }
+
+    /// The changes switching the unit on or off
+    pub fn delta(&self, run: bool) -> AcStateDelta {
+        if !run {
+            return AcStateDelta { power: Some(vars::OnOff::Off), ..Default::default() }
+        }
+        let offset = self.drive_offset as f32;
+        let set_tem = match self.mode {
+            ThermostatMode::Heat => self.setpoint + offset,
+            ThermostatMode::Cool => self.setpoint - offset,
+        };
+        AcStateDelta {
+            power: Some(vars::OnOff::On),
+            mode: Some(self.mode.into()),
+            target_temp: Some((set_tem.round().max(0.0) as u8).clamp(self.range.min, self.range.max)),
+            temp_rec: Some(vars::OnOff::Off),
+            ..Default::default()
+        }
+    }
+
+    /// Spawns the control loop against `gree`, reading the current temperature from `source` every `interval`. A
+    /// stream may be used as the source by wrapping its `next()`. Errors of the source and of the device are logged;
+    /// the unit is only written when it has to be switched, and again after a failed write. Abort the task returned
+    /// to stop.
+    pub fn spawn<C, F, Fut>(self, gree: Arc<Gree<C>>, mut source: F) -> JoinHandle<()>
+    where
+        C: GreeClientApi + 'static,
+        F: FnMut() -> Fut + Send + 'static,
+        Fut: Future<Output = Result<f32>> + Send,
+    {
+        tokio::spawn(async move {
+            let mut running = None;
+            let mut interval = time::interval(self.interval);
+            interval.set_missed_tick_behavior(time::MissedTickBehavior::Delay);
+            loop {
+                interval.tick().await;
+                let measured = match source().await {
+                    Ok(t) => t,
+                    Err(e) => { error!("thermostat {}: source: {e}", self.target); continue }
+                };
+                let run = self.should_run(measured, running);
+                if running == Some(run) { continue }
+                debug!("thermostat {}: {measured} (setpoint {}), switching {}", self.target, self.setpoint, if run { "on" } else { "off" });
+                match gree.apply(&self.target, self.delta(run)).await {
+                    Ok(()) => running = Some(run),
+                    Err(e) => { error!("thermostat {}: {e}", self.target); running = None }
+                }
+            }
+        })
+    }
+}
@@ -0,0 +1,194 @@
+//! Drives the clients against emulated devices, see `gree::emulator`
+
+// the futures of the async client nest deeply with all the features enabled
+#![recursion_limit = "256"]
+
+use std::{net::{IpAddr, Ipv4Addr}, time::Duration};
+use gree::{*, emulator::*, vars::*};
+
+const V1_MAC: &str = "000cc0000001";
+const V2_MAC: &str = "000cc0000002";
+
+/// Starts the emulator on its own loopback address, so that the tests do not share the Gree port
+fn start(host: u8) -> (Emulator, GreeConfig) {
+    let mut v2 = EmulatedDevice::new(V2_MAC, "bedroom");
+    v2.variant = ProtocolVariant::V2;
+    let ip = IpAddr::V4(Ipv4Addr::new(127, 0, 0, host));
+    let emu_cfg = EmulatorConfig { bind_addr: (ip, 7000).into(), ..Default::default() };
+    let emu = Emulator::start(emu_cfg, vec![EmulatedDevice::new(V1_MAC, "livingroom"), v2]).unwrap();
+
+    let mut cfg = GreeConfig::default();
+    cfg.client_config.bcast_addr = ip;
+    cfg.client_config.scan_timeout = Duration::from_millis(300);
+    cfg.client_config.exchange_timeout = Duration::from_millis(300);
+    cfg.client_config.bind_timeout = Duration::from_millis(300);
+    cfg.client_config.retry = RetryPolicy { attempts: 2, base_delay: Duration::from_millis(10), jitter: Duration::ZERO };
+    (emu, cfg)
+}
+
+fn var(emu: &Emulator, mac: &str, name: VarName) -> Value {
+    emu.with_device(mac, |d| d.vars[name].clone()).unwrap()
+}
+
+mod sync {
+    use super::*;
+    use gree::sync_client::*;
+
+    #[test]
+    fn client_scan_bind_status_cmd() {
+        let (emu, cfg) = start(11);
+        let c = GreeClient::new(cfg.client_config).unwrap();
+
+        let mut found = c.scan().unwrap();
+        found.sort_by(|a, b| a.2.cid.cmp(&b.2.cid));
+        let found: Vec<_> = found.iter().map(|(ip, gm, pack)| (*ip, pack.cid.as_str(), gm.variant())).collect();
+        let ip = emu.local_addr().ip();
+        assert_eq!(found, [(ip, V1_MAC, ProtocolVariant::V1), (ip, V2_MAC, ProtocolVariant::V2)]);
+
+        for (mac, variant) in [(V1_MAC, ProtocolVariant::V1), (V2_MAC, ProtocolVariant::V2)] {
+            let bound = c.bind(ip, mac, variant).unwrap();
+            assert_eq!(bound.key, EmulatedDevice::DEFAULT_KEY);
+
+            let r = c.setvars(ip, mac, &bound.key, variant, &[POW, SET_TEM], &[1.into(), 21.into()]).unwrap();
+            assert_eq!(r.opt, [POW, SET_TEM]);
+            assert_eq!(var(&emu, mac, SET_TEM), 21);
+
+            let r = c.getvars(ip, mac, &bound.key, variant, &[POW, SET_TEM]).unwrap();
+            assert_eq!(r.cols, [POW, SET_TEM]);
+            assert_eq!(r.dat, [Value::from(1), Value::from(21)]);
+        }
+    }
+
+    #[test]
+    fn gree_scan_bind_get_set() {
+        let (emu, cfg) = start(12);
+        let gree = Gree::new(cfg).unwrap();
+
+        match gree.scan() {
+            ScanOutcome::Performed { found, .. } => assert_eq!(found, 2),
+            o => panic!("unexpected scan outcome {o:?}"),
+        }
+        for (mac, variant) in [(V1_MAC, ProtocolVariant::V1), (V2_MAC, ProtocolVariant::V2)] {
+            gree.set(mac, MOD, Mod::Heat).unwrap();
+            assert_eq!(var(&emu, mac, MOD), 4);
+            emu.with_device(mac, |d| d.vars.insert(POW.to_owned(), 1.into()));
+            assert_eq!(gree.get::<OnOff>(mac, POW).unwrap(), OnOff::On);
+            assert_eq!(gree.with_device(mac, |d| (d.key.clone(), d.variant)).unwrap(), (Some(EmulatedDevice::DEFAULT_KEY.to_owned()), variant));
+        }
+    }
+
+    #[test]
+    fn dropped_requests_are_retried() {
+        let (emu, cfg) = start(13);
+        let gree = Gree::new(cfg).unwrap();
+        gree.bind(V1_MAC).unwrap();
+
+        emu.with_device(V1_MAC, |d| d.faults.drop = 1);
+        gree.set(V1_MAC, POW, OnOff::On).unwrap();
+        assert_eq!(var(&emu, V1_MAC, POW), 1);
+        assert_eq!(emu.with_device(V1_MAC, |d| d.faults.drop), Some(0));
+    }
+
+    #[test]
+    fn offline_devices_are_tracked() {
+        let (emu, mut cfg) = start(14);
+        cfg.offline_threshold = 1;
+        cfg.offline_backoff = Duration::from_millis(500);
+        cfg.client_config.retry = RetryPolicy::none();
+        let gree = Gree::new(cfg).unwrap();
+        gree.bind(V1_MAC).unwrap();
+        let events = gree.subscribe();
+
+        emu.with_device(V1_MAC, |d| d.faults.offline = true);
+        let is_offline = |e: Error| matches!(e.root(), Error::Offline(mac) if *mac == V1_MAC);
+        assert!(is_offline(gree.get::<OnOff>(V1_MAC, POW).unwrap_err()));
+        assert!(!gree.with_device(V1_MAC, |d| d.online).unwrap());
+        assert!(events.try_iter().any(|e| matches!(e, GreeEvent::DeviceOffline { .. })));
+        // not retried before the backoff expires
+        let t = std::time::Instant::now();
+        assert!(is_offline(gree.get::<OnOff>(V1_MAC, POW).unwrap_err()));
+        assert!(t.elapsed() < Duration::from_millis(300));
+
+        emu.with_device(V1_MAC, |d| d.faults.offline = false);
+        std::thread::sleep(Duration::from_millis(600));
+        assert_eq!(gree.get::<OnOff>(V1_MAC, POW).unwrap(), OnOff::Off);
+        assert!(gree.with_device(V1_MAC, |d| d.online).unwrap());
+    }
+
+    #[test]
+    fn rejected_key_triggers_rebind() {
+        let (emu, cfg) = start(15);
+        let gree = Gree::new(cfg).unwrap();
+        gree.bind(V2_MAC).unwrap();
+
+        emu.with_device(V2_MAC, |d| d.faults.bad_key = true);
+        gree.set(V2_MAC, POW, OnOff::On).unwrap();
+        assert_eq!(var(&emu, V2_MAC, POW), 1);
+        assert_eq!(emu.with_device(V2_MAC, |d| d.faults.bad_key), Some(false));
+    }
+
+    #[test]
+    fn listener_is_stopped_on_drop() {
+        let (_emu, mut cfg) = start(17);
+        let addr = (Ipv4Addr::new(127, 0, 0, 17), 17000).into();
+        cfg.listen_addr = Some(addr);
+        let gree = Gree::new(cfg.clone()).unwrap();
+        // listening again replaces the listener bound to the address
+        gree.listen(addr).unwrap();
+        drop(gree);
+        Gree::new(cfg).unwrap();
+    }
+
+    #[test]
+    fn result_code_is_reported() {
+        let (emu, cfg) = start(16);
+        let gree = Gree::new(cfg).unwrap();
+        gree.bind(V1_MAC).unwrap();
+
+        emu.with_device(V1_MAC, |d| d.faults.result_code = Some(500));
+        let e = gree.set(V1_MAC, POW, OnOff::On).unwrap_err();
+        assert!(matches!(e.root(), Error::DeviceError { mac, code: 500 } if *mac == V1_MAC), "{e}");
+    }
+}
+
+#[cfg(feature = "tokio")]
+mod async_tokio {
+    use super::*;
+    use gree::async_client::*;
+
+    #[tokio::test]
+    async fn gree_scan_bind_get_set() {
+        let (emu, cfg) = start(21);
+        let gree = Gree::new(cfg).await.unwrap();
+
+        for (mac, variant) in [(V1_MAC, ProtocolVariant::V1), (V2_MAC, ProtocolVariant::V2)] {
+            gree.set(mac, MOD, Mod::Heat).await.unwrap();
+            assert_eq!(var(&emu, mac, MOD), 4);
+            emu.with_device(mac, |d| d.vars.insert(POW.to_owned(), 1.into()));
+            assert_eq!(gree.get::<OnOff>(mac, POW).await.unwrap(), OnOff::On);
+            assert_eq!(gree.with_device(mac, |d| d.variant).await.unwrap(), variant);
+        }
+    }
+
+    #[tokio::test]
+    async fn rejected_key_triggers_rebind() {
+        let (emu, cfg) = start(22);
+        let gree = Gree::new(cfg).await.unwrap();
+        gree.bind(V1_MAC).await.unwrap();
+
+        emu.with_device(V1_MAC, |d| d.faults.bad_key = true);
+        gree.set(V1_MAC, POW, OnOff::On).await.unwrap();
+        assert_eq!(var(&emu, V1_MAC, POW), 1);
+    }
+
+    #[tokio::test]
+    async fn result_code_is_reported() {
+        let (emu, cfg) = start(23);
+        let gree = Gree::new(cfg).await.unwrap();
+        gree.bind(V1_MAC).await.unwrap();
+
+        emu.with_device(V1_MAC, |d| d.faults.result_code = Some(500));
+        let e = gree.set(V1_MAC, POW, OnOff::On).await.unwrap_err();
+        assert!(matches!(e.root(), Error::DeviceError { mac, code: 500 } if *mac == V1_MAC), "{e}");
+    }
+}